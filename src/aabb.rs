@@ -1,5 +1,6 @@
 use crate::ray::Ray;
-use crate::utils::Interval;
+use crate::transform::Transform;
+use crate::utils::{gamma, Float, Interval};
 use crate::vec3::Vec3;
 use std::ops::Add;
 #[derive(Debug, Clone, Copy)]
@@ -30,16 +31,25 @@ impl Aabb {
         Aabb { x, y, z }
     }
 
-    fn pad_to_minimums(&mut self) -> () {
-        let delta = 0.0001;
-        if self.x.size() < delta {
-            self.x = self.x.expand(delta);
-        }
-        if self.y.size() < delta {
-            self.y = self.y.expand(delta);
-        }
-        if self.z.size() < delta {
-            self.z = self.z.expand(delta);
+    fn pad_to_minimums(&mut self) {
+        self.x = Aabb::pad_interval(self.x);
+        self.y = Aabb::pad_interval(self.y);
+        self.z = Aabb::pad_interval(self.z);
+    }
+
+    fn pad_interval(interval: Interval) -> Interval {
+        // The padding grows with the magnitude of the bounds, so a flat box keeps some thickness
+        // after the ray origin is subtracted in single precision.
+        let magnitude = interval.min.abs().max(interval.max.abs());
+        let delta = if magnitude.is_finite() {
+            Float::max(0.0001, magnitude * Float::EPSILON * 64.0)
+        } else {
+            0.0001
+        };
+        if interval.size() < delta {
+            interval.expand(delta)
+        } else {
+            interval
         }
     }
 
@@ -97,6 +107,21 @@ impl Aabb {
         temp
     }
 
+    pub fn surface_area(&self) -> Float {
+        let x = self.x.size();
+        let y = self.y.size();
+        let z = self.z.size();
+        2.0 * (x * y + y * z + z * x)
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        Vec3::new(
+            (self.x.min + self.x.max) / 2.0,
+            (self.y.min + self.y.max) / 2.0,
+            (self.z.min + self.z.max) / 2.0,
+        )
+    }
+
     pub fn axis_interval(&self, n: i32) -> Interval {
         if n == 1 {
             return self.y;
@@ -107,33 +132,65 @@ impl Aabb {
         self.x
     }
 
-    pub fn hit(&self, r: Ray, mut ray_t: Interval) -> bool {
-        let ray_origin = r.origin;
-        let ray_direction = r.direction;
-
-        for index in 0..3 {
-            let axis = index as usize;
-            let ax = self.axis_interval(index);
-            let adinv = 1.0 / ray_direction[axis];
+    pub fn transformed(&self, transform: &Transform) -> Aabb {
+        // Returns the box bounding all eight corners of this box after the transform.
+
+        let mut min = Vec3::new(Float::INFINITY, Float::INFINITY, Float::INFINITY);
+        let mut max = Vec3::new(
+            Float::NEG_INFINITY,
+            Float::NEG_INFINITY,
+            Float::NEG_INFINITY,
+        );
+
+        for i in 0..2 {
+            for j in 0..2 {
+                for k in 0..2 {
+                    let x = if i == 0 { self.x.min } else { self.x.max };
+                    let y = if j == 0 { self.y.min } else { self.y.max };
+                    let z = if k == 0 { self.z.min } else { self.z.max };
+
+                    let tester = transform.transform_point(Vec3::new(x, y, z));
+
+                    min = Vec3::new(
+                        min.x().min(tester.x()),
+                        min.y().min(tester.y()),
+                        min.z().min(tester.z()),
+                    );
+
+                    max = Vec3::new(
+                        max.x().max(tester.x()),
+                        max.y().max(tester.y()),
+                        max.z().max(tester.z()),
+                    );
+                }
+            }
+        }
 
-            let t0 = (ax.min - ray_origin[axis]) * adinv;
-            let t1 = (ax.max - ray_origin[axis]) * adinv;
+        Aabb::new_from_vec3(min, max)
+    }
 
-            if t0 < t1 {
-                if t0 > ray_t.min {
-                    ray_t.min = t0;
-                }
-                if t1 < ray_t.max {
-                    ray_t.max = t1;
-                }
+    pub fn hit_precomputed(&self, origin: Vec3, inv_direction: Vec3, mut ray_t: Interval) -> bool {
+        // Same as `hit`, for callers that test many boxes against one ray and can compute the
+        // inverse of the ray direction once.
+        let far_scale = Aabb::far_scale();
+        for axis in 0..3 {
+            let ax = self.axis_interval(axis as i32);
+            let adinv = inv_direction[axis];
+
+            // The near and far planes are picked by the direction of the ray, as in `Aabb4::hit`.
+            let (near, far) = if adinv < 0.0 {
+                (ax.max, ax.min)
             } else {
-                if t1 > ray_t.min {
-                    ray_t.min = t1;
-                }
-                if t0 < ray_t.max {
-                    ray_t.max = t0;
-                }
-            }
+                (ax.min, ax.max)
+            };
+            let t0 = (near - origin[axis]) * adinv;
+            let t1 = (far - origin[axis]) * adinv;
+
+            // A ray lying in the plane of a face gives 0 * inf = NaN, which `max` and `min`
+            // ignore, so the ray counts as inside that slab.
+            ray_t.min = ray_t.min.max(t0);
+            ray_t.max = ray_t.max.min(t1 * far_scale);
+
             if ray_t.max <= ray_t.min {
                 return false;
             }
@@ -141,6 +198,22 @@ impl Aabb {
 
         true
     }
+
+    pub fn far_scale() -> Float {
+        // Factor the distance to the far side of a slab is widened by, which covers its rounding
+        // error (as in PBRT). Without it a ray can miss a flat box, or slip between two boxes
+        // that share a face, when the slab collapses after the ray origin is subtracted.
+        1.0 + 2.0 * gamma(3)
+    }
+
+    pub fn hit(&self, r: Ray, ray_t: Interval) -> bool {
+        let inv_direction = Vec3::new(
+            1.0 / r.direction.x(),
+            1.0 / r.direction.y(),
+            1.0 / r.direction.z(),
+        );
+        self.hit_precomputed(r.origin, inv_direction, ray_t)
+    }
 }
 
 impl Add<Vec3> for Aabb {
@@ -0,0 +1,69 @@
+use crate::textures::Texture;
+use crate::utils::Float;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// What rays see when they leave the scene without hitting anything, when there is no environment
+// light. `screen` is where the path started on the image, from (0, 0) at the bottom left to
+// (1, 1) at the top right, for backgrounds that stay fixed to the screen.
+pub trait Background: Send + Sync {
+    fn value(&self, direction: Vec3, screen: (Float, Float)) -> Vec3;
+}
+
+pub struct SolidBackground {
+    color: Vec3,
+}
+
+impl SolidBackground {
+    pub fn new(color: Vec3) -> SolidBackground {
+        SolidBackground { color }
+    }
+}
+
+impl Background for SolidBackground {
+    fn value(&self, _direction: Vec3, _screen: (Float, Float)) -> Vec3 {
+        self.color
+    }
+}
+
+// Blend from one color straight down to another straight up.
+pub struct GradientBackground {
+    bottom: Vec3,
+    top: Vec3,
+}
+
+impl GradientBackground {
+    pub fn new(bottom: Vec3, top: Vec3) -> GradientBackground {
+        GradientBackground { bottom, top }
+    }
+
+    pub fn new_sky() -> GradientBackground {
+        // White to light blue, as in Ray Tracing in One Weekend.
+        GradientBackground::new(Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Background for GradientBackground {
+    fn value(&self, direction: Vec3, _screen: (Float, Float)) -> Vec3 {
+        let a = 0.5 * (Vec3::unit_vector(direction).y() + 1.0);
+        (1.0 - a) * self.bottom + a * self.top
+    }
+}
+
+// Texture stretched over the screen, like a photograph behind the scene. The texture is looked
+// up with the screen position as its uv coordinates, and as the point in the z = 0 plane.
+pub struct ScreenTexture {
+    texture: Arc<dyn Texture>,
+}
+
+impl ScreenTexture {
+    pub fn new(texture: Arc<dyn Texture>) -> ScreenTexture {
+        ScreenTexture { texture }
+    }
+}
+
+impl Background for ScreenTexture {
+    fn value(&self, _direction: Vec3, (u, v): (Float, Float)) -> Vec3 {
+        self.texture.value(u, v, Vec3::new(u, v, 0.0))
+    }
+}
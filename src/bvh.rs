@@ -1,9 +1,392 @@
 use crate::aabb::Aabb;
 use crate::hittables::{HitRecord, Hittable, HittableList};
 use crate::ray::Ray;
-use crate::utils::Interval;
+use crate::utils::{Float, Interval};
+use crate::vec3::Vec3;
+use std::fmt;
 use std::sync::Arc;
 
+// Relative costs of visiting a node and intersecting a primitive used by the surface area heuristic.
+pub const TRAVERSAL_COST: Float = 0.125;
+pub const INTERSECTION_COST: Float = 1.0;
+
+const MAX_LEAF_SIZE: usize = 4; // Largest span SAH may keep as a leaf
+const SWEEP_THRESHOLD: usize = 32; // Spans up to this size evaluate every split, larger ones use bins
+const BIN_COUNT: usize = 16;
+const PARALLEL_THRESHOLD: usize = 1024; // Spans at least this large build their children in parallel
+const MAX_SAH_DEPTH: usize = 64; // Deeper spans are split in half, which bounds the traversal stack
+
+struct BuildEntry {
+    object: Arc<dyn Hittable>,
+    index: usize, // Position of the object in the list the build started from
+    bbox: Aabb,
+    centroid: Vec3,
+}
+
+impl BuildEntry {
+    fn new(index: usize, object: &Arc<dyn Hittable>) -> BuildEntry {
+        let bbox = object.bounding_box();
+        BuildEntry {
+            object: object.clone(),
+            index,
+            bbox,
+            centroid: bbox.centroid(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BvhStats {
+    pub interior_count: usize,
+    pub leaf_count: usize,
+    pub primitive_count: usize,
+    pub max_depth: usize,
+    pub min_leaf_size: usize,
+    pub max_leaf_size: usize,
+    pub sah_cost: Float, // Expected cost of tracing a ray that hits the root box
+}
+
+impl BvhStats {
+    fn new_leaf(size: usize, bbox: &Aabb) -> BvhStats {
+        BvhStats {
+            interior_count: 0,
+            leaf_count: 1,
+            primitive_count: size,
+            max_depth: 0,
+            min_leaf_size: size,
+            max_leaf_size: size,
+            sah_cost: INTERSECTION_COST * size as Float * bbox.surface_area(),
+        }
+    }
+
+    fn new_interior(left: BvhStats, right: BvhStats, bbox: &Aabb) -> BvhStats {
+        // While building, `sah_cost` holds the area weighted cost, which is only divided by the
+        // root area once the whole tree is known.
+        BvhStats {
+            interior_count: left.interior_count + right.interior_count + 1,
+            leaf_count: left.leaf_count + right.leaf_count,
+            primitive_count: left.primitive_count + right.primitive_count,
+            max_depth: left.max_depth.max(right.max_depth) + 1,
+            min_leaf_size: left.min_leaf_size.min(right.min_leaf_size),
+            max_leaf_size: left.max_leaf_size.max(right.max_leaf_size),
+            sah_cost: left.sah_cost + right.sah_cost + TRAVERSAL_COST * bbox.surface_area(),
+        }
+    }
+
+    pub fn average_leaf_size(&self) -> Float {
+        self.primitive_count as Float / self.leaf_count as Float
+    }
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} leaves, depth {}, leaf size {}-{} (avg {:.2}), SAH cost {:.2}",
+            self.interior_count,
+            self.leaf_count,
+            self.max_depth,
+            self.min_leaf_size,
+            self.max_leaf_size,
+            self.average_leaf_size(),
+            self.sah_cost,
+        )
+    }
+}
+
+// Tree produced by the SAH builder. Leaves refer to a range of `BvhBuild::objects`.
+pub enum BuildNode {
+    Leaf {
+        bbox: Aabb,
+        start: usize,
+        count: usize,
+    },
+    Interior {
+        bbox: Aabb,
+        axis: usize, // Axis the children were split along
+        left: Box<BuildNode>,
+        right: Box<BuildNode>,
+    },
+}
+
+impl BuildNode {
+    pub fn bbox(&self) -> Aabb {
+        match self {
+            BuildNode::Leaf { bbox, .. } => *bbox,
+            BuildNode::Interior { bbox, .. } => *bbox,
+        }
+    }
+}
+
+pub struct BvhBuild {
+    pub root: BuildNode,
+    pub objects: Vec<Arc<dyn Hittable>>, // Reordered so every leaf covers a contiguous range
+    pub indices: Vec<usize>,             // Original position of each of the reordered objects
+    pub stats: BvhStats,
+}
+
+impl BvhBuild {
+    pub fn new(objects: &[Arc<dyn Hittable>], split_root: bool) -> BvhBuild {
+        // The entries are reordered in place while building, so the objects are only copied once.
+        let mut entries: Vec<BuildEntry> = objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| BuildEntry::new(index, object))
+            .collect();
+        let bbox = Self::bounds(&entries);
+
+        let (root, mut stats) = if split_root && entries.len() > 1 {
+            let (mid, axis, _) = Self::partition(&mut entries, &bbox, 0);
+            Self::build_interior(&mut entries, 0, mid, axis, bbox, 0)
+        } else {
+            Self::build(&mut entries, 0, 0)
+        };
+
+        // An empty list has no box to divide by, and costs nothing to trace.
+        stats.sah_cost = if entries.is_empty() || bbox.surface_area() <= 0.0 {
+            0.0
+        } else {
+            stats.sah_cost / bbox.surface_area()
+        };
+        BvhBuild {
+            root,
+            indices: entries.iter().map(|entry| entry.index).collect(),
+            objects: entries.into_iter().map(|entry| entry.object).collect(),
+            stats,
+        }
+    }
+
+    fn build(entries: &mut [BuildEntry], offset: usize, depth: usize) -> (BuildNode, BvhStats) {
+        // `offset` is the index of the first entry of the span within the whole build.
+        let bbox = Self::bounds(entries);
+        let object_span = entries.len();
+
+        if object_span <= 1 {
+            let leaf = BuildNode::Leaf {
+                bbox,
+                start: offset,
+                count: object_span,
+            };
+            return (leaf, BvhStats::new_leaf(object_span, &bbox));
+        }
+
+        let (mid, axis, split_cost) = Self::partition(entries, &bbox, depth);
+        let leaf_cost = INTERSECTION_COST * object_span as Float;
+
+        if object_span <= MAX_LEAF_SIZE && leaf_cost <= split_cost {
+            let leaf = BuildNode::Leaf {
+                bbox,
+                start: offset,
+                count: object_span,
+            };
+            return (leaf, BvhStats::new_leaf(object_span, &bbox));
+        }
+
+        Self::build_interior(entries, offset, mid, axis, bbox, depth)
+    }
+
+    fn build_interior(
+        entries: &mut [BuildEntry],
+        offset: usize,
+        mid: usize,
+        axis: usize,
+        bbox: Aabb,
+        depth: usize,
+    ) -> (BuildNode, BvhStats) {
+        let parallel = entries.len() >= PARALLEL_THRESHOLD;
+        let (left_entries, right_entries) = entries.split_at_mut(mid);
+
+        let ((left, left_stats), (right, right_stats)) = if parallel {
+            rayon::join(
+                || Self::build(left_entries, offset, depth + 1),
+                || Self::build(right_entries, offset + mid, depth + 1),
+            )
+        } else {
+            (
+                Self::build(left_entries, offset, depth + 1),
+                Self::build(right_entries, offset + mid, depth + 1),
+            )
+        };
+
+        let node = BuildNode::Interior {
+            bbox,
+            axis,
+            left: Box::new(left),
+            right: Box::new(right),
+        };
+        (node, BvhStats::new_interior(left_stats, right_stats, &bbox))
+    }
+
+    fn bounds(entries: &[BuildEntry]) -> Aabb {
+        let mut bbox = Aabb::EMPTY;
+        for entry in entries {
+            bbox = Aabb::new_from_aabb(bbox, entry.bbox);
+        }
+        bbox
+    }
+
+    fn split_cost(
+        left_area: Float,
+        left_count: usize,
+        right_area: Float,
+        right_count: usize,
+        area: Float,
+    ) -> Float {
+        TRAVERSAL_COST
+            + INTERSECTION_COST
+                * (left_count as Float * left_area + right_count as Float * right_area)
+                / area
+    }
+
+    fn partition(entries: &mut [BuildEntry], bbox: &Aabb, depth: usize) -> (usize, usize, Float) {
+        // Reorders the entries so the chosen split is at the returned index, and returns the
+        // split axis and the SAH cost of that split.
+        let split = if depth >= MAX_SAH_DEPTH {
+            None
+        } else if entries.len() <= SWEEP_THRESHOLD {
+            Self::partition_sweep(entries, bbox)
+        } else {
+            Self::partition_binned(entries, bbox)
+        };
+
+        // Either every centroid is in the same spot or the tree is getting too deep, so split
+        // the span in half by count.
+        split.unwrap_or_else(|| {
+            let axis = bbox.longest_axis() as usize;
+            let mid = entries.len() / 2;
+            entries
+                .select_nth_unstable_by(mid, |a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+            (mid, axis, Float::INFINITY)
+        })
+    }
+
+    fn sort_by_axis(entries: &mut [BuildEntry], axis: usize) {
+        entries.sort_unstable_by(|a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+    }
+
+    fn partition_sweep(entries: &mut [BuildEntry], bbox: &Aabb) -> Option<(usize, usize, Float)> {
+        // Evaluates a split between every pair of neighbouring centroids along each axis.
+        let object_span = entries.len();
+        let area = bbox.surface_area();
+        let mut right_areas = vec![0.0; object_span];
+        let mut best: Option<(usize, usize, Float)> = None;
+
+        for axis in 0..3 {
+            Self::sort_by_axis(entries, axis);
+
+            let mut right_box = Aabb::EMPTY;
+            for i in (1..object_span).rev() {
+                right_box = Aabb::new_from_aabb(right_box, entries[i].bbox);
+                right_areas[i] = right_box.surface_area();
+            }
+
+            let mut left_box = Aabb::EMPTY;
+            for i in 1..object_span {
+                left_box = Aabb::new_from_aabb(left_box, entries[i - 1].bbox);
+                let cost = Self::split_cost(
+                    left_box.surface_area(),
+                    i,
+                    right_areas[i],
+                    object_span - i,
+                    area,
+                );
+                if best.map_or(true, |(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, i, cost));
+                }
+            }
+        }
+
+        let (axis, mid, cost) = best?;
+        if axis != 2 {
+            Self::sort_by_axis(entries, axis);
+        }
+        Some((mid, axis, cost))
+    }
+
+    fn partition_binned(entries: &mut [BuildEntry], bbox: &Aabb) -> Option<(usize, usize, Float)> {
+        // Groups the centroids into equal width bins along each axis and evaluates a split
+        // between every pair of neighbouring bins.
+        let area = bbox.surface_area();
+
+        let mut centroid_bounds = Aabb::EMPTY;
+        for entry in entries.iter() {
+            let c = entry.centroid;
+            let point = Aabb::new_const(
+                Interval::new(c.x(), c.x()),
+                Interval::new(c.y(), c.y()),
+                Interval::new(c.z(), c.z()),
+            );
+            centroid_bounds = Aabb::new_from_aabb(centroid_bounds, point);
+        }
+
+        let bin_index = |centroid: &Vec3, axis: usize| -> usize {
+            let extent = centroid_bounds.axis_interval(axis as i32);
+            let f = (centroid[axis] - extent.min) / extent.size();
+            ((f * BIN_COUNT as Float) as usize).min(BIN_COUNT - 1)
+        };
+
+        let mut best: Option<(usize, usize, Float)> = None;
+
+        for axis in 0..3 {
+            if centroid_bounds.axis_interval(axis as i32).size() <= 0.0 {
+                continue;
+            }
+
+            let mut counts = [0usize; BIN_COUNT];
+            let mut boxes = [Aabb::EMPTY; BIN_COUNT];
+            for entry in entries.iter() {
+                let b = bin_index(&entry.centroid, axis);
+                counts[b] += 1;
+                boxes[b] = Aabb::new_from_aabb(boxes[b], entry.bbox);
+            }
+
+            let mut right_counts = [0usize; BIN_COUNT];
+            let mut right_areas = [0.0; BIN_COUNT];
+            let mut right_box = Aabb::EMPTY;
+            let mut right_count = 0;
+            for b in (1..BIN_COUNT).rev() {
+                right_box = Aabb::new_from_aabb(right_box, boxes[b]);
+                right_count += counts[b];
+                right_counts[b] = right_count;
+                right_areas[b] = right_box.surface_area();
+            }
+
+            let mut left_box = Aabb::EMPTY;
+            let mut left_count = 0;
+            for b in 1..BIN_COUNT {
+                left_box = Aabb::new_from_aabb(left_box, boxes[b - 1]);
+                left_count += counts[b - 1];
+                if left_count == 0 || right_counts[b] == 0 {
+                    continue;
+                }
+                let cost = Self::split_cost(
+                    left_box.surface_area(),
+                    left_count,
+                    right_areas[b],
+                    right_counts[b],
+                    area,
+                );
+                if best.map_or(true, |(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, b, cost));
+                }
+            }
+        }
+
+        let (axis, split_bin, cost) = best?;
+
+        // Move every entry that falls below the split bin to the front.
+        let mut mid = 0;
+        for i in 0..entries.len() {
+            if bin_index(&entries[i].centroid, axis) < split_bin {
+                entries.swap(i, mid);
+                mid += 1;
+            }
+        }
+
+        Some((mid, axis, cost))
+    }
+}
+
 pub struct BvhNode {
     left: Arc<dyn Hittable>,
     right: Arc<dyn Hittable>,
@@ -15,81 +398,69 @@ impl BvhNode {
         BvhNode::new(list.objects.clone(), 0, list.objects.len())
     }
 
-    pub fn new(objects: Vec<Arc<dyn Hittable>>, start: usize, end: usize) -> BvhNode {
-        // Build the bounding box of the span of source objects
-        let mut bbox = Aabb::EMPTY;
-        for i in start..end {
-            bbox = Aabb::new_from_aabb(bbox, objects[i].bounding_box());
-        }
+    pub fn new_from_list_with_stats(list: &HittableList) -> (Self, BvhStats) {
+        BvhNode::new_with_stats(list.objects.clone(), 0, list.objects.len())
+    }
 
-        // Determine the axis with the largest extent
-        let axis = bbox.longest_axis();
-
-        // Define comparators based on the axis
-        let comparator: Box<dyn Fn(&Arc<dyn Hittable>, &Arc<dyn Hittable>) -> std::cmp::Ordering> =
-            match axis {
-                0 => Box::new(|a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>| {
-                    a.bounding_box()
-                        .axis_interval(axis)
-                        .min
-                        .partial_cmp(&b.bounding_box().axis_interval(axis).min)
-                        .unwrap()
-                }),
-                1 => Box::new(|a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>| {
-                    a.bounding_box()
-                        .axis_interval(axis)
-                        .min
-                        .partial_cmp(&b.bounding_box().axis_interval(axis).min)
-                        .unwrap()
-                }),
-                _ => Box::new(|a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>| {
-                    a.bounding_box()
-                        .axis_interval(axis)
-                        .min
-                        .partial_cmp(&b.bounding_box().axis_interval(axis).min)
-                        .unwrap()
-                }),
-            };
+    pub fn new(objects: Vec<Arc<dyn Hittable>>, start: usize, end: usize) -> BvhNode {
+        BvhNode::new_with_stats(objects, start, end).0
+    }
 
-        let object_span = end - start;
+    pub fn new_with_stats(
+        objects: Vec<Arc<dyn Hittable>>,
+        start: usize,
+        end: usize,
+    ) -> (BvhNode, BvhStats) {
+        // The root is always split, even if SAH would prefer a single leaf.
+        let build = BvhBuild::new(&objects[start..end], true);
 
-        let node = if object_span == 1 {
-            BvhNode {
-                left: objects[start].clone(),
-                right: objects[start].clone(),
-                bbox: objects[start].bounding_box(),
-            }
-        } else if object_span == 2 {
-            BvhNode {
-                left: objects[start].clone(),
-                right: objects[start + 1].clone(),
-                bbox: Aabb::new_from_aabb(
-                    objects[start].bounding_box(),
-                    objects[start + 1].bounding_box(),
-                ),
-            }
-        } else {
-            // Sort the objects based on the comparator
-            let mut sorted_objects = objects;
-            sorted_objects[start..end].sort_by(|a, b| comparator(a, b));
-
-            let mid = start + object_span / 2;
-            let left = Arc::new(BvhNode::new(sorted_objects.clone(), start, mid));
-            let right = Arc::new(BvhNode::new(sorted_objects.clone(), mid, end));
-
-            BvhNode {
-                left: left.clone(),
-                right: right.clone(),
-                bbox: Aabb::new_from_aabb(left.bounding_box(), right.bounding_box()),
+        let node = match &build.root {
+            BuildNode::Interior {
+                bbox, left, right, ..
+            } => BvhNode {
+                left: Self::from_build_node(left, &build.objects),
+                right: Self::from_build_node(right, &build.objects),
+                bbox: *bbox,
+            },
+            BuildNode::Leaf { bbox, .. } => {
+                // A single object, or an empty list with `Aabb::EMPTY` around nothing.
+                let leaf = Self::from_build_node(&build.root, &build.objects);
+                BvhNode {
+                    left: leaf.clone(),
+                    right: leaf,
+                    bbox: *bbox,
+                }
             }
         };
 
-        node
+        (node, build.stats)
+    }
+
+    fn from_build_node(node: &BuildNode, objects: &[Arc<dyn Hittable>]) -> Arc<dyn Hittable> {
+        match node {
+            BuildNode::Leaf {
+                start, count: 1, ..
+            } => objects[*start].clone(),
+            BuildNode::Leaf { start, count, .. } => {
+                let mut leaf = HittableList::new();
+                for object in &objects[*start..*start + *count] {
+                    leaf.add(object.clone());
+                }
+                Arc::new(leaf)
+            }
+            BuildNode::Interior {
+                bbox, left, right, ..
+            } => Arc::new(BvhNode {
+                left: Self::from_build_node(left, objects),
+                right: Self::from_build_node(right, objects),
+                bbox: *bbox,
+            }),
+        }
     }
 }
 
 impl Hittable for BvhNode {
-    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
         // Return None if the bounding box isn't hit
         if !self.bbox.hit(r, ray_t) {
             return None;
@@ -132,3 +503,29 @@ impl Hittable for BvhNode {
         self.bbox
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material};
+    use crate::shapes::Sphere;
+
+    #[test]
+    fn empty_tree() {
+        let (bvh, stats) = BvhNode::new_with_stats(Vec::new(), 0, 0);
+        assert_eq!(stats.primitive_count, 0);
+        assert_eq!(stats.sah_cost, 0.0);
+        let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert!(bvh.hit(r, Interval::new(0.0, Float::INFINITY)).is_none());
+    }
+
+    #[test]
+    fn single_object() {
+        let white = Material::Lambertian(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 5.0), 1.0, white));
+        let bvh = BvhNode::new(vec![sphere], 0, 1);
+        let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let rec = bvh.hit(r, Interval::new(0.0, Float::INFINITY)).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-4);
+    }
+}
@@ -1,8 +1,12 @@
+use crate::background::{Background, SolidBackground};
 use crate::color::linear_to_gamma;
-use crate::hittables::Hittable;
-use crate::material::Scatterable;
+use crate::hittables::{HitRecord, Hittable};
+use crate::lights::{power_heuristic, LightList};
+use crate::material::{ScatterRecord, Scatterable};
 use crate::ray::Ray;
-use crate::utils::{degrees_to_radians, random_double, Interval};
+use crate::spectrum::{SampledSpectrum, SampledWavelengths};
+use crate::textures::UvDifferentials;
+use crate::utils::{degrees_to_radians, random_double, Float, Interval};
 use crate::vec3::Vec3;
 use rayon::current_num_threads;
 use rayon::prelude::*;
@@ -13,52 +17,293 @@ use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 pub struct Camera {
-    image_width: f64,
-    image_height: f64,
+    image_width: Float,
+    image_height: Float,
     camera_center: Vec3,
     pixel00_loc: Vec3,   // Location of pixel 0, 0
     pixel_delta_u: Vec3, // Offset to pixel to the right
     pixel_delta_v: Vec3, // Offset to pixel below
     file: File,
-    samples_per_pixel: i32,   // Count of random samples for each pixel
-    pixel_samples_scale: f64, // Color scale factor for a sum of pixel samples
-    max_depth: i32,           // Maximum number of ray bounces into scene
-    defocus_angle: f64,       // Variation angle of rays through each pixel
-    defocus_disk_u: Vec3,     // Defocus disk horizontal radius
-    defocus_disk_v: Vec3,     // Defocus disk vertical radius
-    background: Vec3,
+    samples_per_pixel: i32,          // Count of random samples for each pixel
+    pixel_samples_scale: Float,      // Color scale factor for a sum of pixel samples
+    max_depth: i32,                  // Maximum number of ray bounces into scene
+    defocus_angle: Float,            // Variation angle of rays through each pixel
+    defocus_disk_u: Vec3,            // Defocus disk horizontal radius
+    defocus_disk_v: Vec3,            // Defocus disk vertical radius
+    background: Arc<dyn Background>, // Seen by rays that leave the scene, without an environment
+    camera_background: Option<Arc<dyn Background>>, // Seen instead by rays straight from the camera
+    shutter_open: Float,             // Time the shutter opens
+    shutter_close: Float,            // Time the shutter closes
+    spectral: bool, // Whether paths are traced at sampled wavelengths instead of in RGB
 }
 
 impl Camera {
-    fn ray_color(r: Ray, depth: i32, world: &dyn Hittable, background: Vec3) -> Vec3 {
+    fn sample_lights<'a>(
+        r: &'a Ray,
+        rec: &'a HitRecord,
+        world: &'a dyn Hittable,
+        lights: &'a LightList,
+    ) -> impl Iterator<Item = (Vec3, Vec3)> + 'a {
+        // Light reaching the hit point straight from each light that nothing blocks, as the
+        // fraction scattered back along `r` and the irradiance from the light. Light that a
+        // scattered ray could also find is weighted against being found that way.
+        lights.iter().filter_map(move |light| {
+            let sample = light.sample(rec.p)?;
+            let scattering = rec.material.eval(r, rec, sample.direction);
+            if scattering.near_zero() {
+                return None;
+            }
+            let shadow_ray = rec.spawn_ray(sample.direction, r.time);
+            if world
+                .hit(shadow_ray, Interval::new(0.0, sample.distance))
+                .is_some()
+            {
+                return None;
+            }
+            let weight = match sample.pdf {
+                Some(pdf) => power_heuristic(pdf, rec.material.pdf(r, rec, sample.direction)),
+                None => 1.0,
+            };
+            Some((scattering, sample.irradiance * weight))
+        })
+    }
+
+    fn scatter_pdf(
+        r: &Ray,
+        rec: &HitRecord,
+        scatter: &ScatterRecord,
+        lights: &LightList,
+    ) -> Option<Float> {
+        // Density a ray was scattered with, for weighing the environment it finds against
+        // `sample_lights`. Mirror-like bounces are left to find it on their own.
+        if scatter.is_specular || lights.environment.is_none() {
+            return None;
+        }
+        Some(rec.material.pdf(r, rec, scatter.scattered.direction))
+    }
+
+    fn escaped(
+        &self,
+        r: &Ray,
+        depth: i32,
+        lights: &LightList,
+        screen: (Float, Float),
+        scatter_pdf: Option<Float>,
+    ) -> Vec3 {
+        // Light from behind everything in the scene.
+        if let Some(background) = &self.camera_background {
+            if depth == self.max_depth {
+                return background.value(r.direction, screen);
+            }
+        }
+        let Some(environment) = &lights.environment else {
+            return self.background.value(r.direction, screen);
+        };
+        let radiance = environment.radiance(r.direction);
+        match scatter_pdf {
+            Some(pdf) => radiance * power_heuristic(pdf, environment.pdf(r.direction)),
+            None => radiance,
+        }
+    }
+
+    fn ray_color(
+        &self,
+        r: Ray,
+        depth: i32,
+        world: &dyn Hittable,
+        lights: &LightList,
+        screen: (Float, Float),
+        scatter_pdf: Option<Float>,
+    ) -> Vec3 {
         if depth <= 0 {
             return Vec3::new(0.0, 0.0, 0.0);
         }
 
-        let hit = world.hit(r, Interval::new(0.001, f64::INFINITY));
+        // Scattered rays start slightly off the surface they leave, so no minimum distance is
+        // needed to avoid hitting it again.
+        let hit = world.hit(r, Interval::new(0.0, Float::INFINITY));
 
         if hit.is_none() {
-            return background;
+            return self.escaped(&r, depth, lights, screen, scatter_pdf);
         }
 
-        let temp_rec = hit.unwrap();
-        let color_from_emission = temp_rec
-            .material
-            .emitted(temp_rec.u, temp_rec.v, temp_rec.p);
+        let mut temp_rec = hit.unwrap();
+        temp_rec.uv_differentials = self.uv_differentials(&temp_rec);
+        temp_rec.normal = temp_rec.material.shading_normal(&temp_rec);
+        let color_from_emission = temp_rec.material.emitted(&r, &temp_rec);
+        let color_from_lights = Self::sample_lights(&r, &temp_rec, world, lights)
+            .fold(Vec3::new(0.0, 0.0, 0.0), |sum, (scattering, irradiance)| {
+                sum + scattering * irradiance
+            });
 
         let scat = temp_rec.material.scatter(&r, &temp_rec);
 
         if scat.is_none() {
-            return color_from_emission;
+            return color_from_emission + color_from_lights;
+        }
+
+        let scat = scat.unwrap();
+        let color_from_scatter = scat.attenuation
+            * self.ray_color(
+                scat.scattered,
+                depth - 1,
+                world,
+                lights,
+                screen,
+                Self::scatter_pdf(&r, &temp_rec, &scat, lights),
+            );
+
+        color_from_emission + color_from_lights + color_from_scatter
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn ray_spectrum(
+        &self,
+        r: Ray,
+        depth: i32,
+        world: &dyn Hittable,
+        lights: &LightList,
+        screen: (Float, Float),
+        wavelengths: &mut SampledWavelengths,
+        scatter_pdf: Option<Float>,
+    ) -> SampledSpectrum {
+        // Same as `ray_color`, but with the colors of lights and surfaces turned into spectra
+        // at the wavelengths of the path.
+        if depth <= 0 {
+            return SampledSpectrum::new(0.0);
+        }
+
+        let Some(mut rec) = world.hit(r, Interval::new(0.0, Float::INFINITY)) else {
+            let radiance = self.escaped(&r, depth, lights, screen, scatter_pdf);
+            return SampledSpectrum::from_rgb_illuminant(radiance, wavelengths);
+        };
+        rec.uv_differentials = self.uv_differentials(&rec);
+        rec.normal = rec.material.shading_normal(&rec);
+
+        let spectrum_from_emission = rec.material.emitted_spectrum(&r, &rec, wavelengths);
+
+        // Lights are shaded at the hero wavelength of a dispersive material, like scattered rays,
+        // so the other wavelengths end there first.
+        let scatter = rec.material.scatter(&r, &rec);
+        if rec.material.is_dispersive() {
+            wavelengths.terminate_secondary();
         }
+        let spectrum_from_lights = Self::sample_lights(&r, &rec, world, lights).fold(
+            SampledSpectrum::new(0.0),
+            |sum, (scattering, irradiance)| {
+                sum + SampledSpectrum::from_rgb(scattering, wavelengths)
+                    * SampledSpectrum::from_rgb_illuminant(irradiance, wavelengths)
+            },
+        );
+
+        let Some(scatter) = scatter else {
+            return spectrum_from_emission + spectrum_from_lights;
+        };
+        let mut scattered = scatter.scattered;
+        scattered.wavelength = r.wavelength;
+
+        let spectrum_from_scatter = SampledSpectrum::from_rgb(scatter.attenuation, wavelengths)
+            * self.ray_spectrum(
+                scattered,
+                depth - 1,
+                world,
+                lights,
+                screen,
+                wavelengths,
+                Self::scatter_pdf(&r, &rec, &scatter, lights),
+            );
+
+        spectrum_from_emission + spectrum_from_lights + spectrum_from_scatter
+    }
+
+    fn uv_differentials(&self, rec: &HitRecord) -> UvDifferentials {
+        // Estimates how far the texture coordinates at a hit move between neighbouring pixels,
+        // from where rays through the pixels around it would meet the plane tangent to the
+        // surface (PBRT-v4's `Approximate_dp_dxy`). Hits seen through reflections and
+        // refractions are treated as if the camera saw them directly.
+        let unknown = UvDifferentials {
+            normal: rec.geometric_normal,
+            ..Default::default()
+        };
+        let forward = Vec3::unit_vector(Vec3::cross(&self.pixel_delta_u, &self.pixel_delta_v));
+        let to_p = rec.p - self.camera_center;
+        let depth = to_p.dot(&forward);
+        if depth <= 0.0 {
+            return unknown;
+        }
+        let direction = to_p * ((self.pixel00_loc - self.camera_center).dot(&forward) / depth);
+
+        // The pixel samples already average over part of the pixel, so each one needs to
+        // filter a smaller area.
+        let scale = (1.0 / (self.samples_per_pixel as Float).sqrt()).max(0.125);
+        let dp = |delta: Vec3| {
+            let offset_direction = direction + delta;
+            let denominator = rec.geometric_normal.dot(&offset_direction);
+            if denominator.abs() < 1e-8 {
+                return Vec3::new(0.0, 0.0, 0.0);
+            }
+            let t = rec.geometric_normal.dot(&to_p) / denominator;
+            (self.camera_center + offset_direction * t - rec.p) * scale
+        };
+        let (dpdx, dpdy) = (dp(self.pixel_delta_u), dp(self.pixel_delta_v));
+
+        // Least squares fit of the changes in u and v that give those changes in position.
+        let (dpdu, dpdv) = (rec.dpdu, rec.dpdv);
+        let (a00, a01, a11) = (dpdu.dot(&dpdu), dpdu.dot(&dpdv), dpdv.dot(&dpdv));
+        let determinant = a00 * a11 - a01 * a01;
+        if determinant <= 0.0 {
+            return unknown;
+        }
+        let solve = |dp: Vec3| {
+            let (b0, b1) = (dpdu.dot(&dp), dpdv.dot(&dp));
+            (
+                (a11 * b0 - a01 * b1) / determinant,
+                (a00 * b1 - a01 * b0) / determinant,
+            )
+        };
+        let (dudx, dvdx) = solve(dpdx);
+        let (dudy, dvdy) = solve(dpdy);
+        let finite = |x: Float| if x.is_finite() { x } else { 0.0 };
+        UvDifferentials {
+            dudx: finite(dudx),
+            dvdx: finite(dvdx),
+            dudy: finite(dudy),
+            dvdy: finite(dvdy),
+            normal: rec.geometric_normal,
+        }
+    }
 
-        let color_from_scatter = scat.unwrap().1
-            * Self::ray_color(scat.unwrap().0.unwrap(), depth - 1, world, background);
+    fn sample_color(
+        &self,
+        r: Ray,
+        world: &dyn Hittable,
+        lights: &LightList,
+        screen: (Float, Float),
+    ) -> Vec3 {
+        if !self.spectral {
+            return self.ray_color(r, self.max_depth, world, lights, screen, None);
+        }
 
-        color_from_emission + color_from_scatter
+        let mut wavelengths = SampledWavelengths::sample_visible(random_double());
+        let mut r = r;
+        r.wavelength = wavelengths.hero();
+        let spectrum = self.ray_spectrum(
+            r,
+            self.max_depth,
+            world,
+            lights,
+            screen,
+            &mut wavelengths,
+            None,
+        );
+        spectrum.to_rgb(&wavelengths)
     }
 
     pub fn render(&self, world: &dyn Hittable) {
+        self.render_with_lights(world, &LightList::new());
+    }
+
+    pub fn render_with_lights(&self, world: &dyn Hittable, lights: &LightList) {
         let image_width = self.image_width as usize;
         let image_height = self.image_height as usize;
 
@@ -85,18 +330,21 @@ impl Camera {
             let row_start_time = Instant::now();
             for (i, pixel) in band.chunks_exact_mut(3).enumerate() {
                 let mut pixel_color = Vec3::new(0.0, 0.0, 0.0);
+                let screen = (
+                    (i as Float + 0.5) / self.image_width,
+                    1.0 - (j as Float + 0.5) / self.image_height,
+                );
                 for _sample in 0..self.samples_per_pixel {
                     let r = self.get_ray(i as i32, j as i32);
-                    pixel_color =
-                        pixel_color + Camera::ray_color(r, self.max_depth, world, self.background);
+                    pixel_color = pixel_color + self.sample_color(r, world, lights, screen);
                 }
 
                 // Scale and gamma correct the color, then convert to bytes.
                 let color = self.pixel_samples_scale * pixel_color;
 
-                let mut r: f64 = color.x();
-                let mut g: f64 = color.y();
-                let mut b: f64 = color.z();
+                let mut r: Float = color.x();
+                let mut g: Float = color.y();
+                let mut b: Float = color.z();
 
                 // Apply a linear to gamma transform for gamma 2
                 r = linear_to_gamma(r);
@@ -207,8 +455,8 @@ impl Camera {
         // point around the pixel location i, j.
         let offset = self.sample_square();
         let pixel_sample = self.pixel00_loc
-            + ((i as f64 + offset.x()) * self.pixel_delta_u)
-            + ((j as f64 + offset.y()) * self.pixel_delta_v);
+            + ((i as Float + offset.x()) * self.pixel_delta_u)
+            + ((j as Float + offset.y()) * self.pixel_delta_v);
 
         let ray_origin = if self.defocus_angle <= 0.0 {
             self.camera_center
@@ -217,8 +465,38 @@ impl Camera {
         };
 
         let ray_direction = pixel_sample - ray_origin;
+        let ray_time =
+            self.shutter_open + random_double() * (self.shutter_close - self.shutter_open);
+
+        Ray::new(ray_origin, ray_direction, ray_time)
+    }
+
+    pub fn set_shutter(&mut self, shutter_open: Float, shutter_close: Float) {
+        // Sets the interval camera ray times are sampled from. Moving objects are
+        // blurred over the part of their motion that falls within it. Times outside the
+        // motion are allowed, but see the objects stopped: moving spheres hold still before
+        // time 0 and after time 1, and animated instances before their first and after their
+        // last keyframe.
+        self.shutter_open = shutter_open;
+        self.shutter_close = shutter_close;
+    }
+
+    pub fn set_spectral(&mut self, spectral: bool) {
+        // Traces each path at a few sampled wavelengths and converts the result to sRGB. This
+        // is slower, but lets dispersive materials split light into colors.
+        self.spectral = spectral;
+    }
+
+    pub fn set_background(&mut self, background: Arc<dyn Background>) {
+        // Replaces the constant background color given to `new`.
+        self.background = background;
+    }
 
-        Ray::new(ray_origin, ray_direction)
+    pub fn set_camera_background(&mut self, background: Arc<dyn Background>) {
+        // Shows `background` to rays straight from the camera instead of the background or the
+        // environment light, which still light the scene and show in reflections. Handy for a
+        // backplate that shouldn't tint the lighting.
+        self.camera_background = Some(background);
     }
 
     fn defocus_disk_sample(&self) -> Vec3 {
@@ -232,17 +510,18 @@ impl Camera {
         Vec3::new(random_double() - 0.5, random_double() - 0.5, 0.0)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        aspect_ratio: f64,
-        image_width: f64,
+        aspect_ratio: Float,
+        image_width: Float,
         samples_per_pixel: i32,
         max_depth: i32,
-        vertical_fov: f64,
+        vertical_fov: Float,
         look_from: Vec3,
         look_at: Vec3,
         vup: Vec3,
-        defocus_angle: f64,
-        focus_dist: f64,
+        defocus_angle: Float,
+        focus_dist: Float,
         file_name: &str,
         background: Vec3,
     ) -> Self {
@@ -255,7 +534,7 @@ impl Camera {
 
         // Determine viewport dimensions.
         let theta = degrees_to_radians(vertical_fov);
-        let h = f64::tan(theta / 2.0);
+        let h = Float::tan(theta / 2.0);
         let viewport_height = 2.0 * h * focus_dist;
         let viewport_width = viewport_height * image_width / image_height;
         let camera_center = look_from;
@@ -279,11 +558,11 @@ impl Camera {
         let pixel00_loc = viewport_upper_left + ((pixel_delta_u + pixel_delta_v) * 0.5);
 
         // Calculate the camera defocus disk basis vectors.
-        let defocus_radius = focus_dist * f64::tan(degrees_to_radians(defocus_angle / 2.0));
+        let defocus_radius = focus_dist * Float::tan(degrees_to_radians(defocus_angle / 2.0));
         let defocus_disk_u = u * defocus_radius;
         let defocus_disk_v = v * defocus_radius;
 
-        let pixel_samples_scale = 1.0 / samples_per_pixel as f64;
+        let pixel_samples_scale = 1.0 / samples_per_pixel as Float;
 
         Self {
             image_width,
@@ -299,7 +578,11 @@ impl Camera {
             defocus_angle,
             defocus_disk_u,
             defocus_disk_v,
-            background,
+            background: Arc::new(SolidBackground::new(background)),
+            camera_background: None,
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+            spectral: false,
         }
     }
 }
@@ -1,6 +1,8 @@
-pub fn linear_to_gamma(linear_component: f64) -> f64 {
+use crate::utils::Float;
+
+pub fn linear_to_gamma(linear_component: Float) -> Float {
     if linear_component > 0.0 {
-        return f64::sqrt(linear_component);
+        return Float::sqrt(linear_component);
     }
-    return 0.0;
+    0.0
 }
@@ -0,0 +1,101 @@
+use crate::utils::Float;
+
+// Piecewise-constant distribution over [0, 1], made of `func.len()` equal steps, for picking
+// samples in proportion to a tabulated function.
+pub struct Distribution1D {
+    func: Vec<Float>,
+    cdf: Vec<Float>,
+    integral: Float,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<Float>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].abs() / n as Float;
+        }
+
+        // A function that is zero everywhere is sampled evenly instead.
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as Float / n as Float
+            };
+        }
+
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> Float {
+        self.integral
+    }
+
+    pub fn sample(&self, u: Float) -> (Float, Float, usize) {
+        // Maps `u` in [0, 1) to a sample, its density and the step it falls in.
+        let offset = self.cdf.partition_point(|&c| c <= u).clamp(1, self.count()) - 1;
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 {
+            (u - self.cdf[offset]) / width
+        } else {
+            0.0
+        };
+        let x = ((offset as Float + du) / self.count() as Float).min(1.0);
+        (x, self.pdf_at(offset), offset)
+    }
+
+    pub fn pdf(&self, x: Float) -> Float {
+        let offset = ((x * self.count() as Float) as usize).min(self.count() - 1);
+        self.pdf_at(offset)
+    }
+
+    fn pdf_at(&self, offset: usize) -> Float {
+        if self.integral > 0.0 {
+            self.func[offset].abs() / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+// Piecewise-constant distribution over [0, 1]^2, sampled by picking a row from the marginal
+// distribution, then a column from the distribution of that row.
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[Float], width: usize, height: usize) -> Distribution2D {
+        // `func` holds `height` rows of `width` values.
+        let rows: Vec<Distribution1D> = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral()).collect());
+        Distribution2D { rows, marginal }
+    }
+
+    pub fn sample(&self, u: Float, v: Float) -> (Float, Float, Float) {
+        // Returns the sample as (x, y), and its density.
+        let (y, pdf_y, row) = self.marginal.sample(v);
+        let (x, pdf_x, _) = self.rows[row].sample(u);
+        (x, y, pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, x: Float, y: Float) -> Float {
+        let row = ((y * self.marginal.count() as Float) as usize).min(self.marginal.count() - 1);
+        self.marginal.pdf(y) * self.rows[row].pdf(x)
+    }
+}
@@ -0,0 +1,138 @@
+use crate::distribution::Distribution2D;
+use crate::image::{ColorSpace, Image};
+use crate::lights::{Light, LightSample};
+use crate::texture_manager::{LoadError, TextureManager};
+use crate::utils::{degrees_to_radians, random_double, Float, PI};
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// Light arriving from all around the scene, which rays that leave the scene see. It is sampled
+// like the other lights, so it needs to tell the camera how likely `sample` is to pick each
+// direction.
+pub trait Environment: Light {
+    fn radiance(&self, direction: Vec3) -> Vec3;
+    fn pdf(&self, direction: Vec3) -> Float;
+    fn as_light(&self) -> &dyn Light;
+}
+
+// Light arriving from every direction, read from an equirectangular image (.hdr or .exr) that
+// wraps around the scene like a sky, with its top row straight up. Bright parts of the image
+// are sampled more often, so a sun in the image casts clean shadows.
+pub struct EnvironmentLight {
+    image: Arc<Image>,
+    intensity: Float,
+    rotation: Float, // Radians about the y axis
+    distribution: Distribution2D,
+}
+
+impl EnvironmentLight {
+    pub fn new(filename: &str) -> Result<EnvironmentLight, LoadError> {
+        let image = TextureManager::global().image(filename, ColorSpace::Srgb)?;
+        let (width, height) = (image.width as usize, image.height as usize);
+
+        // Pixels are picked in proportion to their luminance and to the solid angle they
+        // cover, which shrinks towards the poles.
+        let mut func = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let sin_theta = (PI * (y as Float + 0.5) / height as Float).sin();
+            for x in 0..width {
+                let color = image.pixel(x as i32, y as i32);
+                let luminance = 0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z();
+                func.push(luminance.max(0.0) * sin_theta);
+            }
+        }
+
+        Ok(EnvironmentLight {
+            distribution: Distribution2D::new(&func, width, height),
+            image,
+            intensity: 1.0,
+            rotation: 0.0,
+        })
+    }
+
+    pub fn with_intensity(mut self, intensity: Float) -> EnvironmentLight {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_rotation(mut self, degrees: Float) -> EnvironmentLight {
+        // Turns the image about the vertical axis.
+        self.rotation = degrees_to_radians(degrees);
+        self
+    }
+
+    fn direction_to_uv(&self, direction: Vec3) -> (Float, Float) {
+        direction_to_equirect(self.rotate(direction, -self.rotation))
+    }
+
+    fn uv_to_direction(&self, u: Float, v: Float) -> Vec3 {
+        self.rotate(equirect_to_direction(u, v), self.rotation)
+    }
+
+    fn rotate(&self, d: Vec3, angle: Float) -> Vec3 {
+        // Rotates `d` about the y axis.
+        let (sin, cos) = angle.sin_cos();
+        Vec3::new(cos * d.x() + sin * d.z(), d.y(), -sin * d.x() + cos * d.z())
+    }
+}
+
+impl Environment for EnvironmentLight {
+    fn radiance(&self, direction: Vec3) -> Vec3 {
+        let (u, v) = self.direction_to_uv(direction);
+        let x = ((u * self.image.width as Float) as i32).min(self.image.width - 1);
+        let y = ((v * self.image.height as Float) as i32).min(self.image.height - 1);
+        self.image.pixel(x, y) * self.intensity
+    }
+
+    fn pdf(&self, direction: Vec3) -> Float {
+        // Density over solid angle of `sample` picking `direction`.
+        let (u, v) = self.direction_to_uv(direction);
+        equirect_pdf(self.distribution.pdf(u, v), v)
+    }
+
+    fn as_light(&self) -> &dyn Light {
+        self
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn sample(&self, _p: Vec3) -> Option<LightSample> {
+        let (u, v, pdf_uv) = self.distribution.sample(random_double(), random_double());
+        let pdf = equirect_pdf(pdf_uv, v);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        let direction = self.uv_to_direction(u, v);
+        Some(LightSample {
+            direction,
+            distance: Float::INFINITY,
+            irradiance: self.radiance(direction) / pdf,
+            pdf: Some(pdf),
+        })
+    }
+}
+
+pub fn direction_to_equirect(direction: Vec3) -> (Float, Float) {
+    // Equirectangular image coordinates in [0, 1], with v going down from the top of the sky.
+    // Longitude is measured as for `Sphere`, so a sphere textured with the same image lines up.
+    let d = Vec3::unit_vector(direction);
+    let theta = d.y().clamp(-1.0, 1.0).acos();
+    let phi = (-d.z()).atan2(d.x()) + PI;
+    ((phi / (2.0 * PI)).clamp(0.0, 1.0), theta / PI)
+}
+
+pub fn equirect_to_direction(u: Float, v: Float) -> Vec3 {
+    let (sin_theta, cos_theta) = (PI * v).sin_cos();
+    let (sin_phi, cos_phi) = (2.0 * PI * u).sin_cos();
+    Vec3::new(-sin_theta * cos_phi, cos_theta, sin_theta * sin_phi)
+}
+
+pub fn equirect_pdf(pdf_uv: Float, v: Float) -> Float {
+    // Turns a density over equirectangular image coordinates into one over solid angle.
+    let sin_theta = (PI * v).sin();
+    if sin_theta <= 0.0 {
+        return 0.0;
+    }
+    pdf_uv / (2.0 * PI * PI * sin_theta)
+}
@@ -0,0 +1,300 @@
+use crate::aabb::Aabb;
+use crate::bvh::{BuildNode, BvhBuild, BvhStats, INTERSECTION_COST, TRAVERSAL_COST};
+use crate::hittables::{HitRecord, Hittable, HittableList};
+use crate::ray::Ray;
+use crate::utils::{Float, Interval};
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// Upper bound on the depth of a built tree, see `MAX_SAH_DEPTH` in the builder.
+const STACK_SIZE: usize = 128;
+
+// How much the SAH cost may grow through refitting before `update` rebuilds the tree.
+const REBUILD_COST_RATIO: Float = 1.5;
+
+#[derive(Debug, Clone, Copy)]
+struct LinearNode {
+    bbox: Aabb,
+    offset: u32, // Leaf: index of the first primitive. Interior: index of the second child.
+    count: u16,  // Number of primitives in a leaf, zero for interior nodes
+    axis: u8,    // Axis an interior node was split along
+}
+
+// BVH stored as a flat array of nodes in depth first order, so the first child of an interior
+// node always directly follows it. Leaves hold ranges of `primitives` and are traversed with an
+// explicit stack instead of recursion.
+//
+// Objects can be replaced after building, e.g. by an `Instance` with the transform of the next
+// frame, and the tree then refit instead of rebuilt. Keeping meshes in their own BVH behind
+// instances makes the top level the only tree that needs updating per frame.
+pub struct FlatBvh {
+    nodes: Vec<LinearNode>,
+    primitives: Vec<Arc<dyn Hittable>>,
+    slots: Vec<u32>,   // Index into `primitives` of each object of the source list
+    built_cost: Float, // SAH cost right after the last full build
+}
+
+impl FlatBvh {
+    pub fn new_from_list(list: &HittableList) -> Self {
+        FlatBvh::new_with_stats(&list.objects).0
+    }
+
+    pub fn new_from_list_with_stats(list: &HittableList) -> (Self, BvhStats) {
+        FlatBvh::new_with_stats(&list.objects)
+    }
+
+    pub fn new(objects: &[Arc<dyn Hittable>]) -> Self {
+        FlatBvh::new_with_stats(objects).0
+    }
+
+    pub fn new_with_stats(objects: &[Arc<dyn Hittable>]) -> (Self, BvhStats) {
+        let build = BvhBuild::new(objects, false);
+
+        let mut nodes = Vec::with_capacity(build.stats.interior_count + build.stats.leaf_count);
+        Self::flatten(&build.root, &mut nodes);
+
+        let mut slots = vec![0; build.indices.len()];
+        for (slot, index) in build.indices.iter().enumerate() {
+            slots[*index] = slot as u32;
+        }
+
+        let bvh = FlatBvh {
+            nodes,
+            primitives: build.objects,
+            slots,
+            built_cost: build.stats.sah_cost,
+        };
+        (bvh, build.stats)
+    }
+
+    pub fn len(&self) -> usize {
+        self.primitives.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+
+    pub fn memory_size(&self) -> usize {
+        // Bytes used by the nodes and primitive references, not counting the primitives.
+        self.nodes.len() * size_of::<LinearNode>()
+            + self.primitives.len() * size_of::<Arc<dyn Hittable>>()
+            + self.slots.len() * size_of::<u32>()
+    }
+
+    pub fn object(&self, index: usize) -> &Arc<dyn Hittable> {
+        // Objects are addressed by their position in the list the tree was built from.
+        &self.primitives[self.slots[index] as usize]
+    }
+
+    pub fn set_object(&mut self, index: usize, object: Arc<dyn Hittable>) {
+        // Replaces an object without updating the bounding boxes, call `refit` or `update`
+        // once all objects of the frame have been replaced.
+        self.primitives[self.slots[index] as usize] = object;
+    }
+
+    pub fn refit(&mut self) {
+        // Children are always stored after their parent, so walking the nodes backwards
+        // recomputes every box from already updated children. An empty tree is a single leaf
+        // with no primitives, which has nothing to refit.
+        if self.primitives.is_empty() {
+            return;
+        }
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            self.nodes[i].bbox = if node.count > 0 {
+                let start = node.offset as usize;
+                let mut bbox = Aabb::EMPTY;
+                for object in &self.primitives[start..start + node.count as usize] {
+                    bbox = Aabb::new_from_aabb(bbox, object.bounding_box());
+                }
+                bbox
+            } else {
+                Aabb::new_from_aabb(
+                    self.nodes[i + 1].bbox,
+                    self.nodes[node.offset as usize].bbox,
+                )
+            };
+        }
+    }
+
+    pub fn sah_cost(&self) -> Float {
+        // Expected cost of tracing a ray that hits the root box, comparable with `BvhStats`.
+        // A tree without primitives, or squeezed into a box without area, costs nothing.
+        let root_area = self.nodes[0].bbox.surface_area();
+        if self.primitives.is_empty() || root_area <= 0.0 {
+            return 0.0;
+        }
+        let mut cost = 0.0;
+        for node in &self.nodes {
+            cost += if node.count > 0 {
+                INTERSECTION_COST * node.count as Float * node.bbox.surface_area()
+            } else {
+                TRAVERSAL_COST * node.bbox.surface_area()
+            };
+        }
+        cost / root_area
+    }
+
+    pub fn rebuild(&mut self) -> BvhStats {
+        let objects: Vec<Arc<dyn Hittable>> = (0..self.len())
+            .map(|index| self.object(index).clone())
+            .collect();
+        let (bvh, stats) = FlatBvh::new_with_stats(&objects);
+        *self = bvh;
+        stats
+    }
+
+    pub fn update(&mut self) -> bool {
+        // Refits the tree to the current objects, and rebuilds it if refitting has made it much
+        // more expensive to trace than it was after the last build. Returns whether it rebuilt.
+        self.refit();
+        if self.sah_cost() > self.built_cost * REBUILD_COST_RATIO {
+            self.rebuild();
+            return true;
+        }
+        false
+    }
+
+    fn flatten(node: &BuildNode, nodes: &mut Vec<LinearNode>) -> usize {
+        let index = nodes.len();
+
+        match node {
+            BuildNode::Leaf { bbox, start, count } => nodes.push(LinearNode {
+                bbox: *bbox,
+                offset: *start as u32,
+                count: *count as u16,
+                axis: 0,
+            }),
+            BuildNode::Interior {
+                bbox,
+                axis,
+                left,
+                right,
+            } => {
+                nodes.push(LinearNode {
+                    bbox: *bbox,
+                    offset: 0,
+                    count: 0,
+                    axis: *axis as u8,
+                });
+                Self::flatten(left, nodes);
+                nodes[index].offset = Self::flatten(right, nodes) as u32;
+            }
+        }
+
+        index
+    }
+}
+
+impl Hittable for FlatBvh {
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        // An empty tree is a single leaf with no primitives.
+        if self.primitives.is_empty() {
+            return None;
+        }
+
+        let inv_direction = Vec3::new(
+            1.0 / r.direction.x(),
+            1.0 / r.direction.y(),
+            1.0 / r.direction.z(),
+        );
+        let dir_is_neg = [
+            r.direction.x() < 0.0,
+            r.direction.y() < 0.0,
+            r.direction.z() < 0.0,
+        ];
+
+        let mut temp_rec = None;
+        let mut closest_so_far = ray_t.max;
+
+        let mut stack = [0u32; STACK_SIZE];
+        let mut stack_size = 0;
+        let mut node_index = 0;
+
+        loop {
+            let node = &self.nodes[node_index];
+            let node_t = Interval::new(ray_t.min, closest_so_far);
+
+            if node.bbox.hit_precomputed(r.origin, inv_direction, node_t) {
+                if node.count > 0 {
+                    // Leaf: test every primitive in the range.
+                    let start = node.offset as usize;
+                    for object in &self.primitives[start..start + node.count as usize] {
+                        if let Some(hit) = object.hit(r, Interval::new(ray_t.min, closest_so_far)) {
+                            closest_so_far = hit.t;
+                            temp_rec = Some(hit);
+                        }
+                    }
+                } else {
+                    // Interior: visit the child nearer to the ray origin first and save the
+                    // other one for later.
+                    let first = node_index + 1;
+                    let second = node.offset as usize;
+                    if dir_is_neg[node.axis as usize] {
+                        stack[stack_size] = first as u32;
+                        node_index = second;
+                    } else {
+                        stack[stack_size] = second as u32;
+                        node_index = first;
+                    }
+                    stack_size += 1;
+                    continue;
+                }
+            }
+
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            node_index = stack[stack_size] as usize;
+        }
+
+        temp_rec
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.nodes[0].bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material};
+    use crate::shapes::Sphere;
+
+    fn sphere(center: Vec3) -> Arc<dyn Hittable> {
+        let white = Material::Lambertian(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+        Arc::new(Sphere::new(center, 1.0, white))
+    }
+
+    #[test]
+    fn empty_tree() {
+        let mut bvh = FlatBvh::new(&[]);
+        assert!(bvh.is_empty());
+        assert_eq!(bvh.sah_cost(), 0.0);
+        bvh.refit();
+        assert!(!bvh.update());
+        let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert!(bvh.hit(r, Interval::new(0.0, Float::INFINITY)).is_none());
+    }
+
+    #[test]
+    fn refit_follows_moved_objects() {
+        let objects: Vec<Arc<dyn Hittable>> = (0..8)
+            .map(|i| sphere(Vec3::new(3.0 * i as Float, 0.0, 0.0)))
+            .collect();
+        let mut bvh = FlatBvh::new(&objects);
+        assert!(bvh.sah_cost().is_finite());
+
+        // Move the first sphere far above the others, where a ray along y can only hit it after
+        // the boxes have been refit.
+        bvh.set_object(0, sphere(Vec3::new(0.0, 100.0, 0.0)));
+        bvh.update();
+        assert!(bvh.sah_cost().is_finite());
+        let r = Ray::new(Vec3::new(0.0, 50.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        let rec = bvh.hit(r, Interval::new(0.0, Float::INFINITY)).unwrap();
+        assert!((rec.t - 49.0).abs() < 1e-3);
+    }
+}
@@ -1,29 +1,38 @@
 use crate::aabb::Aabb;
 use crate::material::{Isotropic, Material};
 use crate::ray::Ray;
-use crate::textures::Texture;
-use crate::utils::{degrees_to_radians, random_double, Interval};
+use crate::textures::{Texture, UvDifferentials};
+use crate::transform::{AnimatedTransform, Transform};
+use crate::utils::{
+    degrees_to_radians, gamma, hash_float, next_float_down, next_float_up, random_double, Float,
+    Interval,
+};
 use crate::vec3::Vec3;
 use std::sync::Arc;
 
 pub trait Hittable: Send + Sync {
-    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord>;
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>>;
     fn bounding_box(&self) -> Aabb;
 }
 
 #[derive(Clone, Copy)]
 pub struct HitRecord<'material> {
     pub p: Vec3,
-    pub normal: Vec3,
-    pub t: f64,
+    pub p_error: Vec3, // Bound on the rounding error in each coordinate of `p`
+    pub normal: Vec3,  // Shading normal, tilted by normal and bump maps
+    pub geometric_normal: Vec3, // Normal of the surface itself, on the same side as `normal`
+    pub t: Float,
     pub front_face: bool,
     pub material: &'material Material,
-    pub u: f64,
-    pub v: f64,
+    pub u: Float,
+    pub v: Float,
+    pub dpdu: Vec3, // How far the point moves per unit of u, along the surface
+    pub dpdv: Vec3,
+    pub uv_differentials: UvDifferentials, // Filled in by the camera, for filtering textures
 }
 
 impl<'material> HitRecord<'material> {
-    pub fn set_face_normal(&mut self, r: Ray, outward_normal: Vec3) -> () {
+    pub fn set_face_normal(&mut self, r: Ray, outward_normal: Vec3) {
         // Sets the hit record normal vector.
         // NOTE: the parameter `outward_normal` is assumed to have unit length.
 
@@ -33,25 +42,63 @@ impl<'material> HitRecord<'material> {
         } else {
             -outward_normal
         };
+        self.geometric_normal = self.normal;
+    }
+
+    pub fn spawn_ray(&self, direction: Vec3, time: Float) -> Ray {
+        // Starts a ray at the hit point, moved along the normal just past the error bounds of
+        // the point, to the side the ray leaves through (PBRT's `OffsetRayOrigin`). The true
+        // surface lies within the bounds, so the ray can't hit it again because of rounding.
+        // The geometric normal is used, since a shading normal can lean away from the surface.
+        let distance = self.geometric_normal.abs().dot(&self.p_error);
+        let mut offset = self.geometric_normal * distance;
+        if direction.dot(&self.geometric_normal) < 0.0 {
+            offset = -offset;
+        }
+
+        // Round away from the hit point so the offset isn't lost in the addition.
+        let round_away = |value: Float, offset: Float| {
+            if offset > 0.0 {
+                next_float_up(value)
+            } else if offset < 0.0 {
+                next_float_down(value)
+            } else {
+                value
+            }
+        };
+        let origin = self.p + offset;
+        let origin = Vec3::new(
+            round_away(origin.x(), offset.x()),
+            round_away(origin.y(), offset.y()),
+            round_away(origin.z(), offset.z()),
+        );
+        Ray::new(origin, direction, time)
     }
 
     pub fn new(
         p: Vec3,
         normal: Vec3,
-        t: f64,
+        t: Float,
         front_face: bool,
         material: &'material Material,
-        u: f64,
-        v: f64,
+        u: Float,
+        v: Float,
     ) -> Self {
+        // The point is taken as exact, which suits points that are not on a surface, such as
+        // scattering events inside a volume.
         Self {
             p,
+            p_error: Vec3::new(0.0, 0.0, 0.0),
             normal,
+            geometric_normal: normal,
             t,
             front_face,
             material,
             u,
             v,
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
+            uv_differentials: UvDifferentials::default(),
         }
     }
 }
@@ -68,7 +115,7 @@ impl HittableList {
             bbox: Default::default(),
         }
     }
-    pub fn clear(&mut self) -> () {
+    pub fn clear(&mut self) {
         self.objects.clear();
     }
     pub fn add(&mut self, object: Arc<dyn Hittable>) {
@@ -78,7 +125,7 @@ impl HittableList {
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
         let mut temp_rec = None;
         let mut closest_so_far = ray_t.max;
 
@@ -116,18 +163,13 @@ impl Translate {
 }
 
 impl Hittable for Translate {
-    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord> {
-        let offset_r = Ray::new(r.origin - self.offset, r.direction);
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let offset_r = Ray::new(r.origin - self.offset, r.direction, r.time);
 
-        let hit = self.object.hit(offset_r, ray_t);
-
-        if hit.is_none() {
-            return None;
-        }
-
-        let mut temp_rec = hit?;
+        let mut temp_rec = self.object.hit(offset_r, ray_t)?;
 
         temp_rec.p = temp_rec.p + self.offset;
+        temp_rec.p_error = temp_rec.p_error + temp_rec.p.abs() * gamma(1);
 
         Some(temp_rec)
     }
@@ -139,28 +181,32 @@ impl Hittable for Translate {
 
 pub struct RotateY {
     object: Arc<dyn Hittable>,
-    sin_theta: f64,
-    cos_theta: f64,
+    sin_theta: Float,
+    cos_theta: Float,
     bbox: Aabb,
 }
 
 impl RotateY {
-    pub fn new(object: Arc<dyn Hittable>, angle: f64) -> Self {
+    pub fn new(object: Arc<dyn Hittable>, angle: Float) -> Self {
         let radians = degrees_to_radians(angle);
         let sin_theta = radians.sin();
         let cos_theta = radians.cos();
 
         let mut bbox = object.bounding_box();
 
-        let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
-        let mut max = Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        let mut min = Vec3::new(Float::INFINITY, Float::INFINITY, Float::INFINITY);
+        let mut max = Vec3::new(
+            Float::NEG_INFINITY,
+            Float::NEG_INFINITY,
+            Float::NEG_INFINITY,
+        );
 
         for i in 0..2 {
             for j in 0..2 {
                 for k in 0..2 {
-                    let x = i as f64 * bbox.x.max + (1 - i) as f64 * bbox.x.min;
-                    let y = j as f64 * bbox.y.max + (1 - j) as f64 * bbox.y.min;
-                    let z = k as f64 * bbox.z.max + (1 - k) as f64 * bbox.z.min;
+                    let x = i as Float * bbox.x.max + (1 - i) as Float * bbox.x.min;
+                    let y = j as Float * bbox.y.max + (1 - j) as Float * bbox.y.min;
+                    let z = k as Float * bbox.z.max + (1 - k) as Float * bbox.z.min;
 
                     let newx = cos_theta * x + sin_theta * z;
                     let newz = -sin_theta * x + cos_theta * z;
@@ -194,7 +240,7 @@ impl RotateY {
 }
 
 impl Hittable for RotateY {
-    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
         // Transform the ray from world space to object space.
 
         let origin = Vec3::new(
@@ -209,32 +255,215 @@ impl Hittable for RotateY {
             (self.sin_theta * r.direction.x()) + (self.cos_theta * r.direction.z()),
         );
 
-        let rotated_r = Ray::new(origin, direction);
+        let rotated_r = Ray::new(origin, direction, r.time);
 
         // Determine whether an intersection exists in object space (and if so, where).
 
-        let hit = self.object.hit(rotated_r, ray_t);
+        let mut temp_rec = self.object.hit(rotated_r, ray_t)?;
 
-        if hit.is_none() {
-            return None;
+        // Transform the intersection from object space back to world space.
+
+        let p = temp_rec.p;
+        let p_error = temp_rec.p_error;
+        temp_rec.p = Vec3::new(
+            (self.cos_theta * p.x()) + (self.sin_theta * p.z()),
+            p.y(),
+            (-self.sin_theta * p.x()) + (self.cos_theta * p.z()),
+        );
+
+        // Bound the error of the rotated point the same way as `Transform` does.
+        let (cos_abs, sin_abs) = (self.cos_theta.abs(), self.sin_theta.abs());
+        let error_x = cos_abs * p_error.x() + sin_abs * p_error.z();
+        let error_z = sin_abs * p_error.x() + cos_abs * p_error.z();
+        let rounding_x = cos_abs * p.x().abs() + sin_abs * p.z().abs();
+        let rounding_z = sin_abs * p.x().abs() + cos_abs * p.z().abs();
+        temp_rec.p_error = Vec3::new(
+            (1.0 + gamma(3)) * error_x + gamma(3) * rounding_x,
+            p_error.y(),
+            (1.0 + gamma(3)) * error_z + gamma(3) * rounding_z,
+        );
+
+        let rotate = |v: Vec3| {
+            Vec3::new(
+                (self.cos_theta * v.x()) + (self.sin_theta * v.z()),
+                v.y(),
+                (-self.sin_theta * v.x()) + (self.cos_theta * v.z()),
+            )
+        };
+        temp_rec.normal = rotate(temp_rec.normal);
+        temp_rec.geometric_normal = rotate(temp_rec.geometric_normal);
+        temp_rec.dpdu = rotate(temp_rec.dpdu);
+        temp_rec.dpdv = rotate(temp_rec.dpdv);
+
+        Some(temp_rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+// Places a shared piece of geometry in the world with its own transform. Only the transform,
+// its inverse and an optional material override are stored per instance, so the same
+// geometry (usually a `BvhNode`) can be placed many times at almost no memory cost.
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    object_to_world: Transform,
+    world_to_object: Transform,
+    material: Option<Arc<Material>>,
+    bbox: Aabb,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable>, object_to_world: Transform) -> Self {
+        let bbox = object.bounding_box().transformed(&object_to_world);
+
+        Instance {
+            object,
+            object_to_world,
+            world_to_object: object_to_world.inverse(),
+            material: None,
+            bbox,
         }
+    }
 
-        let mut temp_rec = hit?;
+    pub fn new_with_material(
+        object: Arc<dyn Hittable>,
+        object_to_world: Transform,
+        material: Arc<Material>,
+    ) -> Self {
+        let mut instance = Instance::new(object, object_to_world);
+        instance.material = Some(material);
+        instance
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        // Transform the ray from world space to object space. The direction is not
+        // normalized, so the hit parameter t is the same in both spaces.
+        let object_r = Ray::new(
+            self.world_to_object.transform_point(r.origin),
+            self.world_to_object.transform_vector(r.direction),
+            r.time,
+        );
+
+        let mut temp_rec = self.object.hit(object_r, ray_t)?;
 
         // Transform the intersection from object space back to world space.
+        (temp_rec.p, temp_rec.p_error) = self
+            .object_to_world
+            .transform_point_with_error(temp_rec.p, temp_rec.p_error);
+        temp_rec.normal = Vec3::unit_vector(self.world_to_object.transpose_vector(temp_rec.normal));
+        temp_rec.geometric_normal = Vec3::unit_vector(
+            self.world_to_object
+                .transpose_vector(temp_rec.geometric_normal),
+        );
+        temp_rec.dpdu = self.object_to_world.transform_vector(temp_rec.dpdu);
+        temp_rec.dpdv = self.object_to_world.transform_vector(temp_rec.dpdv);
 
-        temp_rec.p = Vec3::new(
-            (self.cos_theta * temp_rec.p.x()) + (self.sin_theta * temp_rec.p.z()),
-            temp_rec.p.y(),
-            (-self.sin_theta * temp_rec.p.x()) + (self.cos_theta * temp_rec.p.z()),
+        if let Some(material) = &self.material {
+            temp_rec.material = material;
+        }
+
+        Some(temp_rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+// Like `Instance`, but the transform is interpolated between keyframes at the time of
+// each ray, which blurs the object over the camera shutter interval.
+pub struct AnimatedInstance {
+    object: Arc<dyn Hittable>,
+    animation: AnimatedTransform,
+    bbox: Aabb,
+}
+
+impl AnimatedInstance {
+    // Number of poses sampled between each pair of keyframes when bounding the motion.
+    const BOUNDS_STEPS: usize = 32;
+
+    pub fn new(object: Arc<dyn Hittable>, animation: AnimatedTransform) -> Self {
+        // Bound the whole motion by the union of the boxes of densely sampled poses. Every time
+        // lies within half a step of a sample, so no point of the object is farther from where
+        // it was at a sample than it can travel in half a step. The union is padded by the
+        // largest such distance.
+        let object_bbox = object.bounding_box();
+        let keyframes = animation.keyframes();
+        let mut bbox = object_bbox.transformed(&keyframes[0].transform());
+
+        // Rotation and scale move points about the origin of the object, so the farthest corner
+        // of its box moves the most.
+        let largest = |v: Vec3| v.x().abs().max(v.y().abs()).max(v.z().abs());
+        let mut radius: Float = 0.0;
+        for x in [object_bbox.x.min, object_bbox.x.max] {
+            for y in [object_bbox.y.min, object_bbox.y.max] {
+                for z in [object_bbox.z.min, object_bbox.z.max] {
+                    radius = radius.max(Vec3::new(x, y, z).length());
+                }
+            }
+        }
+
+        let mut stray: Float = 0.0;
+        for pair in keyframes.windows(2) {
+            for step in 1..=Self::BOUNDS_STEPS {
+                let f = step as Float / Self::BOUNDS_STEPS as Float;
+                let time = pair[0].time + f * (pair[1].time - pair[0].time);
+                let pose_bbox = object_bbox.transformed(&animation.at(time));
+                bbox = Aabb::new_from_aabb(bbox, pose_bbox);
+            }
+
+            // With f running from 0 to 1 over the pair, the angles turn at a steady rate whose
+            // sum bounds the rate of the combined rotation, and the translation and scale change
+            // steadily. A point then moves at most as fast as the translation, plus
+            // turn * scale * radius from the rotation and growth * radius from the scale.
+            let (a, b) = (&pair[0], &pair[1]);
+            let angles = (b.rotation - a.rotation).abs();
+            let turn = degrees_to_radians(angles.x() + angles.y() + angles.z());
+            let growth = largest(b.scale - a.scale);
+            let scale = largest(a.scale).max(largest(b.scale));
+            let speed = (b.translation - a.translation).length() + (turn * scale + growth) * radius;
+            let h = 1.0 / Self::BOUNDS_STEPS as Float;
+            stray = stray.max(h / 2.0 * speed);
+        }
+        let bbox = Aabb::new(
+            bbox.x.expand(2.0 * stray),
+            bbox.y.expand(2.0 * stray),
+            bbox.z.expand(2.0 * stray),
         );
 
-        temp_rec.normal = Vec3::new(
-            (self.cos_theta * temp_rec.normal.x()) + (self.sin_theta * temp_rec.normal.z()),
-            temp_rec.normal.y(),
-            (-self.sin_theta * temp_rec.normal.x()) + (self.cos_theta * temp_rec.normal.z()),
+        AnimatedInstance {
+            object,
+            animation,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for AnimatedInstance {
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let object_to_world = self.animation.at(r.time);
+        let world_to_object = object_to_world.inverse();
+
+        let object_r = Ray::new(
+            world_to_object.transform_point(r.origin),
+            world_to_object.transform_vector(r.direction),
+            r.time,
         );
 
+        let mut temp_rec = self.object.hit(object_r, ray_t)?;
+
+        (temp_rec.p, temp_rec.p_error) =
+            object_to_world.transform_point_with_error(temp_rec.p, temp_rec.p_error);
+        temp_rec.normal = Vec3::unit_vector(world_to_object.transpose_vector(temp_rec.normal));
+        temp_rec.geometric_normal =
+            Vec3::unit_vector(world_to_object.transpose_vector(temp_rec.geometric_normal));
+        temp_rec.dpdu = object_to_world.transform_vector(temp_rec.dpdu);
+        temp_rec.dpdv = object_to_world.transform_vector(temp_rec.dpdv);
+
         Some(temp_rec)
     }
 
@@ -243,16 +472,73 @@ impl Hittable for RotateY {
     }
 }
 
+// Object with holes in it, where an opacity texture read at the uv coordinates of each hit is
+// below a threshold, as for leaves, fences and decals. Rays pass through the holes and carry on
+// to whatever is behind them, including the far side of the object itself. With partial
+// opacity instead, a surface half see-through lets half of the rays through. Which ones is
+// decided by a hash of the ray and the distance to the hit, so the same ray always gets the
+// same answer, however often it is traced.
+pub struct Cutout {
+    object: Arc<dyn Hittable>,
+    opacity: Arc<dyn Texture>,
+    threshold: Option<Float>, // None for partial opacity
+}
+
+impl Cutout {
+    pub fn new(object: Arc<dyn Hittable>, opacity: Arc<dyn Texture>, threshold: Float) -> Cutout {
+        Cutout {
+            object,
+            opacity,
+            threshold: Some(threshold),
+        }
+    }
+
+    pub fn new_partial(object: Arc<dyn Hittable>, opacity: Arc<dyn Texture>) -> Cutout {
+        Cutout {
+            object,
+            opacity,
+            threshold: None,
+        }
+    }
+}
+
+impl Hittable for Cutout {
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let mut ray_t = ray_t;
+        loop {
+            let rec = self.object.hit(r, ray_t)?;
+            let opacity = self.opacity.opacity(rec.u, rec.v, rec.p);
+            let solid = match self.threshold {
+                Some(threshold) => opacity >= threshold,
+                None => {
+                    let (o, d) = (r.origin, r.direction);
+                    opacity >= 1.0
+                        || hash_float(&[o.x(), o.y(), o.z(), d.x(), d.y(), d.z(), rec.t]) < opacity
+                }
+            };
+            if solid {
+                return Some(rec);
+            }
+            // Hits are only taken strictly inside the interval, so the same one isn't found again.
+            ray_t = Interval::new(rec.t, ray_t.max);
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.object.bounding_box()
+    }
+}
+
 pub struct ConstantMedium {
     boundary: Arc<dyn Hittable>,
-    neg_inv_density: f64,
+    neg_inv_density: Float,
     phase_function: Material,
 }
 
 impl ConstantMedium {
     pub fn new_from_texture(
         boundary: Arc<dyn Hittable>,
-        density: f64,
+        density: Float,
         texture: Arc<dyn Texture>,
     ) -> ConstantMedium {
         Self {
@@ -262,7 +548,7 @@ impl ConstantMedium {
         }
     }
 
-    pub fn new_from_color(boundary: Arc<dyn Hittable>, density: f64, albedo: Vec3) -> Self {
+    pub fn new_from_color(boundary: Arc<dyn Hittable>, density: Float, albedo: Vec3) -> Self {
         Self {
             boundary,
             neg_inv_density: -1.0 / density,
@@ -272,22 +558,14 @@ impl ConstantMedium {
 }
 
 impl Hittable for ConstantMedium {
-    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord> {
-        let hit1 = self.boundary.hit(r, Interval::UNIVERSE);
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let mut rec1 = self.boundary.hit(r, Interval::UNIVERSE)?;
 
-        if hit1.is_none() {
-            return None;
-        }
-        let mut rec1 = hit1?;
-
-        let hit2 = self
+        // Primitives only report hits strictly inside the interval, and the same ray gives the
+        // same distances, so starting at the entry point finds the exit point.
+        let mut rec2 = self
             .boundary
-            .hit(r, Interval::new(rec1.t + 0.0001, f64::INFINITY));
-
-        if hit2.is_none() {
-            return None;
-        }
-        let mut rec2 = hit2?;
+            .hit(r, Interval::new(rec1.t, Float::INFINITY))?;
 
         if rec1.t < ray_t.min {
             rec1.t = ray_t.min;
@@ -325,3 +603,241 @@ impl Hittable for ConstantMedium {
         self.boundary.bounding_box()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flat_bvh::FlatBvh;
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::shapes::{Cube, Cuboid, Quad, Sphere};
+    use crate::textures::SolidColor;
+    use crate::transform::Keyframe;
+
+    fn scaled_box_world(scale: Float) -> HittableList {
+        // A closed Cornell box scaled by `scale`, holding a sphere, a rotated cuboid, a cube and an
+        // instanced sphere, so rays inside it can never escape.
+        let mut world = HittableList::new();
+
+        let white = Material::Lambertian(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+        let red = Material::Lambertian(Lambertian::new(Vec3::new(0.65, 0.05, 0.05)));
+        let green = Material::Lambertian(Lambertian::new(Vec3::new(0.12, 0.45, 0.15)));
+        let light = Material::DiffuseLight(DiffuseLight::new(Vec3::new(15.0, 15.0, 15.0)));
+        let s = |x: Float, y: Float, z: Float| Vec3::new(x, y, z) * scale;
+
+        let walls = [
+            (
+                s(555.0, 0.0, 0.0),
+                s(0.0, 555.0, 0.0),
+                s(0.0, 0.0, 555.0),
+                &green,
+            ),
+            (
+                s(0.0, 0.0, 0.0),
+                s(0.0, 555.0, 0.0),
+                s(0.0, 0.0, 555.0),
+                &red,
+            ),
+            (
+                s(0.0, 0.0, 0.0),
+                s(555.0, 0.0, 0.0),
+                s(0.0, 0.0, 555.0),
+                &white,
+            ),
+            (
+                s(555.0, 555.0, 555.0),
+                s(-555.0, 0.0, 0.0),
+                s(0.0, 0.0, -555.0),
+                &white,
+            ),
+            (
+                s(0.0, 0.0, 555.0),
+                s(555.0, 0.0, 0.0),
+                s(0.0, 555.0, 0.0),
+                &white,
+            ),
+            (
+                s(0.0, 0.0, 0.0),
+                s(555.0, 0.0, 0.0),
+                s(0.0, 555.0, 0.0),
+                &white,
+            ),
+        ];
+        for (q, u, v, material) in walls {
+            world.add(Arc::new(Quad::new(q, u, v, material.clone())));
+        }
+        world.add(Arc::new(Quad::new(
+            s(343.0, 554.0, 332.0),
+            s(-130.0, 0.0, 0.0),
+            s(0.0, 0.0, -105.0),
+            light,
+        )));
+
+        world.add(Arc::new(Translate::new(
+            Arc::new(RotateY::new(
+                Arc::new(Cuboid::new(
+                    s(347.5, 165.0, 377.5),
+                    s(165.0, 330.0, 165.0),
+                    white.clone(),
+                )),
+                15.0,
+            )),
+            s(-70.0, 0.0, 40.0),
+        )));
+        world.add(Arc::new(Sphere::new(
+            s(190.0, 100.0, 190.0),
+            90.0 * scale,
+            white.clone(),
+        )));
+        world.add(Arc::new(Cube::new(
+            s(420.0, 60.0, 120.0),
+            120.0 * scale,
+            white.clone(),
+        )));
+
+        let unit_sphere = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, white));
+        world.add(Arc::new(Instance::new(
+            unit_sphere,
+            Transform::new_translation(s(400.0, 400.0, 300.0))
+                * Transform::new_scale(s(60.0, 40.0, 60.0)),
+        )));
+
+        world
+    }
+
+    #[test]
+    fn spawned_rays_never_hit_their_surface_again() {
+        // Follows diffuse and grazing bounces inside a closed box at scales from 1e-4 to 1e5.
+        // Rays hitting the surface they start on again show shadow acne, and rays leaving the
+        // box show light leaks.
+        let path_count = 5_000;
+        let bounce_count = 16;
+
+        for exponent in -4..=5 {
+            let scale = (10.0 as Float).powi(exponent);
+            let world = scaled_box_world(scale);
+            let bvh = FlatBvh::new_from_list(&world);
+
+            let mut escaped = 0;
+            let mut self_hits = 0;
+            for path in 0..path_count {
+                let origin = Vec3::random_range(20.0, 535.0) * scale;
+                let mut r = Ray::new(origin, Vec3::random_unit_vector(), 0.0);
+                let mut previous: Option<(Vec3, Vec3)> = None;
+
+                for bounce in 0..bounce_count {
+                    let Some(rec) = bvh.hit(r, Interval::new(0.0, Float::INFINITY)) else {
+                        escaped += 1;
+                        break;
+                    };
+
+                    // A hit right next to the previous one, on a surface facing the same way,
+                    // is the ray hitting the surface it left.
+                    if let Some((p, normal)) = previous {
+                        if (rec.p - p).length() < 1e-3 * scale
+                            && rec.normal.dot(&normal).abs() > 0.999
+                        {
+                            self_hits += 1;
+                        }
+                    }
+                    previous = Some((rec.p, rec.normal));
+
+                    // Mix cosine weighted bounces with nearly tangent ones, which are the most
+                    // likely to hit the surface again.
+                    let direction = if (path + bounce) % 4 == 0 {
+                        let tangent = Vec3::random_unit_vector();
+                        let tangent = tangent - rec.normal * tangent.dot(&rec.normal);
+                        tangent + rec.normal * 1e-3 * tangent.length()
+                    } else {
+                        rec.normal + Vec3::random_unit_vector()
+                    };
+                    r = rec.spawn_ray(direction, 0.0);
+                }
+            }
+
+            assert_eq!(
+                self_hits, 0,
+                "{} self hits at scale 1e{}",
+                self_hits, exponent
+            );
+            assert_eq!(
+                escaped, 0,
+                "{} rays escaped at scale 1e{}",
+                escaped, exponent
+            );
+        }
+    }
+
+    #[test]
+    fn animated_bounds_hold_every_pose() {
+        // An off-center box turned half way around, grown and moved, checked at many more
+        // times than the bounds are sampled at.
+        let white = Material::Lambertian(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+        let cuboid = Arc::new(Cuboid::new(
+            Vec3::new(5.0, 1.0, 2.0),
+            Vec3::new(2.0, 1.0, 1.0),
+            white,
+        ));
+        let animation = AnimatedTransform::new(vec![
+            Keyframe::new(
+                0.0,
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(1.0, 1.0, 1.0),
+            ),
+            Keyframe::new(
+                1.0,
+                Vec3::new(1.0, 2.0, 0.0),
+                Vec3::new(30.0, 180.0, 45.0),
+                Vec3::new(2.0, 1.0, 1.5),
+            ),
+        ]);
+        let object_bbox = cuboid.bounding_box();
+        let instance = AnimatedInstance::new(cuboid, animation.clone());
+        let bbox = instance.bounding_box();
+
+        let contains =
+            |outer: Interval, inner: Interval| outer.min <= inner.min && inner.max <= outer.max;
+        for i in 0..=10_000 {
+            let pose = object_bbox.transformed(&animation.at(i as Float / 10_000.0));
+            assert!(
+                contains(bbox.x, pose.x) && contains(bbox.y, pose.y) && contains(bbox.z, pose.z),
+                "pose {} leaves the bounds",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn partial_cutouts_are_deterministic() {
+        // A square half see-through lets about half of the rays through, and always the same
+        // ones.
+        let white = Material::Lambertian(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+        let square = Arc::new(Quad::new(
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            white,
+        ));
+        let half = Arc::new(SolidColor::new(Vec3::new(0.5, 0.5, 0.5)));
+        let cutout = Cutout::new_partial(square, half);
+
+        let ray_count = 10_000;
+        let mut solid = 0;
+        for _ in 0..ray_count {
+            let origin = Vec3::new(0.0, 0.0, 2.0) + Vec3::random_in_unit_sphere();
+            let r = Ray::new(origin, Vec3::random_in_unit_disk() * 0.3 - origin, 0.0);
+            let ray_t = Interval::new(0.0, Float::INFINITY);
+            let first = cutout.hit(r, ray_t).is_some();
+            for _ in 0..4 {
+                assert_eq!(cutout.hit(r, ray_t).is_some(), first);
+            }
+            solid += first as usize;
+        }
+        let fraction = solid as Float / ray_count as Float;
+        assert!(
+            (fraction - 0.5).abs() < 0.03,
+            "{} of the rays hit",
+            fraction
+        );
+    }
+}
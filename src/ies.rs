@@ -0,0 +1,141 @@
+use crate::texture_manager::{LoadError, TextureManager};
+use crate::utils::Float;
+
+// Photometric profile from an IES LM-63 file, which gives the intensity of a light fixture in
+// each direction. Vertical angles are measured from the direction the light points in, and
+// horizontal angles around it.
+#[derive(Debug, Clone)]
+pub struct IesProfile {
+    vertical_angles: Vec<Float>,
+    horizontal_angles: Vec<Float>,
+    candela: Vec<Vec<Float>>, // Intensity for each horizontal angle, then each vertical angle
+    max_candela: Float,
+}
+
+impl IesProfile {
+    pub fn load(filename: &str, manager: &TextureManager) -> Result<IesProfile, LoadError> {
+        // The file is found through the search paths of `manager`.
+        let (path, text) = manager.read_to_string(filename)?;
+        IesProfile::parse(&text).map_err(|message| LoadError::Invalid { path, message })
+    }
+
+    pub fn parse(text: &str) -> Result<IesProfile, String> {
+        // Keywords come first, up to the TILT line. Everything after it is numbers.
+        let mut lines = text.lines();
+        let tilt = lines
+            .by_ref()
+            .find(|line| line.trim_start().starts_with("TILT="))
+            .ok_or("Missing TILT line")?;
+        if tilt.trim() != "TILT=NONE" {
+            return Err(String::from("Only TILT=NONE is supported"));
+        }
+
+        let mut numbers = lines
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                token
+                    .parse::<Float>()
+                    .map_err(|_| format!("Invalid number \"{}\"", token))
+            });
+        let mut next = || -> Result<Float, String> { numbers.next().ok_or("File ends early")? };
+
+        // Lamp count, lumens per lamp and candela multiplier, then the angle counts.
+        for _ in 0..3 {
+            next()?;
+        }
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        // Photometric type, units, the size of the fixture, ballast factor, a reserved value
+        // and input watts.
+        for _ in 0..8 {
+            next()?;
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(String::from("Profile has no angles"));
+        }
+
+        let vertical_angles = (0..vertical_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let horizontal_angles = (0..horizontal_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let candela = (0..horizontal_count)
+            .map(|_| (0..vertical_count).map(|_| next()).collect())
+            .collect::<Result<Vec<Vec<_>>, _>>()?;
+
+        let max_candela = candela.iter().flatten().fold(0.0, |a: Float, &b| a.max(b));
+        if max_candela <= 0.0 {
+            return Err(String::from("Profile gives off no light"));
+        }
+
+        Ok(IesProfile {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+            max_candela,
+        })
+    }
+
+    pub fn value(&self, vertical: Float, horizontal: Float) -> Float {
+        // Intensity in a direction given by angles in degrees, relative to the brightest
+        // direction of the profile.
+        let first = self.vertical_angles[0];
+        let last = self.vertical_angles[self.vertical_angles.len() - 1];
+        if vertical < first || vertical > last {
+            return 0.0;
+        }
+
+        let horizontal = self.fold_horizontal(horizontal.rem_euclid(360.0));
+        let (h0, h1, fh) = IesProfile::bracket(&self.horizontal_angles, horizontal);
+        let (v0, v1, fv) = IesProfile::bracket(&self.vertical_angles, vertical);
+
+        let at = |h: usize| self.candela[h][v0] * (1.0 - fv) + self.candela[h][v1] * fv;
+        (at(h0) * (1.0 - fh) + at(h1) * fh) / self.max_candela
+    }
+
+    fn fold_horizontal(&self, angle: Float) -> Float {
+        // Files leave out the horizontal angles that symmetry gives, by stopping at 0, 90 or
+        // 180 degrees instead of 360.
+        let last = self.horizontal_angles[self.horizontal_angles.len() - 1];
+        let angle = if last <= 180.0 && angle > 180.0 {
+            360.0 - angle
+        } else {
+            angle
+        };
+        if last <= 90.0 && angle > 90.0 {
+            180.0 - angle
+        } else {
+            angle
+        }
+    }
+
+    fn bracket(angles: &[Float], angle: Float) -> (usize, usize, Float) {
+        // Indices of the angles either side of `angle`, and how far it is between them.
+        let upper = angles
+            .partition_point(|&a| a <= angle)
+            .clamp(1, angles.len());
+        let lower = upper - 1;
+        if upper == angles.len() {
+            return (lower, lower, 0.0);
+        }
+        let f = (angle - angles[lower]) / (angles[upper] - angles[lower]);
+        (lower, upper, f.clamp(0.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_through_search_paths() {
+        let manager = TextureManager::new().with_search_path("ies");
+        assert!(IesProfile::load("spot.ies", &manager).is_ok());
+        assert!(matches!(
+            IesProfile::load("missing.ies", &manager),
+            Err(LoadError::NotFound { .. })
+        ));
+    }
+}
@@ -1,64 +1,184 @@
-use stb_image::image::{self, LoadResult};
+use crate::utils::Float;
+use crate::vec3::Vec3;
+use stb_image::stb_image::{
+    stbi_failure_reason, stbi_image_free, stbi_is_16_bit, stbi_is_hdr, stbi_load, stbi_load_16,
+    stbi_loadf,
+};
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_int;
 use std::path::Path;
 
+// How the values stored in an 8 or 16 bit image file are meant. Float images (.hdr and .exr)
+// always hold linear values, whatever they are marked as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    Srgb,   // Colors, such as photographs and painted textures, which are decoded from sRGB
+    Linear, // Data, such as normal maps and roughness, which is used as stored
+}
+
+// Pixels are kept in single precision whatever `Float` is.
+type Component = f32;
+
+// Pixels of an image as linear RGBA floats, whatever the file held. Images without alpha are
+// opaque, and gray images are spread over all three colors.
 pub struct Image {
     pub width: i32,
     pub height: i32,
-    data: Vec<u8>,
+    has_alpha: bool,
+    data: Vec<Component>,
 }
 
 impl Image {
-    pub fn new(filename: &str) -> Result<Self, String> {
-        let current_dir = std::env::current_dir().unwrap();
-        let relative_path = Path::new("images").join(filename);
-        let full_path = current_dir.join(relative_path);
-
-        let image = image::load_with_depth(full_path, 3, false); // Load as RGB with 3 channels
-
-        match image {
-            LoadResult::ImageU8(image_data) => {
-                let width = image_data.width as i32;
-                let height = image_data.height as i32;
-                let mut data = image_data.data;
-
-                // Convert sRGB to linear gamma
-                for pixel in data.chunks_mut(3) {
-                    pixel[0] = Self::srgb_to_linear_u8(pixel[0]);
-                    pixel[1] = Self::srgb_to_linear_u8(pixel[1]);
-                    pixel[2] = Self::srgb_to_linear_u8(pixel[2]);
-                }
-
-                Ok(Self {
-                    width,
-                    height,
-                    data,
-                })
+    pub fn load(path: &Path, color_space: ColorSpace) -> Result<Self, String> {
+        // Reads an image file. `TextureManager` finds files by name and keeps them loaded, and
+        // names the file in its errors.
+        if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("exr"))
+        {
+            return Self::load_exr(path);
+        }
+
+        Self::load_stb(path, color_space)
+    }
+
+    fn load_stb(path: &Path, color_space: ColorSpace) -> Result<Self, String> {
+        // stb_image reads 8 bit, 16 bit and Radiance .hdr files. Every image is asked for as
+        // four channels, and the number the file itself has tells whether it had alpha.
+        let path = path
+            .to_str()
+            .and_then(|path| CString::new(path).ok())
+            .ok_or("path is not valid utf8")?;
+        let (mut width, mut height, mut channels) = (0 as c_int, 0 as c_int, 0 as c_int);
+
+        let (data, is_float) = unsafe {
+            let path = path.as_ptr();
+            if stbi_is_hdr(path) != 0 {
+                let buffer = stbi_loadf(path, &mut width, &mut height, &mut channels, 4);
+                (Self::take(buffer, width, height, |c| c), true)
+            } else if stbi_is_16_bit(path) != 0 {
+                let buffer = stbi_load_16(path, &mut width, &mut height, &mut channels, 4);
+                (
+                    Self::take(buffer, width, height, |c| c as f32 / 65535.0),
+                    false,
+                )
+            } else {
+                let buffer = stbi_load(path, &mut width, &mut height, &mut channels, 4);
+                (
+                    Self::take(buffer, width, height, |c| c as f32 / 255.0),
+                    false,
+                )
             }
-            _ => Err(String::from("Failed to load image or unsupported format")),
+        };
+        let data = data.ok_or_else(|| unsafe {
+            // stb_image keeps the reason for the last failure in a static string.
+            CStr::from_ptr(stbi_failure_reason())
+                .to_string_lossy()
+                .into_owned()
+        })?;
+
+        let mut image = Self {
+            width,
+            height,
+            has_alpha: channels == 2 || channels == 4,
+            data,
+        };
+        if color_space == ColorSpace::Srgb && !is_float {
+            image.decode_srgb();
+        }
+        Ok(image)
+    }
+
+    unsafe fn take<T: Copy>(
+        buffer: *mut T,
+        width: c_int,
+        height: c_int,
+        to_float: impl Fn(T) -> f32,
+    ) -> Option<Vec<f32>> {
+        // Copies four channel pixels out of a buffer from stb_image, and frees it.
+        if buffer.is_null() {
+            return None;
         }
+        let length = (width * height * 4) as usize;
+        let data = std::slice::from_raw_parts(buffer, length)
+            .iter()
+            .map(|&c| to_float(c))
+            .collect();
+        stbi_image_free(buffer as *mut c_void);
+        Some(data)
     }
 
-    fn srgb_to_linear_u8(c: u8) -> u8 {
-        let c = c as f32 / 255.0;
-        let linear = if c <= 0.04045 {
+    fn load_exr(path: &Path) -> Result<Self, String> {
+        // OpenEXR images hold linear values. Only the first layer is read, and alpha is 1
+        // where the layer has none.
+        let image = exr::prelude::read_first_rgba_layer_from_file(
+            path,
+            |resolution, _| {
+                (
+                    resolution.width(),
+                    vec![0.0; resolution.width() * resolution.height() * 4],
+                )
+            },
+            |(width, data): &mut (usize, Vec<f32>),
+             position,
+             (r, g, b, a): (f32, f32, f32, f32)| {
+                let index = (position.y() * *width + position.x()) * 4;
+                data[index..index + 4].copy_from_slice(&[r, g, b, a]);
+            },
+        )
+        .map_err(|e| e.to_string())?;
+
+        let size = image.layer_data.size;
+        let has_alpha = image.layer_data.channel_data.channels.3.is_some();
+        let (_, data) = image.layer_data.channel_data.pixels;
+        Ok(Self {
+            width: size.width() as i32,
+            height: size.height() as i32,
+            has_alpha,
+            data,
+        })
+    }
+
+    fn decode_srgb(&mut self) {
+        // Alpha is coverage, not color, so it is left as it is.
+        for pixel in self.data.chunks_mut(4) {
+            for c in &mut pixel[..3] {
+                *c = Self::srgb_to_linear(*c);
+            }
+        }
+    }
+
+    fn srgb_to_linear(c: f32) -> f32 {
+        if c <= 0.04045 {
             c / 12.92
         } else {
             ((c + 0.055) / 1.055).powf(2.4)
-        };
-        (linear * 255.0).round() as u8
+        }
     }
 
-    pub fn pixel_data(&self, x: i32, y: i32) -> (u8, u8, u8) {
+    pub fn has_alpha(&self) -> bool {
+        self.has_alpha
+    }
+
+    pub fn pixel(&self, x: i32, y: i32) -> Vec3 {
+        // Linear color of a pixel, where 8 and 16 bit values are scaled to [0, 1].
         if x < 0 || y < 0 || x >= self.width || y >= self.height {
-            return (255, 0, 255); // Magenta for out-of-bounds pixels
+            return Vec3::new(1.0, 0.0, 1.0); // Magenta for out-of-bounds pixels
         }
 
-        let index = ((y * self.width + x) * 3) as usize;
-
-        (
-            self.data[index],     // Red
-            self.data[index + 1], // Green
-            self.data[index + 2], // Blue
+        let index = ((y * self.width + x) * 4) as usize;
+        Vec3::new(
+            self.data[index] as Float,
+            self.data[index + 1] as Float,
+            self.data[index + 2] as Float,
         )
     }
+
+    pub fn alpha(&self, x: i32, y: i32) -> Float {
+        // Coverage of a pixel, from 0 where it is see-through to 1 where it is solid.
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return 1.0;
+        }
+        self.data[((y * self.width + x) * 4 + 3) as usize] as Float
+    }
 }
@@ -0,0 +1,188 @@
+use crate::environment::Environment;
+use crate::material::EmissionProfile;
+use crate::onb::Onb;
+use crate::utils::{degrees_to_radians, random_double, Float, PI};
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// Light reaching a point from a light source, found by `Light::sample`.
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    pub direction: Vec3,    // Unit vector from the point towards the light
+    pub distance: Float,    // How far a shadow ray has to go to reach the light
+    pub irradiance: Vec3,   // Light falling on a surface facing the light
+    pub pdf: Option<Float>, // Density of picking `direction`, for lights that rays can also find
+}
+
+// Light source that is not part of the scene geometry. The camera samples each light from every
+// point it shades, and traces a shadow ray to check that nothing is in the way.
+pub trait Light: Send + Sync {
+    fn sample(&self, p: Vec3) -> Option<LightSample>;
+}
+
+pub struct LightList {
+    pub lights: Vec<Arc<dyn Light>>,
+    // Sampled like the other lights, and also seen by rays that leave the scene.
+    pub environment: Option<Arc<dyn Environment>>,
+}
+
+impl LightList {
+    pub fn new() -> Self {
+        LightList {
+            lights: Vec::new(),
+            environment: None,
+        }
+    }
+    pub fn clear(&mut self) {
+        self.lights.clear();
+        self.environment = None;
+    }
+    pub fn add(&mut self, light: Arc<dyn Light>) {
+        self.lights.push(light);
+    }
+    pub fn set_environment(&mut self, environment: Arc<dyn Environment>) {
+        self.environment = Some(environment);
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Light> {
+        let environment = self.environment.iter().map(|e| e.as_light());
+        self.lights.iter().map(|l| l.as_ref()).chain(environment)
+    }
+}
+
+pub fn power_heuristic(pdf: Float, other_pdf: Float) -> Float {
+    // Weight of a sample found with density `pdf`, when another way of sampling could have
+    // found it with `other_pdf` (Veach's power heuristic). The weights of the two add up to 1.
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+// Light given off from a single point, equally in every direction. The intensity is the
+// irradiance at a distance of 1 metre, and falls off with the square of the distance.
+pub struct PointLight {
+    position: Vec3,
+    intensity: Vec3,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Vec3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.length();
+        if distance <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            irradiance: self.intensity / (distance * distance),
+            pdf: None,
+        })
+    }
+}
+
+// Point light shining in a cone, or following any other `EmissionProfile` around the direction
+// it points in.
+pub struct SpotLight {
+    position: Vec3,
+    direction: Vec3,
+    intensity: Vec3,
+    profile: EmissionProfile,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Vec3,
+        look_at: Vec3,
+        intensity: Vec3,
+        inner_angle: Float,
+        outer_angle: Float,
+    ) -> SpotLight {
+        // Cone angles are in degrees from the direction the light points in.
+        SpotLight {
+            position,
+            direction: Vec3::unit_vector(look_at - position),
+            intensity,
+            profile: EmissionProfile::new_spot(inner_angle, outer_angle),
+        }
+    }
+
+    pub fn with_profile(mut self, profile: EmissionProfile) -> SpotLight {
+        self.profile = profile;
+        self
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: Vec3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.length();
+        if distance <= 0.0 {
+            return None;
+        }
+        let direction = to_light / distance;
+        let scale = self.profile.value(-direction, self.direction);
+        if scale <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            irradiance: self.intensity * scale / (distance * distance),
+            pdf: None,
+        })
+    }
+}
+
+// Light from far enough away that it arrives from the same direction everywhere, like the sun.
+// The irradiance is the light falling on a surface facing the light. A light with an angular
+// diameter is a disk in the sky instead of a point, which softens the edges of shadows.
+pub struct DirectionalLight {
+    frame: Onb, // Frame whose normal points towards the light
+    irradiance: Vec3,
+    cos_theta_max: Float, // Cosine of the angular radius of the disk
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Vec3) -> DirectionalLight {
+        // `direction` points from the scene towards the light.
+        DirectionalLight {
+            frame: Onb::new(Vec3::unit_vector(direction)),
+            irradiance,
+            cos_theta_max: 1.0,
+        }
+    }
+
+    pub fn with_angular_diameter(mut self, angular_diameter: Float) -> DirectionalLight {
+        // The sun is about 0.53 degrees across.
+        self.cos_theta_max = degrees_to_radians(angular_diameter / 2.0).cos();
+        self
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: Vec3) -> Option<LightSample> {
+        // Picks a direction evenly within the disk, which carries the light of the whole disk.
+        let cos_theta = 1.0 - random_double() * (1.0 - self.cos_theta_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_double();
+        let local = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        Some(LightSample {
+            direction: self.frame.to_world(local),
+            distance: Float::INFINITY,
+            irradiance: self.irradiance,
+            pdf: None,
+        })
+    }
+}
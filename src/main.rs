@@ -1,26 +1,70 @@
 #![allow(dead_code)]
 mod aabb;
+mod background;
 mod bvh;
 mod camera;
 mod color;
+mod distribution;
+mod environment;
+mod flat_bvh;
 mod hittables;
+mod ies;
 mod image;
+mod lights;
 mod material;
+mod microfacet;
+mod mipmap;
+mod noise;
+mod onb;
 mod ray;
 mod shapes;
+mod sky;
+mod spectrum;
+mod texture_library;
+mod texture_manager;
+mod texture_nodes;
 mod textures;
+mod transform;
 mod utils;
 mod vec3;
+mod wide_bvh;
 
+use crate::aabb::Aabb;
+use crate::background::{GradientBackground, ScreenTexture};
 use crate::bvh::BvhNode;
 use crate::camera::Camera;
-use crate::hittables::{ConstantMedium, HittableList, RotateY, Translate};
-use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::environment::EnvironmentLight;
+use crate::flat_bvh::FlatBvh;
+use crate::hittables::{
+    AnimatedInstance, ConstantMedium, Cutout, Hittable, HittableList, Instance, RotateY,
+    Translate,
+};
+use crate::ies::IesProfile;
+use crate::lights::{DirectionalLight, LightList, PointLight, SpotLight};
+use crate::mipmap::{FilterMode, WrapMode};
+use crate::material::{
+    Conductor, Dielectric, DiffuseLight, EmissionProfile, Lambertian, Material, Metal, Principled,
+    PrincipledInput, ThinDielectric,
+};
+use crate::ray::Ray;
 use crate::shapes::{Cube, Cuboid, Quad, Sphere};
-use crate::textures::{CheckerTexture, ImageTexture};
-use crate::utils::{random_double, random_double_range, rotate_y_translation};
+use crate::sky::Sky;
+use crate::spectrum::{Dispersion, Spectrum, StandardIlluminant};
+use crate::noise::{Fractal, FractalNoise, NoiseKind};
+use crate::texture_library::TextureLibrary;
+use crate::texture_manager::TextureManager;
+use crate::textures::{
+    CheckerTexture, GraniteTexture, ImageTexture, MarbleTexture, NoiseTexture, SolidColor,
+    Texture, WoodTexture,
+};
+use crate::transform::{AnimatedTransform, Keyframe, Transform};
+use crate::utils::{random_double, random_double_range, rotate_y_translation, Float, Interval};
 use crate::vec3::Vec3;
+use crate::wide_bvh::{Aabb4, WideBvh};
+use std::hint::black_box;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 fn spheres_and_cubes() {
     let mut world = HittableList::new();
@@ -39,9 +83,9 @@ fn spheres_and_cubes() {
         for b in -11..11 {
             let choose_mat = random_double();
             let center = Vec3::new(
-                a as f64 + 0.9 * random_double(),
+                a as Float + 0.9 * random_double(),
                 0.2,
-                b as f64 + 0.9 * random_double(),
+                b as Float + 0.9 * random_double(),
             );
 
             if (center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
@@ -87,7 +131,7 @@ fn spheres_and_cubes() {
         material1,
     )));
 
-    let earth_texture = Arc::new(ImageTexture::new("earthmap.jpg"));
+    let earth_texture = Arc::new(ImageTexture::new("earthmap.jpg").unwrap());
     let earth_surface = Material::Lambertian(Lambertian::new_from_texture(earth_texture));
     world.add(Arc::new(Sphere::new(
         Vec3::new(-5.0, 1.0, 0.0),
@@ -96,7 +140,7 @@ fn spheres_and_cubes() {
     )));
 
     //let material3 = Material::Metal(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.01));
-    let moon_texture = Arc::new(ImageTexture::new("moon.png"));
+    let moon_texture = Arc::new(ImageTexture::new("moon.png").unwrap());
     let moon_surface = Material::Lambertian(Lambertian::new_from_texture(moon_texture));
     world.add(Arc::new(Cuboid::new(
         Vec3::new(4.5, 1.0, -0.3),
@@ -104,7 +148,7 @@ fn spheres_and_cubes() {
         moon_surface,
     )));
 
-    let bvh_node = BvhNode::new_from_list(&world);
+    let bvh_node = FlatBvh::new_from_list(&world);
 
     /* Camera */
     let cam: Camera = Camera::new(
@@ -144,7 +188,7 @@ fn checkered_spheres() {
         checker,
     )));
 
-    let bvh_node = BvhNode::new_from_list(&world);
+    let bvh_node = FlatBvh::new_from_list(&world);
 
     /* Camera */
     let cam: Camera = Camera::new(
@@ -168,7 +212,7 @@ fn checkered_spheres() {
 fn earth() {
     let mut world = HittableList::new();
 
-    let earth_texture = Arc::new(ImageTexture::new("earthmap.jpg"));
+    let earth_texture = Arc::new(ImageTexture::new("earthmap.jpg").unwrap());
     let earth_surface = Material::Lambertian(Lambertian::new_from_texture(earth_texture));
 
     world.add(Arc::new(Sphere::new(
@@ -177,7 +221,7 @@ fn earth() {
         earth_surface,
     )));
 
-    let bvh_node = BvhNode::new_from_list(&world);
+    let bvh_node = FlatBvh::new_from_list(&world);
 
     /* Camera */
     let cam: Camera = Camera::new(
@@ -238,7 +282,7 @@ fn quads() {
         lower_teal,
     )));
 
-    let bvh_node = BvhNode::new_from_list(&world);
+    let bvh_node = FlatBvh::new_from_list(&world);
 
     /* Camera */
     let cam: Camera = Camera::new(
@@ -287,7 +331,7 @@ fn light() {
         Vec3::new(0.0, 2.0, 0.0),
         light,
     )));
-    let bvh_node = BvhNode::new_from_list(&world);
+    let bvh_node = FlatBvh::new_from_list(&world);
 
     /* Camera */
     let cam: Camera = Camera::new(
@@ -308,13 +352,15 @@ fn light() {
     cam.render(&bvh_node);
 }
 
-fn cornell_box() {
+fn cornell_box_world() -> HittableList {
     let mut world = HittableList::new();
 
     let red = Material::Lambertian(Lambertian::new(Vec3::new(0.65, 0.05, 0.05)));
     let white = Material::Lambertian(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
     let green = Material::Lambertian(Lambertian::new(Vec3::new(0.12, 0.45, 0.15)));
-    let light = Material::DiffuseLight(DiffuseLight::new(Vec3::new(15.0, 15.0, 15.0)));
+    let light = Material::DiffuseLight(
+        DiffuseLight::new(Vec3::new(15.0, 15.0, 15.0)).with_two_sided(false),
+    );
 
     world.add(Arc::new(Quad::new(
         Vec3::new(555.0, 0.0, 0.0),
@@ -376,7 +422,12 @@ fn cornell_box() {
         Vec3::new(-70.0, 0.0, 40.0),
     )));
 
-    let bvh_node = BvhNode::new_from_list(&world);
+    world
+}
+
+fn cornell_box() {
+    let world = cornell_box_world();
+    let bvh_node = FlatBvh::new_from_list(&world);
 
     /* Camera */
     let cam: Camera = Camera::new(
@@ -397,7 +448,7 @@ fn cornell_box() {
     cam.render(&bvh_node);
 }
 
-fn final_scene() {
+fn final_scene_world() -> HittableList {
     let mut world = HittableList::new();
 
     let ground = Material::Lambertian(Lambertian::new(Vec3::new(0.48, 0.83, 0.53)));
@@ -407,8 +458,8 @@ fn final_scene() {
     for i in 0..boxes_per_side {
         for j in 0..boxes_per_side {
             let w = 100.0;
-            let x0 = -1000.0 + i as f64 * w;
-            let z0 = -1000.0 + j as f64 * w;
+            let x0 = -1000.0 + i as Float * w;
+            let z0 = -1000.0 + j as Float * w;
             let y0 = 0.0;
             let x1 = x0 + w;
             let y1 = random_double_range(1.0, 101.0);
@@ -431,7 +482,7 @@ fn final_scene() {
         light,
     )));
 
-    let glass = Material::Dielectric(Dielectric::new(1.5));
+    let glass = Material::Dielectric(Dielectric::new_dispersive(Dispersion::BK7, 0.0));
     world.add(Arc::new(Sphere::new(
         Vec3::new(260.0, 150.0, 45.0),
         50.0,
@@ -462,7 +513,7 @@ fn final_scene() {
         glass_sphere_size,
         glass.clone(),
     )));
-    let moon_texture = Arc::new(ImageTexture::new("dirt.png"));
+    let moon_texture = Arc::new(ImageTexture::new("dirt.png").unwrap());
     let moon_surface = Material::Lambertian(Lambertian::new_from_texture(moon_texture));
     let moon_cube_center = Vec3::new(220.0, 280.0, 300.0);
     let moon_cube = Arc::new(Cube::new(
@@ -476,7 +527,7 @@ fn final_scene() {
         rotate_y_translation(moon_cube_center, rotation_angle),
     )));
 
-    let earth_texture = Arc::new(ImageTexture::new("earthmap.jpg"));
+    let earth_texture = Arc::new(ImageTexture::new("earthmap.jpg").unwrap());
     let earth_surface = Material::Lambertian(Lambertian::new_from_texture(earth_texture));
     world.add(Arc::new(Sphere::new(
         Vec3::new(400.0, 200.0, 400.0),
@@ -497,7 +548,7 @@ fn final_scene() {
 
     world.add(Arc::new(Translate::new(
         Arc::new(RotateY::new(
-            Arc::new(BvhNode::new_from_list(&spheres)),
+            Arc::new(FlatBvh::new_from_list(&spheres)),
             15.0,
         )),
         Vec3::new(-100.0, 270.0, 395.0),
@@ -527,8 +578,14 @@ fn final_scene() {
         Arc::new(spheres),
         Vec3::new(0.0, 0.0, -100.0),
     )));
-    
-    let bvh_node = BvhNode::new_from_list(&world);
+
+    world
+}
+
+fn final_scene() {
+    let world = final_scene_world();
+
+    let bvh_node = FlatBvh::new_from_list(&world);
 
     /* Camera */
     let cam: Camera = Camera::new(
@@ -549,6 +606,1444 @@ fn final_scene() {
     cam.render(&bvh_node);
 }
 
+fn instanced_spheres() {
+    let mut world = HittableList::new();
+
+    let ground = Material::Lambertian(Lambertian::new(Vec3::new(0.48, 0.83, 0.53)));
+    world.add(Arc::new(Quad::new(
+        Vec3::new(-10000.0, 0.0, -10000.0),
+        Vec3::new(20000.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 20000.0),
+        ground,
+    )));
+
+    // Build the sphere cluster once and share it between every instance.
+    let white = Material::Lambertian(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+    let sphere_count = 1000;
+    let mut spheres = HittableList::new();
+    for _i in 0..sphere_count {
+        spheres.add(Arc::new(Sphere::new(
+            Vec3::random_range(0.0, 165.0),
+            10.0,
+            white.clone(),
+        )));
+    }
+    let cluster: Arc<dyn Hittable> = Arc::new(FlatBvh::new_from_list(&spheres));
+
+    // 1000 instances of 1000 spheres each.
+    let instances_per_side = 10;
+    let mut instances = HittableList::new();
+    for i in 0..instances_per_side {
+        for j in 0..instances_per_side {
+            for k in 0..instances_per_side {
+                let offset = Vec3::new(i as Float, j as Float, k as Float) * 250.0;
+                let scale = random_double_range(0.5, 1.0);
+                let transform = Transform::new_translation(offset)
+                    * Transform::new_rotation_y(random_double_range(0.0, 360.0))
+                    * Transform::new_scale(Vec3::new(scale, scale, scale));
+
+                let instance = if random_double() < 0.2 {
+                    let albedo = Vec3::random() * Vec3::random();
+                    Instance::new_with_material(
+                        cluster.clone(),
+                        transform,
+                        Arc::new(Material::Lambertian(Lambertian::new(albedo))),
+                    )
+                } else {
+                    Instance::new(cluster.clone(), transform)
+                };
+                instances.add(Arc::new(instance));
+            }
+        }
+    }
+    world.add(Arc::new(FlatBvh::new_from_list(&instances)));
+
+    let light = Material::DiffuseLight(DiffuseLight::new(Vec3::new(7.0, 7.0, 7.0)));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(1250.0, 5000.0, -1000.0),
+        1500.0,
+        light,
+    )));
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        200,
+        40,
+        40.0,
+        Vec3::new(-1500.0, 1800.0, -2000.0),
+        Vec3::new(1250.0, 1000.0, 1250.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out8.ppm",
+        Vec3::new(0.70, 0.80, 1.00),
+    );
+
+    cam.render(&bvh_node);
+}
+
+fn motion_blur() {
+    let mut world = HittableList::new();
+
+    let checker = Material::Lambertian(Lambertian::new_from_texture(Arc::new(
+        CheckerTexture::new_from_rgb(0.32, Vec3::new(0.2, 0.3, 0.1), Vec3::new(0.9, 0.9, 0.9)),
+    )));
+
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        checker,
+    )));
+
+    // Bouncing spheres moving straight up during the shutter interval.
+    for a in -6..6 {
+        for b in -6..6 {
+            let center = Vec3::new(
+                a as Float + 0.9 * random_double(),
+                0.2,
+                b as Float + 0.9 * random_double(),
+            );
+            let center2 = center + Vec3::new(0.0, random_double_range(0.0, 0.5), 0.0);
+            let albedo = Vec3::random() * Vec3::random();
+            world.add(Arc::new(Sphere::new_moving(
+                center,
+                center2,
+                0.2,
+                Material::Lambertian(Lambertian::new(albedo)),
+            )));
+        }
+    }
+
+    // A cube spinning and sliding between keyframes.
+    let moon_texture = Arc::new(ImageTexture::new("moon.png").unwrap());
+    let moon_surface = Material::Lambertian(Lambertian::new_from_texture(moon_texture));
+    let cube: Arc<dyn Hittable> = Arc::new(Cube::new(Vec3::new(0.0, 0.0, 0.0), 1.5, moon_surface));
+    let one = Vec3::new(1.0, 1.0, 1.0);
+    let animation = AnimatedTransform::new(vec![
+        Keyframe::new(0.0, Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 0.0), one),
+        Keyframe::new(
+            0.5,
+            Vec3::new(0.3, 1.2, 0.0),
+            Vec3::new(0.0, 30.0, 10.0),
+            one,
+        ),
+        Keyframe::new(
+            1.0,
+            Vec3::new(0.6, 1.0, 0.0),
+            Vec3::new(0.0, 60.0, 0.0),
+            one,
+        ),
+    ]);
+    world.add(Arc::new(AnimatedInstance::new(cube, animation)));
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let mut cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        200,
+        20,
+        20.0,
+        Vec3::new(13.0, 2.0, 3.0),
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out9.ppm",
+        Vec3::new(0.70, 0.80, 1.00),
+    );
+    cam.set_shutter(0.0, 1.0);
+
+    cam.render(&bvh_node);
+}
+
+fn animated_instances() {
+    // Two-level BVH: the sphere cluster has its own tree that never changes, and only the top
+    // level over the instances is refit (or rebuilt when refitting degraded it) every frame.
+    let white = Material::Lambertian(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+    let mut spheres = HittableList::new();
+    for _i in 0..200 {
+        spheres.add(Arc::new(Sphere::new(
+            Vec3::random_range(-1.0, 1.0),
+            0.15,
+            white.clone(),
+        )));
+    }
+    let cluster: Arc<dyn Hittable> = Arc::new(FlatBvh::new_from_list(&spheres));
+
+    let instance_count = 100;
+    let orbits: Vec<(Float, Float, Float)> = (0..instance_count)
+        .map(|_| {
+            (
+                random_double_range(3.0, 12.0),  // radius
+                random_double_range(0.0, 360.0), // starting angle
+                random_double_range(-2.0, 2.0),  // height
+            )
+        })
+        .collect();
+    let pose = |orbit: &(Float, Float, Float), frame: usize| {
+        let (radius, start_angle, height) = *orbit;
+        let angle = start_angle + 120.0 * frame as Float / radius;
+        Transform::new_rotation_y(angle)
+            * Transform::new_translation(Vec3::new(radius, height, 0.0))
+    };
+
+    let mut instances = HittableList::new();
+    for orbit in &orbits {
+        instances.add(Arc::new(Instance::new(cluster.clone(), pose(orbit, 0))));
+    }
+    let mut top_level = FlatBvh::new_from_list(&instances);
+
+    let frame_count = 8;
+    for frame in 0..frame_count {
+        if frame > 0 {
+            for (index, orbit) in orbits.iter().enumerate() {
+                let instance = Instance::new(cluster.clone(), pose(orbit, frame));
+                top_level.set_object(index, Arc::new(instance));
+            }
+            let rebuilt = top_level.update();
+            println!(
+                "Frame {}: {} top level BVH, SAH cost {:.2}",
+                frame,
+                if rebuilt { "rebuilt" } else { "refit" },
+                top_level.sah_cost(),
+            );
+        }
+
+        /* Camera */
+        let cam: Camera = Camera::new(
+            16.0 / 9.0,
+            600.0,
+            50,
+            20,
+            40.0,
+            Vec3::new(0.0, 18.0, 24.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            0.0,
+            10.0,
+            &format!("out11_{}.ppm", frame),
+            Vec3::new(0.70, 0.80, 1.00),
+        );
+
+        cam.render(&top_level);
+    }
+}
+
+fn bvh_benchmark() {
+    // Compares building and tracing the final scene with the recursive and the flattened BVH.
+    let world = final_scene_world();
+
+    let start = Instant::now();
+    let tree = BvhNode::new_from_list(&world);
+    let tree_build_time = start.elapsed();
+
+    let start = Instant::now();
+    let (flat, stats) = FlatBvh::new_from_list_with_stats(&world);
+    let flat_build_time = start.elapsed();
+
+    println!(
+        "Build: recursive {:.2}ms, flat {:.2}ms",
+        tree_build_time.as_secs_f64() * 1000.0,
+        flat_build_time.as_secs_f64() * 1000.0,
+    );
+    println!("BVH: {}", stats);
+
+    // Camera rays through the view of the final scene, and bounce-like rays starting inside it.
+    let ray_count = 1_000_000;
+    let look_from = Vec3::new(478.0, 278.0, -600.0);
+    let camera_rays: Vec<Ray> = (0..ray_count)
+        .map(|_| {
+            let target = Vec3::new(
+                random_double_range(-200.0, 800.0),
+                random_double_range(-200.0, 800.0),
+                0.0,
+            );
+            Ray::new(look_from, target - look_from, 0.0)
+        })
+        .collect();
+    let bounce_rays: Vec<Ray> = (0..ray_count)
+        .map(|_| {
+            Ray::new(
+                Vec3::random_range(0.0, 600.0),
+                Vec3::random_unit_vector(),
+                0.0,
+            )
+        })
+        .collect();
+
+    // The fog of the final scene scatters at random, so hit counts differ slightly between runs.
+    let trace = |bvh: &dyn Hittable, rays: &[Ray]| {
+        let start = Instant::now();
+        let ray_t = Interval::new(0.001, Float::INFINITY);
+        let hits = rays
+            .iter()
+            .filter(|r| bvh.hit(**r, ray_t).is_some())
+            .count();
+        let mrays_per_sec = rays.len() as f64 / start.elapsed().as_secs_f64() / 1e6;
+        (mrays_per_sec, hits)
+    };
+
+    let wide = WideBvh::new_from_list(&world);
+
+    for (name, rays) in [("camera", &camera_rays), ("bounce", &bounce_rays)] {
+        let (tree_speed, tree_hits) = trace(&tree, rays);
+        let (flat_speed, flat_hits) = trace(&flat, rays);
+        let (wide_speed, wide_hits) = trace(&wide, rays);
+        println!(
+            "{} rays: recursive {:.2} Mrays/s ({} hits), flat {:.2} Mrays/s ({} hits), wide {:.2} Mrays/s ({} hits)",
+            name, tree_speed, tree_hits, flat_speed, flat_hits, wide_speed, wide_hits,
+        );
+    }
+}
+
+fn aabb_benchmark() {
+    // Compares the speed of `Aabb::hit` with the scalar and SIMD paths of `Aabb4::hit` on random
+    // boxes and rays. The tests in wide_bvh.rs check that they agree.
+    let test_count = 1_000_000;
+    let boxes: Vec<Aabb> = (0..test_count * 4)
+        .map(|_| {
+            let corner = Vec3::random_range(-10.0, 10.0);
+            Aabb::new_from_vec3(corner, corner + Vec3::random_range(0.0, 5.0))
+        })
+        .collect();
+    let packed: Vec<Aabb4> = boxes.chunks(4).map(Aabb4::new).collect();
+    let rays: Vec<Ray> = (0..test_count)
+        .map(|i| {
+            // Every eighth ray is parallel to an axis plane to cover infinite inverse directions.
+            let mut direction = Vec3::random_unit_vector();
+            if i % 8 == 0 {
+                direction = Vec3::new(direction.x(), 0.0, direction.z());
+            }
+            Ray::new(Vec3::random_range(-15.0, 15.0), direction, 0.0)
+        })
+        .collect();
+    let inverse = |r: &Ray| {
+        Vec3::new(
+            1.0 / r.direction.x(),
+            1.0 / r.direction.y(),
+            1.0 / r.direction.z(),
+        )
+    };
+    let ray_t = Interval::new(0.001, Float::INFINITY);
+
+    let start = Instant::now();
+    let mut hits = 0;
+    for (r, group) in rays.iter().zip(boxes.chunks(4)) {
+        for bbox in group {
+            hits += black_box(bbox.hit(*r, ray_t)) as usize;
+        }
+    }
+    let single_time = start.elapsed();
+
+    let start = Instant::now();
+    for (r, group) in rays.iter().zip(&packed) {
+        let (mask, _) = black_box(group.hit_scalar(r.origin, inverse(r), ray_t));
+        hits += mask.count_ones() as usize;
+    }
+    let scalar_time = start.elapsed();
+
+    let start = Instant::now();
+    for (r, group) in rays.iter().zip(&packed) {
+        let (mask, _) = black_box(group.hit(r.origin, inverse(r), ray_t));
+        hits += mask.count_ones() as usize;
+    }
+    let simd_time = start.elapsed();
+
+    let per_box = |time: Duration| time.as_secs_f64() * 1e9 / (test_count * 4) as f64;
+    println!(
+        "Box test: Aabb::hit {:.2}ns, Aabb4 scalar {:.2}ns, Aabb4 SIMD {:.2}ns per box ({} hits)",
+        per_box(single_time),
+        per_box(scalar_time),
+        per_box(simd_time),
+        hits,
+    );
+}
+
+fn precision_benchmark() {
+    // Renders small versions of the Cornell box and the final scene and reports the time and
+    // BVH memory. Run once as is and once with `--features f32` to compare the two precisions.
+    println!(
+        "Precision: {}, Vec3 {} bytes, Ray {} bytes, Aabb {} bytes",
+        std::any::type_name::<Float>(),
+        size_of::<Vec3>(),
+        size_of::<Ray>(),
+        size_of::<Aabb>(),
+    );
+
+    let scenes = [
+        (
+            "cornell_box",
+            cornell_box_world(),
+            Vec3::new(278.0, 278.0, -800.0),
+            Vec3::new(278.0, 278.0, 0.0),
+        ),
+        (
+            "final_scene",
+            final_scene_world(),
+            Vec3::new(478.0, 278.0, -600.0),
+            Vec3::new(278.0, 278.0, 0.0),
+        ),
+    ];
+
+    for (name, world, look_from, look_at) in scenes {
+        let bvh_node = FlatBvh::new_from_list(&world);
+        let cam: Camera = Camera::new(
+            1.0,
+            300.0,
+            50,
+            50,
+            40.0,
+            look_from,
+            look_at,
+            Vec3::new(0.0, 1.0, 0.0),
+            0.0,
+            10.0,
+            &format!("precision_{}_{}.ppm", name, std::any::type_name::<Float>()),
+            Vec3::new(0.0, 0.0, 0.0),
+        );
+
+        let start = Instant::now();
+        cam.render(&bvh_node);
+        println!(
+            "{}: {:.2}s, BVH {} bytes",
+            name,
+            start.elapsed().as_secs_f64(),
+            bvh_node.memory_size(),
+        );
+    }
+}
+
+fn conductors() {
+    let mut world = HittableList::new();
+
+    let checker = Material::Lambertian(Lambertian::new_from_texture(Arc::new(
+        CheckerTexture::new_from_rgb(0.32, Vec3::new(0.2, 0.3, 0.1), Vec3::new(0.9, 0.9, 0.9)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        checker,
+    )));
+
+    // Each metal from smooth in front to rough at the back.
+    for (i, (name, _, _)) in Conductor::PRESETS.iter().enumerate() {
+        for (j, roughness) in [0.0, 0.2, 0.5].into_iter().enumerate() {
+            let metal = Conductor::new_preset(name, roughness, roughness).unwrap();
+            world.add(Arc::new(Sphere::new(
+                Vec3::new(-3.3 + 2.2 * i as Float, 1.0, 2.2 - 2.2 * j as Float),
+                1.0,
+                Material::Conductor(metal),
+            )));
+        }
+    }
+
+    // Brushed aluminum, rough along one tangent and nearly smooth along the other.
+    let brushed = Conductor::new_preset("aluminum", 0.5, 0.05).unwrap();
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(5.5, 1.0, 0.0),
+        1.0,
+        Material::Conductor(brushed),
+    )));
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        500,
+        50,
+        30.0,
+        Vec3::new(0.0, 6.0, 14.0),
+        Vec3::new(1.0, 0.5, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out15.ppm",
+        Vec3::new(0.70, 0.80, 1.00),
+    );
+
+    cam.render(&bvh_node);
+}
+
+fn glass() {
+    let mut world = HittableList::new();
+
+    let checker = Material::Lambertian(Lambertian::new_from_texture(Arc::new(
+        CheckerTexture::new_from_rgb(0.32, Vec3::new(0.2, 0.3, 0.1), Vec3::new(0.9, 0.9, 0.9)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        checker,
+    )));
+
+    // Smooth glass on the left, getting rougher to the right.
+    for (i, roughness) in [0.0, 0.1, 0.3, 0.6].into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-3.3 + 2.2 * i as Float, 1.0, 1.2),
+            1.0,
+            Material::Dielectric(Dielectric::new_rough(1.5, roughness)),
+        )));
+    }
+
+    // Tinted glass, which gets darker where light travels further through it.
+    world.add(Arc::new(Cuboid::new(
+        Vec3::new(-3.0, 0.75, -2.5),
+        Vec3::new(1.5, 1.5, 1.5),
+        Material::Dielectric(Dielectric::new_tinted(
+            1.5,
+            0.0,
+            Vec3::new(0.2, 0.6, 0.9),
+            1.0,
+        )),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, 0.75, -2.5),
+        0.75,
+        Material::Dielectric(Dielectric::new_tinted(
+            1.5,
+            0.2,
+            Vec3::new(0.9, 0.4, 0.2),
+            1.0,
+        )),
+    )));
+
+    // A window pane, which only shows a faint reflection.
+    world.add(Arc::new(Quad::new(
+        Vec3::new(1.8, 0.0, -3.0),
+        Vec3::new(2.5, 0.0, 0.0),
+        Vec3::new(0.0, 2.0, 0.0),
+        Material::ThinDielectric(ThinDielectric::new(1.5)),
+    )));
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        500,
+        50,
+        30.0,
+        Vec3::new(0.0, 8.0, 14.0),
+        Vec3::new(0.0, 0.5, -0.8),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out16.ppm",
+        Vec3::new(0.70, 0.80, 1.00),
+    );
+
+    cam.render(&bvh_node);
+}
+
+fn principled() {
+    use PrincipledInput::*;
+
+    let mut world = HittableList::new();
+
+    let checker = Material::Lambertian(Lambertian::new_from_texture(Arc::new(
+        CheckerTexture::new_from_rgb(0.32, Vec3::new(0.2, 0.3, 0.1), Vec3::new(0.9, 0.9, 0.9)),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        checker,
+    )));
+
+    // Front row goes from plastic to metal, middle row from smooth to rough.
+    for i in 0..5 {
+        let f = i as Float / 4.0;
+        let metal = Principled::new(Vec3::new(0.9, 0.6, 0.2))
+            .with_value(Metallic, f)
+            .with_value(Roughness, 0.2);
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-4.4 + 2.2 * i as Float, 1.0, 2.2),
+            1.0,
+            Material::Principled(metal),
+        )));
+
+        let plastic = Principled::new(Vec3::new(0.1, 0.2, 0.8)).with_value(Roughness, f);
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-4.4 + 2.2 * i as Float, 1.0, 0.0),
+            1.0,
+            Material::Principled(plastic),
+        )));
+    }
+
+    // Back row shows the remaining layers.
+    let back_row = [
+        Principled::new_from_texture(Arc::new(ImageTexture::new("earthmap.jpg").unwrap()))
+            .with_value(Roughness, 0.6)
+            .with_value(Clearcoat, 1.0),
+        Principled::new(Vec3::new(0.3, 0.05, 0.1))
+            .with_value(Roughness, 1.0)
+            .with_value(Specular, 0.0)
+            .with_value(Sheen, 1.0),
+        Principled::new(Vec3::new(0.5, 0.9, 0.6))
+            .with_value(Roughness, 0.1)
+            .with_value(Transmission, 1.0),
+        Principled::new(Vec3::new(0.9, 0.2, 0.2))
+            .with_value(Specular, 1.0)
+            .with_value(SpecularTint, 1.0),
+        Principled::new(Vec3::new(0.1, 0.1, 0.1)).with_color(Emission, Vec3::new(4.0, 2.0, 0.5)),
+    ];
+    for (i, material) in back_row.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-4.4 + 2.2 * i as Float, 1.0, -2.2),
+            1.0,
+            Material::Principled(material),
+        )));
+    }
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        500,
+        50,
+        30.0,
+        Vec3::new(0.0, 9.0, 16.0),
+        Vec3::new(0.0, 0.5, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out18.ppm",
+        Vec3::new(0.70, 0.80, 1.00),
+    );
+
+    cam.render(&bvh_node);
+}
+
+fn spectral_final_scene() {
+    // The final scene traced at sampled wavelengths, so light through the glass spheres
+    // spreads into colors.
+    let world = final_scene_world();
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let mut cam: Camera = Camera::new(
+        1.0,
+        1000.0,
+        2500,
+        40,
+        40.0,
+        Vec3::new(478.0, 278.0, -600.0),
+        Vec3::new(278.0, 278.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out19.ppm",
+        Vec3::new(0.0, 0.0, 0.0),
+    );
+    cam.set_spectral(true);
+
+    cam.render(&bvh_node);
+}
+
+fn light_spectra() {
+    // White spheres under lights of different spectra, in a room measured in metres.
+    let mut world = HittableList::new();
+
+    let white = Material::Lambertian(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+    world.add(Arc::new(Quad::new(
+        Vec3::new(-5.0, 0.0, -3.0),
+        Vec3::new(10.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 6.0),
+        white.clone(),
+    )));
+    world.add(Arc::new(Quad::new(
+        Vec3::new(-5.0, 0.0, -3.0),
+        Vec3::new(10.0, 0.0, 0.0),
+        Vec3::new(0.0, 4.0, 0.0),
+        white.clone(),
+    )));
+
+    let lights = [
+        DiffuseLight::new_from_kelvin(1900.0, 8.0), // Candle flame
+        DiffuseLight::new_from_spectrum(Spectrum::Illuminant(StandardIlluminant::A), 8.0),
+        DiffuseLight::new_from_spectrum(Spectrum::Illuminant(StandardIlluminant::F11), 8.0),
+        DiffuseLight::new_from_kelvin(10000.0, 8.0), // Clear blue sky
+        DiffuseLight::new_from_power(Spectrum::Illuminant(StandardIlluminant::D65), 0.4),
+    ];
+    for (i, light) in lights.into_iter().enumerate() {
+        let x = -3.6 + 1.8 * i as Float;
+        world.add(Arc::new(Quad::new(
+            Vec3::new(x - 0.6, 2.5, -0.6),
+            Vec3::new(1.2, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.2),
+            Material::DiffuseLight(light),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(x, 0.5, 0.0),
+            0.5,
+            white.clone(),
+        )));
+    }
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let mut cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        500,
+        50,
+        40.0,
+        Vec3::new(0.0, 1.5, 9.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out20.ppm",
+        Vec3::new(0.0, 0.0, 0.0),
+    );
+    cam.set_spectral(true);
+
+    cam.render(&bvh_node);
+}
+
+fn emission_profiles() {
+    // Ceiling lights that only shine downwards, each spreading its light differently.
+    let mut world = HittableList::new();
+
+    let white = Material::Lambertian(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+    world.add(Arc::new(Quad::new(
+        Vec3::new(-5.0, 0.0, -3.0),
+        Vec3::new(10.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 6.0),
+        white.clone(),
+    )));
+    world.add(Arc::new(Quad::new(
+        Vec3::new(-5.0, 0.0, -1.0),
+        Vec3::new(10.0, 0.0, 0.0),
+        Vec3::new(0.0, 4.0, 0.0),
+        white.clone(),
+    )));
+
+    let manager = TextureManager::new().with_search_path("ies");
+    let ies = Arc::new(IesProfile::load("spot.ies", &manager).unwrap());
+    let profiles = [
+        EmissionProfile::Diffuse,
+        EmissionProfile::new_spot(15.0, 25.0),
+        EmissionProfile::CosinePower(8.0),
+        EmissionProfile::Ies(ies),
+    ];
+    for (i, profile) in profiles.into_iter().enumerate() {
+        let x = -3.0 + 2.0 * i as Float;
+        let light = DiffuseLight::new(Vec3::new(40.0, 40.0, 40.0))
+            .with_two_sided(false)
+            .with_profile(profile);
+        world.add(Arc::new(Quad::new(
+            Vec3::new(x - 0.3, 2.5, 0.2),
+            Vec3::new(0.6, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.6),
+            Material::DiffuseLight(light),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(x, 0.3, 0.8),
+            0.3,
+            white.clone(),
+        )));
+    }
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        500,
+        50,
+        40.0,
+        Vec3::new(0.0, 1.5, 9.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out21.ppm",
+        Vec3::new(0.0, 0.0, 0.0),
+    );
+
+    cam.render(&bvh_node);
+}
+
+fn delta_lights() {
+    // Spheres lit by a soft-edged sun, a spotlight and a point light, none of which are part of
+    // the geometry.
+    let mut world = HittableList::new();
+
+    let checker = Material::Lambertian(Lambertian::new_from_texture(Arc::new(
+        CheckerTexture::new_from_rgb(0.5, Vec3::new(0.2, 0.2, 0.2), Vec3::new(0.8, 0.8, 0.8)),
+    )));
+    world.add(Arc::new(Quad::new(
+        Vec3::new(-10.0, 0.0, -10.0),
+        Vec3::new(20.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 20.0),
+        checker,
+    )));
+
+    let materials = [
+        Material::Lambertian(Lambertian::new(Vec3::new(0.7, 0.2, 0.2))),
+        Material::Conductor(Conductor::new_preset("gold", 0.3, 0.3).unwrap()),
+        Material::Dielectric(Dielectric::new_rough(1.5, 0.2)),
+        Material::Principled(
+            Principled::new(Vec3::new(0.1, 0.2, 0.6)).with_value(PrincipledInput::Clearcoat, 1.0),
+        ),
+    ];
+    for (i, material) in materials.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-3.0 + 2.0 * i as Float, 0.7, 0.0),
+            0.7,
+            material,
+        )));
+    }
+
+    let mut lights = LightList::new();
+    lights.add(Arc::new(
+        DirectionalLight::new(Vec3::new(-1.0, 2.0, 1.0), Vec3::new(2.0, 1.9, 1.7))
+            .with_angular_diameter(4.0),
+    ));
+    lights.add(Arc::new(SpotLight::new(
+        Vec3::new(1.0, 4.0, 3.0),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(30.0, 20.0, 10.0),
+        15.0,
+        25.0,
+    )));
+    lights.add(Arc::new(PointLight::new(
+        Vec3::new(-2.0, 1.0, 2.0),
+        Vec3::new(1.0, 2.0, 4.0),
+    )));
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        200,
+        50,
+        30.0,
+        Vec3::new(0.0, 4.0, 12.0),
+        Vec3::new(0.0, 0.5, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out22.ppm",
+        Vec3::new(0.1, 0.12, 0.15),
+    );
+
+    cam.render_with_lights(&bvh_node, &lights);
+}
+
+fn environment_light() {
+    // Spheres lit only by an HDR sky, whose sun casts sharp shadows.
+    let mut world = HittableList::new();
+
+    let ground = Material::Lambertian(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+    world.add(Arc::new(Quad::new(
+        Vec3::new(-20.0, 0.0, -20.0),
+        Vec3::new(40.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 40.0),
+        ground,
+    )));
+
+    let materials = [
+        Material::Lambertian(Lambertian::new(Vec3::new(0.8, 0.8, 0.8))),
+        Material::Conductor(Conductor::new_preset("gold", 0.2, 0.2).unwrap()),
+        Material::Dielectric(Dielectric::new(1.5)),
+        Material::Principled(
+            Principled::new(Vec3::new(0.6, 0.1, 0.1)).with_value(PrincipledInput::Clearcoat, 1.0),
+        ),
+    ];
+    for (i, material) in materials.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-3.0 + 2.0 * i as Float, 0.7, 0.0),
+            0.7,
+            material,
+        )));
+    }
+
+    let mut lights = LightList::new();
+    lights.set_environment(Arc::new(
+        EnvironmentLight::new("sky.hdr")
+            .unwrap()
+            .with_rotation(-90.0),
+    ));
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        100,
+        50,
+        30.0,
+        Vec3::new(0.0, 3.0, 12.0),
+        Vec3::new(0.0, 0.7, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out23.ppm",
+        Vec3::new(0.0, 0.0, 0.0),
+    );
+
+    cam.render_with_lights(&bvh_node, &lights);
+}
+
+fn sky() {
+    // Blocks on a plaza in the late afternoon of midsummer, lit by a procedural sky and sun.
+    let mut world = HittableList::new();
+
+    let ground = Material::Lambertian(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+    world.add(Arc::new(Quad::new(
+        Vec3::new(-50.0, 0.0, -50.0),
+        Vec3::new(100.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 100.0),
+        ground,
+    )));
+
+    let white = Material::Lambertian(Lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
+    world.add(Arc::new(Cuboid::new_from_corners(
+        Vec3::new(-4.0, 0.0, -3.0),
+        Vec3::new(-1.0, 4.0, -1.0),
+        white.clone(),
+    )));
+    world.add(Arc::new(Cuboid::new_from_corners(
+        Vec3::new(1.0, 0.0, -4.0),
+        Vec3::new(4.0, 2.0, -2.0),
+        white,
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, 0.8, 1.0),
+        0.8,
+        Material::Conductor(Conductor::new_preset("aluminum", 0.1, 0.1).unwrap()),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Vec3::new(2.5, 0.6, 1.5),
+        0.6,
+        Material::Dielectric(Dielectric::new(1.5)),
+    )));
+
+    // Radiance is in nits, far too bright for the camera as it is.
+    let mut lights = LightList::new();
+    lights.set_environment(Arc::new(
+        Sky::new_from_date(172.0, 17.0, 48.0, 3.0).with_intensity(1.0 / 20000.0),
+    ));
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        100,
+        50,
+        50.0,
+        Vec3::new(0.0, 1.5, 10.0),
+        Vec3::new(0.0, 2.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out24.ppm",
+        Vec3::new(0.0, 0.0, 0.0),
+    );
+
+    cam.render_with_lights(&bvh_node, &lights);
+}
+
+fn backgrounds() {
+    // Spheres lit by the gradient sky from the book, in front of a photo that only the camera
+    // sees. The mirror and the glass show the sky rather than the photo.
+    let mut world = HittableList::new();
+
+    let checker = Material::Lambertian(Lambertian::new_from_texture(Arc::new(
+        CheckerTexture::new_from_rgb(0.5, Vec3::new(0.2, 0.3, 0.1), Vec3::new(0.9, 0.9, 0.9)),
+    )));
+    world.add(Arc::new(Quad::new(
+        Vec3::new(-4.0, 0.0, -3.0),
+        Vec3::new(8.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 6.0),
+        checker,
+    )));
+
+    let materials = [
+        Material::Lambertian(Lambertian::new(Vec3::new(0.7, 0.3, 0.3))),
+        Material::Metal(Metal::new(Vec3::new(0.8, 0.8, 0.8), 0.0)),
+        Material::Dielectric(Dielectric::new(1.5)),
+    ];
+    for (i, material) in materials.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-2.2 + 2.2 * i as Float, 1.0, 0.0),
+            1.0,
+            material,
+        )));
+    }
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let mut cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        100,
+        50,
+        40.0,
+        Vec3::new(0.0, 2.0, 9.0),
+        Vec3::new(0.0, 0.8, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out25.ppm",
+        Vec3::new(0.0, 0.0, 0.0),
+    );
+    cam.set_background(Arc::new(GradientBackground::new_sky()));
+    cam.set_camera_background(Arc::new(ScreenTexture::new(Arc::new(
+        ImageTexture::new("earthmap.jpg").unwrap(),
+    ))));
+
+    cam.render(&bvh_node);
+}
+
+fn noise_textures() {
+    // Top row: each kind of noise with a different fractal. Bottom row: marble, wood, granite,
+    // and finer, rougher fBm from more octaves and a higher gain.
+    let mut world = HittableList::new();
+
+    let textures: [Arc<dyn Texture>; 8] = [
+        Arc::new(NoiseTexture::new(
+            FractalNoise::new(NoiseKind::Perlin, Fractal::Fbm),
+            4.0,
+        )),
+        Arc::new(NoiseTexture::new(
+            FractalNoise::new(NoiseKind::ImprovedPerlin, Fractal::Turbulence),
+            4.0,
+        )),
+        Arc::new(
+            NoiseTexture::new(FractalNoise::new(NoiseKind::Simplex, Fractal::Ridged), 2.0)
+                .with_colors(Vec3::new(0.05, 0.1, 0.3), Vec3::new(0.9, 0.95, 1.0)),
+        ),
+        Arc::new(NoiseTexture::new(
+            FractalNoise::new(NoiseKind::Worley, Fractal::Fbm).with_octaves(3),
+            4.0,
+        )),
+        Arc::new(MarbleTexture::new(4.0)),
+        Arc::new(WoodTexture::new(6.0)),
+        Arc::new(GraniteTexture::new(20.0)),
+        Arc::new(
+            NoiseTexture::new(
+                FractalNoise::new(NoiseKind::Simplex, Fractal::Fbm)
+                    .with_octaves(10)
+                    .with_lacunarity(2.5)
+                    .with_gain(0.7),
+                2.0,
+            )
+            .with_colors(Vec3::new(0.3, 0.1, 0.0), Vec3::new(1.0, 0.8, 0.3)),
+        ),
+    ];
+    for (i, texture) in textures.into_iter().enumerate() {
+        let (column, row) = ((i % 4) as Float, (i / 4) as Float);
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-3.3 + 2.2 * column, 1.1 - 2.2 * row, 0.0),
+            1.0,
+            Material::Lambertian(Lambertian::new_from_texture(texture)),
+        )));
+    }
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let mut cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        100,
+        50,
+        40.0,
+        Vec3::new(0.0, 0.0, 12.0),
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out26.ppm",
+        Vec3::new(0.0, 0.0, 0.0),
+    );
+    cam.set_background(Arc::new(GradientBackground::new_sky()));
+
+    cam.render(&bvh_node);
+}
+
+fn texture_filtering() {
+    // Floors running off to the horizon with a tiled map on them, filtered from left to right
+    // by nearest pixel, trilinear and EWA. The first shimmers into noise in the distance, the
+    // second blurs, and the third stays sharp for longer. The spheres show the wrap modes, with
+    // the map scaled down to repeat, mirror and clamp around them, and bicubic filtering.
+    let mut world = HittableList::new();
+
+    let filters = [FilterMode::Nearest, FilterMode::Trilinear, FilterMode::Ewa];
+    for (i, filter) in filters.into_iter().enumerate() {
+        let texture = ImageTexture::new("earthmap.jpg")
+            .unwrap()
+            .with_filter(filter)
+            .with_uv_scale(1.0, 100.0);
+        world.add(Arc::new(Quad::new(
+            Vec3::new(-3.3 + 2.2 * i as Float, 0.0, 4.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -400.0),
+            Material::Lambertian(Lambertian::new_from_texture(Arc::new(texture))),
+        )));
+    }
+
+    let wraps = [WrapMode::Repeat, WrapMode::Mirror, WrapMode::Clamp];
+    for (i, wrap) in wraps.into_iter().enumerate() {
+        let texture = ImageTexture::new("earthmap.jpg")
+            .unwrap()
+            .with_wrap(wrap)
+            .with_filter(FilterMode::Bicubic)
+            .with_uv_scale(2.0, 2.0)
+            .with_uv_offset(-0.5, -0.5)
+            .with_uv_rotation(20.0);
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-2.2 + 2.2 * i as Float, 1.8, -4.0),
+            0.8,
+            Material::Lambertian(Lambertian::new_from_texture(Arc::new(texture))),
+        )));
+    }
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let mut cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        16,
+        50,
+        40.0,
+        Vec3::new(0.0, 1.2, 6.0),
+        Vec3::new(0.0, 0.9, -10.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out27.ppm",
+        Vec3::new(0.0, 0.0, 0.0),
+    );
+    cam.set_background(Arc::new(GradientBackground::new_sky()));
+
+    cam.render(&bvh_node);
+}
+
+fn image_color_spaces() {
+    // The same map loaded as color on the left and as data on the right. Data is used as
+    // stored, so the sRGB encoded map comes out washed out on the right, which is what a color
+    // image marked as data looks like. The high dynamic range sky in the middle is linear
+    // either way.
+    let mut world = HittableList::new();
+
+    let textures = [
+        ImageTexture::new("earthmap.jpg").unwrap(),
+        ImageTexture::new("sky.hdr").unwrap(),
+        ImageTexture::new_data("earthmap.jpg").unwrap(),
+    ];
+    for (i, texture) in textures.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-2.2 + 2.2 * i as Float, 1.0, 0.0),
+            1.0,
+            Material::Lambertian(Lambertian::new_from_texture(Arc::new(texture))),
+        )));
+    }
+    world.add(Arc::new(Quad::new(
+        Vec3::new(-6.0, 0.0, 4.0),
+        Vec3::new(12.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -8.0),
+        Material::Lambertian(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+    )));
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let mut cam: Camera = Camera::new(
+        16.0 / 9.0,
+        800.0,
+        64,
+        50,
+        35.0,
+        Vec3::new(0.0, 3.0, 9.0),
+        Vec3::new(0.0, 0.8, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out28.ppm",
+        Vec3::new(0.0, 0.0, 0.0),
+    );
+    cam.set_background(Arc::new(GradientBackground::new_sky()));
+
+    cam.render(&bvh_node);
+}
+
+fn surface_detail() {
+    // A brick wall drawn only into the shading normal by a normal map, behind spheres with bumps
+    // from textures: dimpled white plaster, hammered copper, the earth with its land raised,
+    // and glass with the bricks on it. A low sun from the left shows up the relief.
+    let mut world = HittableList::new();
+
+    let bricks = ImageTexture::new_data("bricks_normal.png")
+        .unwrap()
+        .with_uv_scale(4.0, 2.0);
+    let wall = Material::Lambertian(Lambertian::new(Vec3::new(0.6, 0.3, 0.2)))
+        .with_normal_map(Arc::new(bricks), 1.0);
+    world.add(Arc::new(Quad::new(
+        Vec3::new(-6.0, 0.0, -2.0),
+        Vec3::new(12.0, 0.0, 0.0),
+        Vec3::new(0.0, 6.0, 0.0),
+        wall,
+    )));
+
+    let gravel = NoiseTexture::new(FractalNoise::new(NoiseKind::Simplex, Fractal::Fbm), 8.0);
+    let floor = Material::Lambertian(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)))
+        .with_bump_map(Arc::new(gravel), 0.02);
+    world.add(Arc::new(Quad::new(
+        Vec3::new(-6.0, 0.0, 6.0),
+        Vec3::new(12.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -8.0),
+        floor,
+    )));
+
+    let dimples = || {
+        NoiseTexture::new(
+            FractalNoise::new(NoiseKind::Worley, Fractal::Fbm).with_octaves(1),
+            12.0,
+        )
+    };
+    let bricks = ImageTexture::new_data("bricks_normal.png")
+        .unwrap()
+        .with_uv_scale(4.0, 2.0);
+    let materials = [
+        Material::Lambertian(Lambertian::new(Vec3::new(0.8, 0.8, 0.8)))
+            .with_bump_map(Arc::new(dimples()), 0.03),
+        Material::Conductor(Conductor::new_preset("copper", 0.1, 0.1).unwrap())
+            .with_bump_map(Arc::new(dimples()), 0.03),
+        Material::Principled(Principled::new_from_texture(Arc::new(
+            ImageTexture::new("earthmap.jpg").unwrap(),
+        )))
+        .with_bump_map(
+            Arc::new(ImageTexture::new_data("earthmap.jpg").unwrap()),
+            0.03,
+        ),
+        Material::Dielectric(Dielectric::new(1.5)).with_normal_map(Arc::new(bricks), 1.0),
+    ];
+    for (i, material) in materials.into_iter().enumerate() {
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(-3.0 + 2.0 * i as Float, 0.8, 1.0),
+            0.8,
+            material,
+        )));
+    }
+
+    let mut lights = LightList::new();
+    lights.add(Arc::new(
+        DirectionalLight::new(Vec3::new(-3.0, 1.5, 1.0), Vec3::new(3.0, 2.8, 2.5))
+            .with_angular_diameter(2.0),
+    ));
+
+    let bvh_node = FlatBvh::new_from_list(&world);
+
+    /* Camera */
+    let mut cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        100,
+        50,
+        35.0,
+        Vec3::new(0.0, 2.5, 10.0),
+        Vec3::new(0.0, 1.2, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out29.ppm",
+        Vec3::new(0.0, 0.0, 0.0),
+    );
+    cam.set_background(Arc::new(GradientBackground::new(
+        Vec3::new(0.1, 0.1, 0.1),
+        Vec3::new(0.2, 0.25, 0.35),
+    )));
+
+    cam.render_with_lights(&bvh_node, &lights);
+}
+
+fn cutouts() {
+    // Holes cut by opacity textures, which rays pass straight through. From left to right: the
+    // moon map, cut out by its alpha channel to the cross it is painted on; a checkered screen,
+    // turned and moved into place; a sphere eaten away by noise, showing its inside; and a
+    // screen half see-through at random. Everything sits in a `BvhNode`, and the shadows have
+    // the same holes.
+    let mut objects = HittableList::new();
+
+    let ground = Material::Lambertian(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+    objects.add(Arc::new(Quad::new(
+        Vec3::new(-10.0, 0.0, -10.0),
+        Vec3::new(20.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 20.0),
+        ground,
+    )));
+
+    let moon = Arc::new(ImageTexture::new("moon.png").unwrap());
+    let moon_quad = Arc::new(Quad::new(
+        Vec3::new(-4.6, 0.2, 0.0),
+        Vec3::new(2.4, 0.0, 0.0),
+        Vec3::new(0.0, 1.8, 0.0),
+        Material::Lambertian(Lambertian::new_from_texture(moon.clone())),
+    ));
+    objects.add(Arc::new(Cutout::new(moon_quad, moon, 0.5)));
+
+    let checker: Arc<dyn Texture> = Arc::new(CheckerTexture::new_from_rgb(
+        0.25,
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(1.0, 1.0, 1.0),
+    ));
+    let screen = Arc::new(Quad::new(
+        Vec3::new(-1.0, 0.0, 0.1),
+        Vec3::new(2.0, 0.0, 0.0),
+        Vec3::new(0.0, 2.0, 0.0),
+        Material::Lambertian(Lambertian::new(Vec3::new(0.2, 0.4, 0.7))),
+    ));
+    objects.add(Arc::new(Translate::new(
+        Arc::new(RotateY::new(
+            Arc::new(Cutout::new(screen, checker, 0.5)),
+            30.0,
+        )),
+        Vec3::new(-0.9, 0.1, 0.0),
+    )));
+
+    let holes = NoiseTexture::new(FractalNoise::new(NoiseKind::Simplex, Fractal::Fbm), 3.0);
+    let sphere = Arc::new(Sphere::new(
+        Vec3::new(1.6, 1.0, 0.0),
+        0.9,
+        Material::Lambertian(Lambertian::new(Vec3::new(0.8, 0.6, 0.2))),
+    ));
+    objects.add(Arc::new(Cutout::new(sphere, Arc::new(holes), 0.45)));
+
+    let half = Arc::new(SolidColor::new(Vec3::new(0.5, 0.5, 0.5)));
+    let veil = Arc::new(Quad::new(
+        Vec3::new(3.0, 0.0, 0.5),
+        Vec3::new(1.8, 0.0, -0.6),
+        Vec3::new(0.0, 2.0, 0.0),
+        Material::Lambertian(Lambertian::new(Vec3::new(0.7, 0.2, 0.2))),
+    ));
+    objects.add(Arc::new(Cutout::new_partial(veil, half)));
+
+    let mut lights = LightList::new();
+    lights.add(Arc::new(
+        DirectionalLight::new(Vec3::new(1.0, 2.0, 2.0), Vec3::new(2.5, 2.4, 2.2))
+            .with_angular_diameter(1.0),
+    ));
+
+    let world = BvhNode::new_from_list(&objects);
+
+    /* Camera */
+    let mut cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        100,
+        50,
+        40.0,
+        Vec3::new(0.0, 2.0, 9.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out30.ppm",
+        Vec3::new(0.0, 0.0, 0.0),
+    );
+    cam.set_background(Arc::new(GradientBackground::new_sky()));
+
+    cam.render_with_lights(&world, &lights);
+}
+
+fn texture_nodes() {
+    // Textures wired together from nodes in scenes/texture_nodes.textures. Along the back:
+    // rusted steel, the earth at night and the earth tiled; in front: marble veins, bands of
+    // color by height and a turned cube with the earth projected onto it, all on a floor of
+    // checkered wood.
+    let library = TextureLibrary::load_scene(Path::new("scenes/texture_nodes.textures")).unwrap();
+    let texture = |name: &str| library.get(name).unwrap();
+    let mut objects = HittableList::new();
+
+    objects.add(Arc::new(Quad::new(
+        Vec3::new(-10.0, 0.0, -10.0),
+        Vec3::new(20.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 20.0),
+        Material::Lambertian(Lambertian::new_from_texture(texture("floor"))),
+    )));
+
+    let back = [("rusty", -2.4), ("night_earth", 0.0), ("tiled_earth", 2.4)];
+    for (name, x) in back {
+        objects.add(Arc::new(Sphere::new(
+            Vec3::new(x, 1.0, -1.5),
+            1.0,
+            Material::Lambertian(Lambertian::new_from_texture(texture(name))),
+        )));
+    }
+
+    objects.add(Arc::new(Sphere::new(
+        Vec3::new(-1.8, 0.7, 1.2),
+        0.7,
+        Material::Lambertian(Lambertian::new_from_texture(texture("tinted_marble"))),
+    )));
+    objects.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, 0.7, 1.2),
+        0.7,
+        Material::Lambertian(Lambertian::new_from_texture(texture("bands"))),
+    )));
+    objects.add(Arc::new(Translate::new(
+        Arc::new(RotateY::new(
+            Arc::new(Cube::new(
+                Vec3::new(0.0, 0.6, 0.0),
+                1.2,
+                Material::Lambertian(Lambertian::new_from_texture(texture("earth_cube"))),
+            )),
+            30.0,
+        )),
+        Vec3::new(1.9, 0.0, 1.2),
+    )));
+
+    let mut lights = LightList::new();
+    lights.add(Arc::new(
+        DirectionalLight::new(Vec3::new(1.0, 2.0, 2.0), Vec3::new(2.5, 2.4, 2.2))
+            .with_angular_diameter(1.0),
+    ));
+
+    let world = BvhNode::new_from_list(&objects);
+
+    /* Camera */
+    let mut cam: Camera = Camera::new(
+        16.0 / 9.0,
+        1200.0,
+        100,
+        50,
+        40.0,
+        Vec3::new(0.0, 3.0, 9.0),
+        Vec3::new(0.0, 0.8, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        10.0,
+        "out31.ppm",
+        Vec3::new(0.0, 0.0, 0.0),
+    );
+    cam.set_background(Arc::new(GradientBackground::new_sky()));
+
+    cam.render_with_lights(&world, &lights);
+}
+
 fn main() {
     let num = 7;
     match num {
@@ -559,6 +2054,30 @@ fn main() {
         5 => light(),
         6 => cornell_box(),
         7 => final_scene(),
+        8 => instanced_spheres(),
+        9 => motion_blur(),
+        10 => bvh_benchmark(),
+        11 => animated_instances(),
+        12 => aabb_benchmark(),
+        13 => precision_benchmark(),
+        // 14, the scale test, is now a test in hittables.rs.
+        15 => conductors(),
+        16 => glass(),
+        // 17, the white furnace, is now the furnace tests in material.rs.
+        18 => principled(),
+        19 => spectral_final_scene(),
+        20 => light_spectra(),
+        21 => emission_profiles(),
+        22 => delta_lights(),
+        23 => environment_light(),
+        24 => sky(),
+        25 => backgrounds(),
+        26 => noise_textures(),
+        27 => texture_filtering(),
+        28 => image_color_spaces(),
+        29 => surface_detail(),
+        30 => cutouts(),
+        31 => texture_nodes(),
         _ => final_scene(),
     }
 }
@@ -1,40 +1,162 @@
 use crate::hittables::HitRecord;
+use crate::ies::IesProfile;
+use crate::microfacet::{fresnel_complex_rgb, fresnel_dielectric, TrowbridgeReitz};
+use crate::onb::Onb;
 use crate::ray::Ray;
-use crate::textures::{SolidColor, Texture};
-use crate::utils::random_double;
+use crate::spectrum::{Dispersion, SampledSpectrum, SampledWavelengths, Spectrum};
+use crate::textures::{gray, SolidColor, Texture};
+use crate::utils::{degrees_to_radians, random_double, Float, PI};
 use crate::vec3::Vec3;
 use std::sync::Arc;
 
+// Ray leaving a surface, picked by `Scatterable::scatter`.
+#[derive(Clone, Copy)]
+pub struct ScatterRecord {
+    pub scattered: Ray,
+    pub attenuation: Vec3,
+    pub is_specular: bool, // Picked from a mirror-like lobe, which `eval` and `pdf` leave out
+}
+
 pub trait Scatterable {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Option<Ray>, Vec3)>;
-    fn emitted(&self, _u: f64, _v: f64, _p: Vec3) -> Vec3;
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord>;
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Vec3;
+    // Fraction of the light arriving from `direction` that leaves back along `r_in`, times the
+    // cosine of its angle to the normal. Lights sampled directly are shaded with this.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3;
+    // Density over solid angle of `scatter` picking `direction`, not counting mirror-like lobes.
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Float;
 }
 #[derive(Clone)]
 pub enum Material {
     Lambertian(Lambertian),
     Metal(Metal),
+    Conductor(Conductor),
     Dielectric(Dielectric),
+    ThinDielectric(ThinDielectric),
+    Principled(Principled),
     DiffuseLight(DiffuseLight),
     Isotropic(Isotropic),
+    NormalMapped(NormalMapped),
+}
+impl Material {
+    pub fn is_dispersive(&self) -> bool {
+        // Whether the direction of scattered rays depends on their wavelength.
+        match self {
+            Material::Dielectric(d) => d.dispersion.is_some(),
+            Material::NormalMapped(m) => m.material.is_dispersive(),
+            _ => false,
+        }
+    }
+
+    pub fn emitted_spectrum(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: &SampledWavelengths,
+    ) -> SampledSpectrum {
+        match self {
+            Material::DiffuseLight(l) => l.emitted_spectrum(r_in, rec, wavelengths),
+            Material::NormalMapped(m) => m.material.emitted_spectrum(r_in, rec, wavelengths),
+            _ => SampledSpectrum::from_rgb_illuminant(self.emitted(r_in, rec), wavelengths),
+        }
+    }
+
+    pub fn with_emitter_area(self, area: Float, closed: bool) -> Material {
+        // Called by shapes with their surface area, so lights given in watts can work out their
+        // brightness. Only the outside of a closed shape counts, since light given off inside
+        // never leaves it.
+        match self {
+            Material::DiffuseLight(l) => Material::DiffuseLight(l.with_area(area, closed)),
+            Material::NormalMapped(m) => Material::NormalMapped(NormalMapped {
+                material: Box::new(m.material.with_emitter_area(area, closed)),
+                ..m
+            }),
+            material => material,
+        }
+    }
+
+    pub fn with_normal_map(self, texture: Arc<dyn Texture>, strength: Float) -> Material {
+        // Tilts the shading normal by a tangent space normal map, which should be loaded as data.
+        // `strength` scales the tilt, with 1 using the map as it is.
+        Material::NormalMapped(NormalMapped {
+            material: Box::new(self),
+            detail: SurfaceDetail::NormalMap(texture, strength),
+        })
+    }
+
+    pub fn with_bump_map(self, texture: Arc<dyn Texture>, height: Float) -> Material {
+        // Tilts the shading normal as if the surface were raised by the texture, times `height`
+        // in world units. The texture is read as gray, from the average of its channels.
+        Material::NormalMapped(NormalMapped {
+            material: Box::new(self),
+            detail: SurfaceDetail::Bump(texture, height),
+        })
+    }
+
+    pub fn shading_normal(&self, rec: &HitRecord) -> Vec3 {
+        // Normal the material is shaded with. The camera sets it on each hit record before the
+        // material scatters, so every material uses it the same way.
+        match self {
+            Material::NormalMapped(m) => m.shading_normal(rec),
+            _ => rec.normal,
+        }
+    }
 }
+
 impl Scatterable for Material {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Option<Ray>, Vec3)> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         match self {
             Material::Lambertian(l) => l.scatter(r_in, rec),
             Material::Metal(m) => m.scatter(r_in, rec),
+            Material::Conductor(c) => c.scatter(r_in, rec),
             Material::Dielectric(d) => d.scatter(r_in, rec),
+            Material::ThinDielectric(d) => d.scatter(r_in, rec),
+            Material::Principled(pr) => pr.scatter(r_in, rec),
             Material::DiffuseLight(d) => d.scatter(r_in, rec),
             Material::Isotropic(i) => i.scatter(r_in, rec),
+            Material::NormalMapped(m) => m.material.scatter(r_in, rec),
+        }
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        match self {
+            Material::Lambertian(l) => l.emitted(r_in, rec),
+            Material::Metal(m) => m.emitted(r_in, rec),
+            Material::Conductor(c) => c.emitted(r_in, rec),
+            Material::Dielectric(d) => d.emitted(r_in, rec),
+            Material::ThinDielectric(d) => d.emitted(r_in, rec),
+            Material::Principled(pr) => pr.emitted(r_in, rec),
+            Material::DiffuseLight(d) => d.emitted(r_in, rec),
+            Material::Isotropic(i) => i.emitted(r_in, rec),
+            Material::NormalMapped(m) => m.material.emitted(r_in, rec),
         }
     }
 
-    fn emitted(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
         match self {
-            Material::Lambertian(l) => l.emitted(u, v, p),
-            Material::Metal(m) => m.emitted(u, v, p),
-            Material::Dielectric(d) => d.emitted(u, v, p),
-            Material::DiffuseLight(d) => d.emitted(u, v, p),
-            Material::Isotropic(i) => i.emitted(u, v, p),
+            Material::Lambertian(l) => l.eval(r_in, rec, direction),
+            Material::Metal(m) => m.eval(r_in, rec, direction),
+            Material::Conductor(c) => c.eval(r_in, rec, direction),
+            Material::Dielectric(d) => d.eval(r_in, rec, direction),
+            Material::ThinDielectric(d) => d.eval(r_in, rec, direction),
+            Material::Principled(pr) => pr.eval(r_in, rec, direction),
+            Material::DiffuseLight(d) => d.eval(r_in, rec, direction),
+            Material::Isotropic(i) => i.eval(r_in, rec, direction),
+            Material::NormalMapped(m) => m.material.eval(r_in, rec, direction),
+        }
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Float {
+        match self {
+            Material::Lambertian(l) => l.pdf(r_in, rec, direction),
+            Material::Metal(m) => m.pdf(r_in, rec, direction),
+            Material::Conductor(c) => c.pdf(r_in, rec, direction),
+            Material::Dielectric(d) => d.pdf(r_in, rec, direction),
+            Material::ThinDielectric(d) => d.pdf(r_in, rec, direction),
+            Material::Principled(pr) => pr.pdf(r_in, rec, direction),
+            Material::DiffuseLight(d) => d.pdf(r_in, rec, direction),
+            Material::Isotropic(i) => i.pdf(r_in, rec, direction),
+            Material::NormalMapped(m) => m.material.pdf(r_in, rec, direction),
         }
     }
 }
@@ -53,33 +175,50 @@ impl Lambertian {
     }
 }
 impl Scatterable for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<(Option<Ray>, Vec3)> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
 
         if scatter_direction.near_zero() {
             scatter_direction = rec.normal;
         }
 
-        let scattered = Ray::new(rec.p, scatter_direction);
-        let attenuation = self.texture.value(rec.u, rec.v, rec.p);
-        Some((Some(scattered), attenuation))
+        let scattered = rec.spawn_ray(scatter_direction, r_in.time);
+        let attenuation = self
+            .texture
+            .value_filtered(rec.u, rec.v, rec.p, rec.uv_differentials);
+        Some(ScatterRecord {
+            scattered,
+            attenuation,
+            is_specular: false,
+        })
     }
 
-    fn emitted(&self, _u: f64, _v: f64, _p: Vec3) -> Vec3 {
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
     }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        self.texture
+            .value_filtered(rec.u, rec.v, rec.p, rec.uv_differentials)
+            * self.pdf(r_in, rec, direction)
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Float {
+        // Directions are picked in proportion to the cosine, like the light reflected.
+        rec.normal.dot(&Vec3::unit_vector(direction)).max(0.0) / PI
+    }
 }
 
 #[derive(Clone)]
 pub struct Metal {
     albedo: Vec3,
-    fuzz: f64,
+    fuzz: Float,
 }
 
 impl Metal {
-    pub fn new(albedo: Vec3, fuzz: f64) -> Self {
+    pub fn new(albedo: Vec3, fuzz: Float) -> Self {
         let mut fuzz = fuzz;
-        if !(fuzz < 1.0) {
+        if fuzz.is_nan() || fuzz >= 1.0 {
             fuzz = 1.0;
         }
         Metal { albedo, fuzz }
@@ -87,89 +226,822 @@ impl Metal {
 }
 
 impl Scatterable for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Option<Ray>, Vec3)> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let mut reflected = Vec3::reflect(r_in.direction, rec.normal);
         reflected = Vec3::unit_vector(reflected) + (self.fuzz * Vec3::random_unit_vector());
-        let scattered = Ray::new(rec.p, reflected);
+        let scattered = rec.spawn_ray(reflected, r_in.time);
         let attenuation = self.albedo;
 
-        Some((Some(scattered), attenuation))
+        Some(ScatterRecord {
+            scattered,
+            attenuation,
+            is_specular: true,
+        })
+    }
+
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> Vec3 {
+        // Fuzzed reflections have no density to evaluate, so they count as mirror-like and only
+        // rays that happen to reach a light are reflected.
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> Float {
+        0.0
+    }
+}
+
+// Metal with a rough surface of mirror microfacets, whose color comes from its complex index of
+// refraction. Roughness can differ along the two tangents of the surface for brushed metals.
+#[derive(Clone)]
+pub struct Conductor {
+    eta: Vec3,
+    k: Vec3,
+    distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    // Index of refraction (eta, k) of common metals at the red, green and blue wavelengths.
+    pub const PRESETS: [(&'static str, [Float; 3], [Float; 3]); 4] = [
+        ("gold", [0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+        ("copper", [0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+        ("aluminum", [1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+        ("silver", [0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+    ];
+
+    pub fn new(eta: Vec3, k: Vec3, roughness_u: Float, roughness_v: Float) -> Self {
+        Conductor {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::new(roughness_u, roughness_v),
+        }
+    }
+
+    pub fn new_preset(name: &str, roughness_u: Float, roughness_v: Float) -> Result<Self, String> {
+        let (_, eta, k) = Conductor::PRESETS
+            .iter()
+            .find(|(preset, _, _)| *preset == name)
+            .ok_or_else(|| format!("Unknown metal \"{}\"", name))?;
+        Ok(Conductor::new(
+            Vec3::new(eta[0], eta[1], eta[2]),
+            Vec3::new(k[0], k[1], k[2]),
+            roughness_u,
+            roughness_v,
+        ))
+    }
+}
+
+impl Scatterable for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        // Work in the shading frame, where the normal is the z axis and the x axis follows u, so
+        // anisotropic roughness runs along the surface's parameterization.
+        let frame = Onb::new_from_tangent(rec.normal, rec.dpdu);
+        let wo = frame.to_local(-Vec3::unit_vector(r_in.direction));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        // Reflect off a sampled visible microfacet. Rays sent below the surface are absorbed,
+        // which stands in for the energy lost to multiple scattering between microfacets.
+        let wm = self.distribution.sample_visible_normal(wo);
+        let wi = Vec3::reflect(-wo, wm);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+
+        let fresnel = fresnel_complex_rgb(wo.dot(&wm), self.eta, self.k);
+        let attenuation = fresnel * self.distribution.masking_weight(wo, wi);
+        let scattered = rec.spawn_ray(frame.to_world(wi), r_in.time);
+        Some(ScatterRecord {
+            scattered,
+            attenuation,
+            is_specular: self.distribution.is_smooth(),
+        })
     }
 
-    fn emitted(&self, _u: f64, _v: f64, _p: Vec3) -> Vec3 {
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
     }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        let frame = Onb::new_from_tangent(rec.normal, rec.dpdu);
+        let wo = frame.to_local(-Vec3::unit_vector(r_in.direction));
+        let wi = frame.to_local(Vec3::unit_vector(direction));
+        let (wm, reflected) = self.distribution.reflection(wo, wi);
+        fresnel_complex_rgb(wo.dot(&wm), self.eta, self.k) * reflected
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Float {
+        let frame = Onb::new_from_tangent(rec.normal, rec.dpdu);
+        let wo = frame.to_local(-Vec3::unit_vector(r_in.direction));
+        let wi = frame.to_local(Vec3::unit_vector(direction));
+        self.distribution.reflection_pdf(wo, wi).1
+    }
 }
 
+// Glass and other transparent solids. Rough surfaces scatter through microfacets, and light
+// travelling inside is absorbed following the Beer-Lambert law.
 #[derive(Clone)]
 pub struct Dielectric {
-    refraction_index: f64,
+    refraction_index: Float,
+    dispersion: Option<Dispersion>,
+    distribution: TrowbridgeReitz,
+    absorption: Vec3,
 }
 
 impl Dielectric {
-    pub fn new(refraction_index: f64) -> Self {
-        Dielectric { refraction_index }
+    pub fn new(refraction_index: Float) -> Self {
+        Dielectric::new_rough(refraction_index, 0.0)
     }
 
-    fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
-        let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
-        r0 = r0 * r0;
-        r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+    pub fn new_rough(refraction_index: Float, roughness: Float) -> Self {
+        Dielectric {
+            refraction_index,
+            dispersion: None,
+            distribution: TrowbridgeReitz::new(roughness, roughness),
+            absorption: Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn new_dispersive(dispersion: Dispersion, roughness: Float) -> Self {
+        // Rendering in RGB uses the index of refraction at the d line.
+        Dielectric {
+            dispersion: Some(dispersion),
+            ..Dielectric::new_rough(dispersion.refraction_index(Dispersion::D_LINE), roughness)
+        }
+    }
+
+    pub fn new_tinted(
+        refraction_index: Float,
+        roughness: Float,
+        color: Vec3,
+        distance: Float,
+    ) -> Self {
+        // Absorbs just enough that light travelling `distance` inside is tinted to `color`. A
+        // distance that isn't positive can't be tinted over, and gives clear glass.
+        let absorption = |c: Float| {
+            if distance > 0.0 {
+                -c.clamp(1e-6, 1.0).ln() / distance
+            } else {
+                0.0
+            }
+        };
+        Dielectric {
+            absorption: Vec3::new(
+                absorption(color.x()),
+                absorption(color.y()),
+                absorption(color.z()),
+            ),
+            ..Dielectric::new_rough(refraction_index, roughness)
+        }
+    }
+
+    fn eta(&self, r_in: &Ray, rec: &HitRecord) -> Float {
+        // Index of refraction on the far side of the surface over the one on the near side.
+        let refraction_index = match self.dispersion {
+            Some(dispersion) if r_in.wavelength > 0.0 => {
+                dispersion.refraction_index(r_in.wavelength)
+            }
+            _ => self.refraction_index,
+        };
+        if rec.front_face {
+            refraction_index
+        } else {
+            1.0 / refraction_index
+        }
+    }
+
+    fn transmittance(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        // A ray hitting the inside of the surface has travelled through the medium to get here.
+        if rec.front_face {
+            return Vec3::new(1.0, 1.0, 1.0);
+        }
+        let distance = rec.t * r_in.direction.length();
+        let transmittance = |a: Float| (-a * distance).exp();
+        Vec3::new(
+            transmittance(self.absorption.x()),
+            transmittance(self.absorption.y()),
+            transmittance(self.absorption.z()),
+        )
     }
 }
 
 impl Scatterable for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Option<Ray>, Vec3)> {
-        let attenuation = Vec3::new(1.0, 1.0, 1.0);
-        let ri = if rec.front_face {
-            1.0 / self.refraction_index
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let eta = self.eta(r_in, rec);
+
+        let frame = Onb::new(rec.normal);
+        let wo = frame.to_local(-Vec3::unit_vector(r_in.direction));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        // Choose between reflection and refraction in proportion to the Fresnel reflectance of
+        // the sampled microfacet, which cancels the Fresnel term out of the weight.
+        let wm = self.distribution.sample_visible_normal(wo);
+        let wi = if fresnel_dielectric(wo.dot(&wm), eta) > random_double() {
+            let wi = Vec3::reflect(-wo, wm);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            wi
         } else {
-            self.refraction_index
+            let wi = Vec3::refract(&-wo, &wm, 1.0 / eta);
+            if wi.z() >= 0.0 {
+                return None;
+            }
+            wi
         };
 
+        let attenuation = self.transmittance(r_in, rec) * self.distribution.masking_weight(wo, wi);
+        let scattered = rec.spawn_ray(frame.to_world(wi), r_in.time);
+        Some(ScatterRecord {
+            scattered,
+            attenuation,
+            is_specular: self.distribution.is_smooth(),
+        })
+    }
+
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        let eta = self.eta(r_in, rec);
+        let frame = Onb::new(rec.normal);
+        let wo = frame.to_local(-Vec3::unit_vector(r_in.direction));
+        let wi = frame.to_local(Vec3::unit_vector(direction));
+
+        // Only one of the two is non-zero, depending on the side of the surface `wi` is on.
+        let (wm_r, reflected) = self.distribution.reflection(wo, wi);
+        let (wm_t, transmitted) = self.distribution.transmission(wo, wi, eta);
+        let value = fresnel_dielectric(wo.dot(&wm_r), eta) * reflected
+            + (1.0 - fresnel_dielectric(wo.dot(&wm_t), eta)) * transmitted;
+        self.transmittance(r_in, rec) * value
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Float {
+        let eta = self.eta(r_in, rec);
+        let frame = Onb::new(rec.normal);
+        let wo = frame.to_local(-Vec3::unit_vector(r_in.direction));
+        let wi = frame.to_local(Vec3::unit_vector(direction));
+
+        let (wm_r, reflected) = self.distribution.reflection_pdf(wo, wi);
+        let (wm_t, transmitted) = self.distribution.transmission_pdf(wo, wi, eta);
+        fresnel_dielectric(wo.dot(&wm_r), eta) * reflected
+            + (1.0 - fresnel_dielectric(wo.dot(&wm_t), eta)) * transmitted
+    }
+}
+
+// Glass with no thickness, such as a window pane modelled as a single quad. Light passes
+// straight through, and the reflectance includes the bounces between the two faces.
+#[derive(Clone)]
+pub struct ThinDielectric {
+    refraction_index: Float,
+}
+
+impl ThinDielectric {
+    pub fn new(refraction_index: Float) -> Self {
+        ThinDielectric { refraction_index }
+    }
+}
+
+impl Scatterable for ThinDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let unit_direction = Vec3::unit_vector(r_in.direction);
-        let cos_theta = (-unit_direction).dot(&rec.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-        let cannot_refract = ri * sin_theta > 1.0;
-        if cannot_refract || Dielectric::reflectance(cos_theta, ri) > random_double() {
-            let direction = Vec3::reflect(unit_direction, rec.normal);
-            let scattered = Ray::new(rec.p, direction);
-            Some((Some(scattered), attenuation))
-        } else {
-            let direction = Vec3::refract(&unit_direction, &rec.normal, ri);
-            let scattered = Ray::new(rec.p, direction);
-            Some((Some(scattered), attenuation))
+        let cos_theta = (-unit_direction).dot(&rec.normal);
+        let mut reflectance = fresnel_dielectric(cos_theta, self.refraction_index);
+        if reflectance < 1.0 {
+            let transmittance = 1.0 - reflectance;
+            reflectance +=
+                transmittance * transmittance * reflectance / (1.0 - reflectance * reflectance);
         }
+
+        let direction = if reflectance > random_double() {
+            Vec3::reflect(unit_direction, rec.normal)
+        } else {
+            unit_direction
+        };
+        Some(ScatterRecord {
+            scattered: rec.spawn_ray(direction, r_in.time),
+            attenuation: Vec3::new(1.0, 1.0, 1.0),
+            is_specular: true,
+        })
+    }
+
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
     }
 
-    fn emitted(&self, _u: f64, _v: f64, _p: Vec3) -> Vec3 {
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> Vec3 {
+        // Light only leaves along the mirror and straight-through directions.
         Vec3::new(0.0, 0.0, 0.0)
     }
+
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> Float {
+        0.0
+    }
 }
 
+// Inputs of the principled material. Scalar inputs are read from the first channel of their
+// texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrincipledInput {
+    BaseColor,
+    Metallic,
+    Roughness,
+    Specular,
+    SpecularTint,
+    Sheen,
+    SheenTint,
+    Clearcoat,
+    ClearcoatRoughness,
+    Transmission,
+    Ior,
+    Emission,
+}
+
+// Layered material in the style of the Disney principled BSDF, following the metallic/roughness
+// workflow. Each bounce picks one layer with the probability of light reaching and being
+// scattered by it, so the layers together never reflect more light than they receive.
+#[derive(Clone)]
+pub struct Principled {
+    inputs: [Arc<dyn Texture>; 12],
+}
+
+impl Principled {
+    pub fn new(base_color: Vec3) -> Principled {
+        Principled::new_from_texture(Arc::new(SolidColor::new(base_color)))
+    }
+
+    pub fn new_from_texture(base_color: Arc<dyn Texture>) -> Principled {
+        let value =
+            |v: Float| -> Arc<dyn Texture> { Arc::new(SolidColor::new(Vec3::new(v, v, v))) };
+        Principled {
+            inputs: [
+                base_color,
+                value(0.0),  // Metallic
+                value(0.5),  // Roughness
+                value(0.5),  // Specular
+                value(0.0),  // SpecularTint
+                value(0.0),  // Sheen
+                value(0.5),  // SheenTint
+                value(0.0),  // Clearcoat
+                value(0.03), // ClearcoatRoughness
+                value(0.0),  // Transmission
+                value(1.5),  // Ior
+                value(0.0),  // Emission
+            ],
+        }
+    }
+
+    pub fn with_value(self, input: PrincipledInput, value: Float) -> Principled {
+        self.with_color(input, Vec3::new(value, value, value))
+    }
+
+    pub fn with_color(self, input: PrincipledInput, color: Vec3) -> Principled {
+        self.with_texture(input, Arc::new(SolidColor::new(color)))
+    }
+
+    pub fn with_texture(mut self, input: PrincipledInput, texture: Arc<dyn Texture>) -> Principled {
+        self.inputs[input as usize] = texture;
+        self
+    }
+
+    fn color(&self, input: PrincipledInput, rec: &HitRecord) -> Vec3 {
+        self.inputs[input as usize].value_filtered(rec.u, rec.v, rec.p, rec.uv_differentials)
+    }
+
+    fn value(&self, input: PrincipledInput, rec: &HitRecord) -> Float {
+        self.color(input, rec).x()
+    }
+
+    fn tint(base_color: Vec3) -> Vec3 {
+        // Hue and saturation of the base color, at a luminance of 1.
+        let luminance = 0.3 * base_color.x() + 0.6 * base_color.y() + 0.1 * base_color.z();
+        if luminance > 0.0 {
+            base_color / luminance
+        } else {
+            Vec3::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    fn sqrt_color(color: Vec3) -> Vec3 {
+        Vec3::new(color.x().sqrt(), color.y().sqrt(), color.z().sqrt())
+    }
+
+    fn lobes(&self, rec: &HitRecord, wo: Vec3) -> Lobes {
+        // Chances of `scatter` picking each layer, which `evaluate` weights the layers by too.
+        // They are taken from the Fresnel terms at the surface normal, not at the microfacet
+        // normal `scatter` samples, so they only depend on `wo` and the density of any direction
+        // can be worked out again afterwards.
+        use PrincipledInput::*;
+
+        let transmission = self.value(Transmission, rec);
+        let ior = self.value(Ior, rec);
+        if !rec.front_face && transmission > 0.0 {
+            // Light transmitted into the material only meets the boundary again from inside,
+            // where it behaves like rough glass. Opaque materials are seen from behind as from
+            // the front, since the hit record's normal always faces the ray.
+            let reflected = fresnel_dielectric(wo.z(), 1.0 / ior);
+            return Lobes {
+                inside: true,
+                specular: reflected,
+                transmission: 1.0 - reflected,
+                ..Lobes::default()
+            };
+        }
+
+        let mut weight = 1.0;
+        let clearcoat = self.value(Clearcoat, rec) * fresnel_dielectric(wo.z(), 1.5);
+        weight -= clearcoat;
+        let metal = weight * self.value(Metallic, rec);
+        weight -= metal;
+        let reflectance =
+            (2.0 * self.value(Specular, rec) * fresnel_dielectric(wo.z(), ior)).min(1.0);
+        let specular = weight * reflectance;
+        weight -= specular;
+        let transmission = weight * transmission;
+        Lobes {
+            inside: false,
+            clearcoat,
+            metal,
+            specular,
+            transmission,
+            diffuse: weight - transmission,
+        }
+    }
+
+    fn specular_color(&self, rec: &HitRecord, lobes: &Lobes, base_color: Vec3) -> Vec3 {
+        // Tint of the specular reflection off the dielectric base. From inside, the boundary
+        // reflects like clear glass.
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        if lobes.inside {
+            return white;
+        }
+        let specular_tint = self.value(PrincipledInput::SpecularTint, rec);
+        white * (1.0 - specular_tint) + Principled::tint(base_color) * specular_tint
+    }
+
+    fn evaluate(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> (Vec3, Float) {
+        // Light scattered from `direction` back along `r_in`, and the density of `scatter`
+        // picking it. The layers are added up, each weighted by the chance of picking it.
+        use PrincipledInput::*;
+
+        let frame = Onb::new(rec.normal);
+        let wo = frame.to_local(-Vec3::unit_vector(r_in.direction));
+        let wi = frame.to_local(Vec3::unit_vector(direction));
+        if wo.z() <= 0.0 {
+            return (Vec3::new(0.0, 0.0, 0.0), 0.0);
+        }
+
+        let lobes = self.lobes(rec, wo);
+        let base_color = self.color(BaseColor, rec);
+        let roughness = self.value(Roughness, rec);
+        let distribution = TrowbridgeReitz::new(roughness, roughness);
+        let ior = self.value(Ior, rec);
+        let eta = if lobes.inside { 1.0 / ior } else { ior };
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let (wm_r, reflected) = distribution.reflection(wo, wi);
+        let reflected_pdf = distribution.reflection_pdf(wo, wi).1;
+
+        let mut value = Vec3::new(0.0, 0.0, 0.0);
+        let mut pdf = 0.0;
+
+        if lobes.clearcoat > 0.0 {
+            let coat_roughness = self.value(ClearcoatRoughness, rec);
+            let coat = TrowbridgeReitz::new(coat_roughness, coat_roughness);
+            value = value + white * lobes.clearcoat * coat.reflection(wo, wi).1;
+            pdf += lobes.clearcoat * coat.reflection_pdf(wo, wi).1;
+        }
+
+        let fresnel = base_color + (white - base_color) * (1.0 - wo.dot(&wm_r)).powi(5);
+        value = value + fresnel * lobes.metal * reflected;
+        pdf += lobes.metal * reflected_pdf;
+
+        let specular_color = self.specular_color(rec, &lobes, base_color);
+        value = value + specular_color * lobes.specular * reflected;
+        pdf += lobes.specular * reflected_pdf;
+
+        if lobes.transmission > 0.0 {
+            let transmitted = distribution.transmission(wo, wi, eta).1;
+            value = value + Principled::sqrt_color(base_color) * lobes.transmission * transmitted;
+            pdf += lobes.transmission * distribution.transmission_pdf(wo, wi, eta).1;
+        }
+
+        if lobes.diffuse > 0.0 && wi.z() > 0.0 {
+            let diffuse = self.diffuse_color(rec, base_color, wo, wi);
+            value = value + diffuse * lobes.diffuse * wi.z() / PI;
+            pdf += lobes.diffuse * wi.z() / PI;
+        }
+        (value, pdf)
+    }
+
+    fn diffuse_color(&self, rec: &HitRecord, base_color: Vec3, wo: Vec3, wi: Vec3) -> Vec3 {
+        // Diffuse base with sheen, a soft white rim seen on cloth. The sheen takes the place of
+        // as much of the base as it covers, so it never reflects more light than comes in.
+        use PrincipledInput::*;
+
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let sheen_tint = self.value(SheenTint, rec);
+        let sheen_color = white * (1.0 - sheen_tint) + Principled::tint(base_color) * sheen_tint;
+        // The tint is scaled to a luminance of 1, which can take a saturated channel above 1.
+        let sheen_color = Vec3::new(
+            sheen_color.x().min(1.0),
+            sheen_color.y().min(1.0),
+            sheen_color.z().min(1.0),
+        );
+        let cos_d = wi.dot(&Vec3::unit_vector(wi + wo));
+        let sheen = self.value(Sheen, rec).clamp(0.0, 1.0) * (1.0 - cos_d).powi(5);
+        base_color * (1.0 - sheen) + sheen_color * sheen
+    }
+}
+
+// Chances of the principled material scattering off each of its layers, adding up to 1.
+#[derive(Default)]
+struct Lobes {
+    inside: bool, // Seen from inside a transmissive material, where only the boundary scatters
+    clearcoat: Float,
+    metal: Float,
+    specular: Float,
+    transmission: Float,
+    diffuse: Float,
+}
+
+impl Scatterable for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        use PrincipledInput::*;
+
+        let frame = Onb::new(rec.normal);
+        let wo = frame.to_local(-Vec3::unit_vector(r_in.direction));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let lobes = self.lobes(rec, wo);
+        let base_color = self.color(BaseColor, rec);
+        let roughness = self.value(Roughness, rec);
+        let distribution = TrowbridgeReitz::new(roughness, roughness);
+        let ior = self.value(Ior, rec);
+        let eta = if lobes.inside { 1.0 / ior } else { ior };
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let spawn = |wi: Vec3, attenuation: Vec3, is_specular: bool| {
+            Some(ScatterRecord {
+                scattered: rec.spawn_ray(frame.to_world(wi), r_in.time),
+                attenuation,
+                is_specular,
+            })
+        };
+        let is_smooth = distribution.is_smooth();
+        let mut choice = random_double();
+
+        // Clear coat, a thin layer of varnish on top of everything else.
+        if choice < lobes.clearcoat {
+            let coat_roughness = self.value(ClearcoatRoughness, rec);
+            let coat = TrowbridgeReitz::new(coat_roughness, coat_roughness);
+            let wi = Vec3::reflect(-wo, coat.sample_visible_normal(wo));
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            return spawn(wi, white * coat.masking_weight(wo, wi), coat.is_smooth());
+        }
+        choice -= lobes.clearcoat;
+
+        // Metal, reflecting its base color with Schlick's approximation of the Fresnel term.
+        let wm = distribution.sample_visible_normal(wo);
+        if choice < lobes.metal {
+            let wi = Vec3::reflect(-wo, wm);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            let fresnel = base_color + (white - base_color) * (1.0 - wo.dot(&wm)).powi(5);
+            return spawn(wi, fresnel * distribution.masking_weight(wo, wi), is_smooth);
+        }
+        choice -= lobes.metal;
+
+        // Specular reflection off the dielectric base. A specular input of 0.5 matches the
+        // index of refraction.
+        if choice < lobes.specular {
+            let wi = Vec3::reflect(-wo, wm);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            let specular_color = self.specular_color(rec, &lobes, base_color);
+            return spawn(
+                wi,
+                specular_color * distribution.masking_weight(wo, wi),
+                is_smooth,
+            );
+        }
+        choice -= lobes.specular;
+
+        // Light passing through the boundary. Each crossing is tinted by half of the base color,
+        // so light passing in and out again takes on the whole of it. Light can't get out
+        // through microfacets it would be totally reflected by.
+        if choice < lobes.transmission {
+            if fresnel_dielectric(wo.dot(&wm), eta) >= 1.0 {
+                return None;
+            }
+            let wi = Vec3::refract(&-wo, &wm, 1.0 / eta);
+            if wi.z() >= 0.0 {
+                return None;
+            }
+            return spawn(
+                wi,
+                Principled::sqrt_color(base_color) * distribution.masking_weight(wo, wi),
+                is_smooth,
+            );
+        }
+
+        let mut wi = Vec3::new(0.0, 0.0, 1.0) + Vec3::random_unit_vector();
+        if wi.near_zero() {
+            wi = Vec3::new(0.0, 0.0, 1.0);
+        }
+        let wi = Vec3::unit_vector(wi);
+        spawn(wi, self.diffuse_color(rec, base_color, wo, wi), false)
+    }
+
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.color(PrincipledInput::Emission, rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        self.evaluate(r_in, rec, direction).0
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Float {
+        self.evaluate(r_in, rec, direction).1
+    }
+}
+
+// How the brightness of a light changes with the angle away from its surface normal.
+#[derive(Debug, Clone)]
+pub enum EmissionProfile {
+    // The same in every direction.
+    Diffuse,
+    // Full brightness within the inner cone, fading smoothly to nothing at the outer cone.
+    Spot { cos_inner: Float, cos_outer: Float },
+    // Falls off with the cosine of the angle raised to this power.
+    CosinePower(Float),
+    // Measured distribution of a real light fixture, pointing along the normal.
+    Ies(Arc<IesProfile>),
+}
+
+impl EmissionProfile {
+    pub fn new_spot(inner_angle: Float, outer_angle: Float) -> EmissionProfile {
+        // Cone angles are in degrees from the normal.
+        EmissionProfile::Spot {
+            cos_inner: degrees_to_radians(inner_angle).cos(),
+            cos_outer: degrees_to_radians(outer_angle).cos(),
+        }
+    }
+
+    pub fn value(&self, direction: Vec3, normal: Vec3) -> Float {
+        // Brightness of light leaving in unit `direction`, relative to along the normal.
+        let cos_theta = direction.dot(&normal).clamp(-1.0, 1.0);
+        match self {
+            EmissionProfile::Diffuse => 1.0,
+            EmissionProfile::Spot {
+                cos_inner,
+                cos_outer,
+            } => {
+                let t = ((cos_theta - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+                t * t * (3.0 - 2.0 * t)
+            }
+            EmissionProfile::CosinePower(exponent) => cos_theta.max(0.0).powf(*exponent),
+            EmissionProfile::Ies(profile) => {
+                let local = Onb::new(normal).to_local(direction);
+                let vertical = cos_theta.acos().to_degrees();
+                let horizontal = local.y().atan2(local.x()).to_degrees();
+                profile.value(vertical, horizontal)
+            }
+        }
+    }
+}
+
+// Light emitted from a surface. The brightness of an RGB light is its luminance in nits, where
+// (1, 1, 1) is white at 1 nit. Lights can also be given a spectrum, and a brightness in nits or
+// a power in watts. They shine from both sides of a surface unless made one-sided, and evenly in
+// every direction unless given an `EmissionProfile`.
 #[derive(Clone)]
 pub struct DiffuseLight {
     texture: Arc<dyn Texture>,
+    spectrum: Option<(Spectrum, Float)>, // Spectrum and the scale giving its radiance
+    power: Option<Float>,                // Watts to spread over the area of the shape
+    two_sided: bool,
+    profile: EmissionProfile,
 }
 
 impl DiffuseLight {
     pub fn new(albedo: Vec3) -> DiffuseLight {
         let texture = Arc::new(SolidColor::new(albedo));
-        DiffuseLight { texture }
+        DiffuseLight::new_from_texture(texture)
     }
 
     pub fn new_from_texture(texture: Arc<dyn Texture>) -> DiffuseLight {
-        DiffuseLight { texture }
+        DiffuseLight {
+            texture,
+            spectrum: None,
+            power: None,
+            two_sided: true,
+            profile: EmissionProfile::Diffuse,
+        }
+    }
+
+    pub fn new_from_spectrum(spectrum: Spectrum, nits: Float) -> DiffuseLight {
+        DiffuseLight::new(Vec3::new(0.0, 0.0, 0.0)).with_spectrum(spectrum, nits)
+    }
+
+    pub fn new_from_kelvin(kelvin: Float, nits: Float) -> DiffuseLight {
+        DiffuseLight::new_from_spectrum(Spectrum::Blackbody(kelvin), nits)
+    }
+
+    pub fn new_from_power(spectrum: Spectrum, watts: Float) -> DiffuseLight {
+        // Gives off no light until the shape it is on sets its area.
+        DiffuseLight {
+            power: Some(watts),
+            ..DiffuseLight::new_from_spectrum(spectrum, 0.0)
+        }
+    }
+
+    pub fn with_two_sided(mut self, two_sided: bool) -> DiffuseLight {
+        // A one-sided light only shines from the side its normal points to.
+        self.two_sided = two_sided;
+        self
+    }
+
+    pub fn with_profile(mut self, profile: EmissionProfile) -> DiffuseLight {
+        self.profile = profile;
+        self
+    }
+
+    fn with_spectrum(mut self, spectrum: Spectrum, nits: Float) -> DiffuseLight {
+        // RGB renders use the color of the spectrum, scaled to the same luminance.
+        let scale = nits / spectrum.to_xyz().y();
+        self.spectrum = Some((spectrum, scale));
+        self.texture = Arc::new(SolidColor::new(spectrum.to_rgb() * scale));
+        self
+    }
+
+    pub fn with_area(self, area: Float, closed: bool) -> DiffuseLight {
+        // Spreads the power evenly over `area`, in square metres, on every side that gives off
+        // light. Each point then gives off watts / (pi * area) per steradian. An emission
+        // profile only shapes this light, so the light gives off less power in total.
+        match (self.power, self.spectrum) {
+            (Some(watts), Some((spectrum, _))) => {
+                let sides = if self.two_sided && !closed { 2.0 } else { 1.0 };
+                let radiance = watts / (PI * area * sides);
+                let nits = radiance * spectrum.luminous_efficacy();
+                self.with_spectrum(spectrum, nits)
+            }
+            _ => self,
+        }
+    }
+
+    fn emission_scale(&self, r_in: &Ray, rec: &HitRecord) -> Float {
+        if !self.two_sided && !rec.front_face {
+            return 0.0;
+        }
+        let direction = -Vec3::unit_vector(r_in.direction);
+        self.profile.value(direction, rec.normal)
+    }
+
+    pub fn emitted_spectrum(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: &SampledWavelengths,
+    ) -> SampledSpectrum {
+        match self.spectrum {
+            Some((spectrum, scale)) => {
+                let scale = scale * self.emission_scale(r_in, rec);
+                SampledSpectrum::from_fn(wavelengths, |lambda| spectrum.value(lambda) * scale)
+            }
+            None => SampledSpectrum::from_rgb_illuminant(self.emitted(r_in, rec), wavelengths),
+        }
     }
 }
 
 impl Scatterable for DiffuseLight {
-    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<(Option<Ray>, Vec3)> {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<ScatterRecord> {
         None
     }
 
-    fn emitted(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
-        self.texture.value(u, v, p)
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.texture
+            .value_filtered(rec.u, rec.v, rec.p, rec.uv_differentials)
+            * self.emission_scale(r_in, rec)
+    }
+
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> Float {
+        0.0
     }
 }
 
@@ -191,13 +1063,297 @@ impl Isotropic {
 }
 
 impl Scatterable for Isotropic {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<(Option<Ray>, Vec3)> {
-        let scattered = Ray::new(rec.p, Vec3::random_unit_vector());
-        let attenuation = self.texture.value(rec.u, rec.v, rec.p);
-        Some((Some(scattered), attenuation))
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let scattered = Ray::new(rec.p, Vec3::random_unit_vector(), r_in.time);
+        let attenuation = self
+            .texture
+            .value_filtered(rec.u, rec.v, rec.p, rec.uv_differentials);
+        Some(ScatterRecord {
+            scattered,
+            attenuation,
+            is_specular: false,
+        })
     }
 
-    fn emitted(&self, _u: f64, _v: f64, _p: Vec3) -> Vec3 {
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
     }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, _direction: Vec3) -> Vec3 {
+        self.texture
+            .value_filtered(rec.u, rec.v, rec.p, rec.uv_differentials)
+            / (4.0 * PI)
+    }
+
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> Float {
+        // Scatters evenly over the whole sphere of directions.
+        1.0 / (4.0 * PI)
+    }
+}
+
+#[derive(Clone)]
+enum SurfaceDetail {
+    NormalMap(Arc<dyn Texture>, Float), // Strength
+    Bump(Arc<dyn Texture>, Float),      // Height
+}
+
+// Any material with detail too small to model drawn into its shading normal. The material
+// itself is shaded as usual, with the tilted normal in `HitRecord::normal`.
+#[derive(Clone)]
+pub struct NormalMapped {
+    material: Box<Material>,
+    detail: SurfaceDetail,
+}
+
+impl NormalMapped {
+    fn shading_normal(&self, rec: &HitRecord) -> Vec3 {
+        // Maps stacked on a material apply in turn, innermost first. The detail is worked out on
+        // the outside of the surface, so the back of a bump looks like a dent.
+        let normal = self.material.shading_normal(rec);
+        let outward = if rec.front_face { normal } else { -normal };
+        let tilted = match &self.detail {
+            SurfaceDetail::NormalMap(texture, strength) => {
+                // Colors in [0, 1] stand for tangent space directions in [-1, 1], with red
+                // along u, green along v and blue along the normal.
+                let (tangent, bitangent) = NormalMapped::tangents(rec, outward);
+                let c = texture.value_filtered(rec.u, rec.v, rec.p, rec.uv_differentials);
+                let (x, y, z) = (2.0 * c.x() - 1.0, 2.0 * c.y() - 1.0, 2.0 * c.z() - 1.0);
+                *strength * (x * tangent + y * bitangent) + z.max(0.0) * outward
+            }
+            SurfaceDetail::Bump(texture, height) => {
+                // Moves the tangents up the slope of the height, found from a step across the
+                // pixel's footprint in uv, or a small fixed step without one (PBRT's BumpMap).
+                let d = rec.uv_differentials;
+                let step = |a: Float, b: Float| {
+                    let step = 0.5 * (a.abs() + b.abs());
+                    if step > 0.0 {
+                        step
+                    } else {
+                        0.0005
+                    }
+                };
+                let (du, dv) = (step(d.dudx, d.dudy), step(d.dvdx, d.dvdy));
+                let height_at =
+                    |u: Float, v: Float, p: Vec3| gray(texture.value_filtered(u, v, p, d));
+                let here = height_at(rec.u, rec.v, rec.p);
+                let slope_u = (height_at(rec.u + du, rec.v, rec.p + du * rec.dpdu) - here) / du;
+                let slope_v = (height_at(rec.u, rec.v + dv, rec.p + dv * rec.dpdv) - here) / dv;
+                let dpdu = rec.dpdu + *height * slope_u * outward;
+                let dpdv = rec.dpdv + *height * slope_v * outward;
+                let n = Vec3::cross(&dpdu, &dpdv);
+                if n.dot(&outward) < 0.0 {
+                    -n
+                } else {
+                    n
+                }
+            }
+        };
+        if tilted.near_zero() {
+            return normal;
+        }
+        let tilted = Vec3::unit_vector(tilted);
+        if rec.front_face {
+            tilted
+        } else {
+            -tilted
+        }
+    }
+
+    fn tangents(rec: &HitRecord, normal: Vec3) -> (Vec3, Vec3) {
+        // Unit tangent along u in the plane of `normal`, and the one at right angles to it on
+        // the side v increases. Surfaces without derivatives get arbitrary tangents.
+        let frame = Onb::new_from_tangent(normal, rec.dpdu);
+        if frame.v.dot(&rec.dpdv) < 0.0 {
+            (frame.u, -frame.v)
+        } else {
+            (frame.u, frame.v)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittables::Hittable;
+    use crate::shapes::Sphere;
+    use crate::utils::Interval;
+
+    fn furnace(material: Material) -> Vec3 {
+        // A sphere lit evenly from every direction by a white background. Each path is followed
+        // until it escapes, and the average light it brings back is the fraction of light the
+        // material keeps, which is 1 for lossless materials. Paths still inside after many
+        // bounces count as lost.
+        furnace_paths(material, || {
+            // Aim at a random point inside the sphere from a random point around it.
+            let origin = Vec3::random_unit_vector() * 3.0;
+            let target = Vec3::random_in_unit_sphere();
+            Ray::new(origin, target - origin, 0.0)
+        })
+    }
+
+    fn furnace_at(material: Material, cos_theta: Float) -> Vec3 {
+        // The same, with every path arriving at the sphere at the same angle to the normal,
+        // which shows losses and gains at grazing angles the average over all angles hides.
+        let offset = (1.0 - cos_theta * cos_theta).sqrt();
+        furnace_paths(material, || {
+            Ray::new(Vec3::new(offset, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0), 0.0)
+        })
+    }
+
+    fn furnace_paths(material: Material, start: impl Fn() -> Ray) -> Vec3 {
+        let path_count = 50_000;
+        let max_bounces = 100;
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, material);
+
+        let mut total = Vec3::new(0.0, 0.0, 0.0);
+        for _ in 0..path_count {
+            let mut r = start();
+            let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+
+            for _ in 0..max_bounces {
+                let Some(rec) = sphere.hit(r, Interval::new(0.0, Float::INFINITY)) else {
+                    total = total + throughput;
+                    break;
+                };
+                let Some(scatter) = rec.material.scatter(&r, &rec) else {
+                    break;
+                };
+                throughput = throughput * scatter.attenuation;
+                r = scatter.scattered;
+            }
+        }
+        total / path_count as Float
+    }
+
+    fn assert_lossless(name: &str, material: Material) {
+        let albedo = furnace(material);
+        for c in [albedo.x(), albedo.y(), albedo.z()] {
+            assert!((c - 1.0).abs() < 0.01, "{} keeps {:?}", name, albedo);
+        }
+    }
+
+    fn assert_conserving(name: &str, material: Material) {
+        let albedo = furnace(material);
+        for c in [albedo.x(), albedo.y(), albedo.z()] {
+            assert!(c <= 1.01, "{} keeps {:?}", name, albedo);
+        }
+    }
+
+    #[test]
+    fn lambertian_furnace() {
+        let white = Material::Lambertian(Lambertian::new(Vec3::new(1.0, 1.0, 1.0)));
+        assert_lossless("white diffuse", white);
+    }
+
+    #[test]
+    fn conductor_furnace() {
+        // A huge extinction coefficient reflects everything, as an ideal mirror.
+        let mirror = Conductor::new(Vec3::new(1.0, 1.0, 1.0), Vec3::new(1e4, 1e4, 1e4), 0.0, 0.0);
+        assert_lossless("ideal mirror", Material::Conductor(mirror));
+        for (name, _, _) in Conductor::PRESETS {
+            for (roughness_u, roughness_v) in [(0.0, 0.0), (0.3, 0.3), (0.5, 0.05), (1.0, 1.0)] {
+                let metal = Conductor::new_preset(name, roughness_u, roughness_v).unwrap();
+                assert_conserving(name, Material::Conductor(metal));
+            }
+        }
+    }
+
+    #[test]
+    fn dielectric_furnace() {
+        assert_lossless("smooth glass", Material::Dielectric(Dielectric::new(1.5)));
+        assert_lossless(
+            "thin glass",
+            Material::ThinDielectric(ThinDielectric::new(1.5)),
+        );
+    }
+
+    #[test]
+    fn tint_needs_a_distance() {
+        let red = Vec3::new(0.8, 0.2, 0.2);
+        for distance in [0.0, -1.0, Float::NAN] {
+            let glass = Dielectric::new_tinted(1.5, 0.0, red, distance);
+            let a = glass.absorption;
+            assert_eq!(
+                (a.x(), a.y(), a.z()),
+                (0.0, 0.0, 0.0),
+                "distance {}",
+                distance
+            );
+        }
+        let glass = Dielectric::new_tinted(1.5, 0.0, red, 2.0);
+        assert!((glass.absorption.y() - (-(0.2 as Float).ln() / 2.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rough_dielectric_furnace() {
+        // Light scattering between microfacets more than once is lost, so rough glass falls a
+        // little short.
+        for roughness in [0.1, 0.3, 0.6, 1.0] {
+            let glass = Material::Dielectric(Dielectric::new_rough(1.5, roughness));
+            assert_conserving("rough glass", glass);
+        }
+    }
+
+    #[test]
+    fn principled_furnace() {
+        use PrincipledInput::*;
+
+        let white = || Principled::new(Vec3::new(1.0, 1.0, 1.0));
+        let smooth = [
+            ("white", white()),
+            ("white with clear coat", white().with_value(Clearcoat, 1.0)),
+            ("white with sheen", white().with_value(Sheen, 1.0)),
+            ("white metal", white().with_value(Metallic, 1.0)),
+            ("clear glass", white().with_value(Transmission, 1.0)),
+        ];
+        for (name, material) in smooth {
+            let material = material.with_value(Roughness, 0.0);
+            assert_lossless(name, Material::Principled(material));
+        }
+
+        let rough = [
+            ("rough", white().with_value(Roughness, 0.6)),
+            (
+                "rough with clear coat and sheen",
+                white()
+                    .with_value(Clearcoat, 1.0)
+                    .with_value(ClearcoatRoughness, 0.3)
+                    .with_value(Sheen, 1.0),
+            ),
+            (
+                "rough half metal",
+                white().with_value(Metallic, 0.5).with_value(Roughness, 0.4),
+            ),
+            (
+                "rough glass",
+                white()
+                    .with_value(Transmission, 1.0)
+                    .with_value(Roughness, 0.4),
+            ),
+            (
+                "half transmissive, specular 1",
+                white()
+                    .with_value(Transmission, 0.5)
+                    .with_value(Specular, 1.0),
+            ),
+        ];
+        for (name, material) in rough {
+            assert_conserving(name, Material::Principled(material));
+        }
+
+        // Sheen is strongest at grazing angles, where it must not add to a white base. Without
+        // specular reflection all of the light reaches the diffuse layer.
+        for cos_theta in [0.5, 0.2, 0.05] {
+            let sheen = white().with_value(Sheen, 1.0).with_value(Specular, 0.0);
+            let albedo = furnace_at(Material::Principled(sheen), cos_theta);
+            for c in [albedo.x(), albedo.y(), albedo.z()] {
+                assert!(
+                    (c - 1.0).abs() < 0.01,
+                    "sheen at cos {} keeps {:?}",
+                    cos_theta,
+                    albedo
+                );
+            }
+        }
+    }
 }
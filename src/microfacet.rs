@@ -0,0 +1,233 @@
+use crate::utils::{random_double, Float, PI};
+use crate::vec3::Vec3;
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals. All directions are in the local
+// shading frame, where the surface normal is the z axis.
+#[derive(Debug, Clone, Copy)]
+pub struct TrowbridgeReitz {
+    alpha_x: Float,
+    alpha_y: Float,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(roughness_x: Float, roughness_y: Float) -> TrowbridgeReitz {
+        // Roughness in [0, 1] is squared into the width of the distribution, which spreads
+        // perceived roughness more evenly.
+        let to_alpha = |roughness: Float| roughness.clamp(0.0, 1.0).powi(2);
+        TrowbridgeReitz {
+            alpha_x: to_alpha(roughness_x),
+            alpha_y: to_alpha(roughness_y),
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        // Below this width the distribution is sampled as a perfect mirror.
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    pub fn d(&self, wm: Vec3) -> Float {
+        // Density of microfacets with normal `wm`.
+        let cos2_theta = wm.z() * wm.z();
+        if cos2_theta <= 0.0 {
+            return 0.0;
+        }
+        let e = (wm.x() / self.alpha_x).powi(2) + (wm.y() / self.alpha_y).powi(2) + cos2_theta;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    pub fn lambda(&self, w: Vec3) -> Float {
+        // Smith's auxiliary function for the height-correlated masking of direction `w`.
+        let cos2_theta = w.z() * w.z();
+        if cos2_theta <= 0.0 {
+            return Float::INFINITY;
+        }
+        let alpha2_tan2_theta =
+            ((self.alpha_x * w.x()).powi(2) + (self.alpha_y * w.y()).powi(2)) / cos2_theta;
+        ((1.0 + alpha2_tan2_theta).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: Vec3) -> Float {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> Float {
+        // Fraction of microfacets visible from both directions.
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    pub fn sample_visible_normal(&self, w: Vec3) -> Vec3 {
+        // Like `sample_wm`, but a smooth surface always uses the surface normal.
+        if self.is_smooth() {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            self.sample_wm(w)
+        }
+    }
+
+    pub fn masking_weight(&self, wo: Vec3, wi: Vec3) -> Float {
+        // Weight of a direction found through a normal from `sample_visible_normal`.
+        if self.is_smooth() {
+            1.0
+        } else {
+            self.g(wo, wi) / self.g1(wo)
+        }
+    }
+
+    pub fn reflection(&self, wo: Vec3, wi: Vec3) -> (Vec3, Float) {
+        // Light from unit direction `wi` reflected towards unit `wo`, times the cosine at `wi`
+        // but without the Fresnel term, and the microfacet normal that does the reflecting.
+        match self.reflected_normal(wo, wi) {
+            Some(wm) => (wm, self.d(wm) * self.g(wo, wi) / (4.0 * wo.z())),
+            None => (Vec3::new(0.0, 0.0, 1.0), 0.0),
+        }
+    }
+
+    pub fn transmission(&self, wo: Vec3, wi: Vec3, eta: Float) -> (Vec3, Float) {
+        // Like `reflection`, for light refracted from `wi` below the surface, where `eta` is the
+        // index of refraction below over the one above.
+        match self.refracted_normal(wo, wi, eta) {
+            Some(wm) => {
+                let (cos_om, cos_im) = (wo.dot(&wm), wi.dot(&wm));
+                let denominator = (cos_im + cos_om / eta).powi(2);
+                let value =
+                    self.d(wm) * self.g(wo, wi) * (cos_im * cos_om).abs() / (wo.z() * denominator);
+                (wm, value)
+            }
+            None => (Vec3::new(0.0, 0.0, 1.0), 0.0),
+        }
+    }
+
+    pub fn reflection_pdf(&self, wo: Vec3, wi: Vec3) -> (Vec3, Float) {
+        // Density of reflecting `wo` into `wi` through a normal from `sample_visible_normal`.
+        match self.reflected_normal(wo, wi) {
+            Some(wm) => (wm, self.visible_normal_pdf(wo, wm) / (4.0 * wo.dot(&wm))),
+            None => (Vec3::new(0.0, 0.0, 1.0), 0.0),
+        }
+    }
+
+    pub fn transmission_pdf(&self, wo: Vec3, wi: Vec3, eta: Float) -> (Vec3, Float) {
+        // Density of refracting `wo` into `wi` through a normal from `sample_visible_normal`.
+        match self.refracted_normal(wo, wi, eta) {
+            Some(wm) => {
+                let (cos_om, cos_im) = (wo.dot(&wm), wi.dot(&wm));
+                let denominator = (cos_im + cos_om / eta).powi(2);
+                (
+                    wm,
+                    self.visible_normal_pdf(wo, wm) * cos_im.abs() / denominator,
+                )
+            }
+            None => (Vec3::new(0.0, 0.0, 1.0), 0.0),
+        }
+    }
+
+    fn visible_normal_pdf(&self, wo: Vec3, wm: Vec3) -> Float {
+        // Density of `sample_wm` picking `wm`.
+        self.g1(wo) * wo.dot(&wm).max(0.0) * self.d(wm) / wo.z()
+    }
+
+    fn reflected_normal(&self, wo: Vec3, wi: Vec3) -> Option<Vec3> {
+        // Microfacet normal that reflects `wi` into `wo`. A smooth surface only reflects along
+        // the mirror direction, which a given `wi` never hits.
+        let wm = wo + wi;
+        if self.is_smooth() || wo.z() <= 0.0 || wi.z() <= 0.0 || wm.near_zero() {
+            return None;
+        }
+        Some(Vec3::unit_vector(wm))
+    }
+
+    fn refracted_normal(&self, wo: Vec3, wi: Vec3, eta: Float) -> Option<Vec3> {
+        let wm = wi * eta + wo;
+        if self.is_smooth() || wo.z() <= 0.0 || wi.z() >= 0.0 || wm.near_zero() {
+            return None;
+        }
+        let mut wm = Vec3::unit_vector(wm);
+        if wm.z() < 0.0 {
+            wm = -wm;
+        }
+
+        // Microfacets facing away from either direction can't refract one into the other.
+        if wo.dot(&wm) <= 0.0 || wi.dot(&wm) >= 0.0 {
+            return None;
+        }
+        Some(wm)
+    }
+
+    pub fn sample_wm(&self, w: Vec3) -> Vec3 {
+        // Samples a microfacet normal visible from `w`, in proportion to its projected area
+        // (Heitz 2018). Weighting by G(wo, wi) / G1(wo) then gives an unbiased estimate.
+        let mut wh =
+            Vec3::unit_vector(Vec3::new(self.alpha_x * w.x(), self.alpha_y * w.y(), w.z()));
+        if wh.z() < 0.0 {
+            wh = -wh;
+        }
+
+        let t1 = if wh.z() < 0.99999 {
+            Vec3::unit_vector(Vec3::cross(&Vec3::new(0.0, 0.0, 1.0), &wh))
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vec3::cross(&wh, &t1);
+
+        // Sample the projected hemisphere as a disk, squashed on the side facing away from `w`.
+        let r = random_double().sqrt();
+        let phi = 2.0 * PI * random_double();
+        let (x, y) = (r * phi.cos(), r * phi.sin());
+        let h = (1.0 - x * x).sqrt();
+        let s = (1.0 + wh.z()) / 2.0;
+        let y = (1.0 - s) * h + s * y;
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+
+        let nh = x * t1 + y * t2 + z * wh;
+        Vec3::unit_vector(Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        ))
+    }
+}
+
+pub fn fresnel_dielectric(cos_theta_i: Float, eta: Float) -> Float {
+    // Unpolarized Fresnel reflectance at the boundary of a dielectric, where `eta` is the index
+    // of refraction on the far side of the boundary over the one on the near side.
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        // Total internal reflection.
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+pub fn fresnel_complex(cos_theta_i: Float, eta: Float, k: Float) -> Float {
+    // Unpolarized Fresnel reflectance of a conductor with complex index of refraction eta + ik,
+    // seen from a medium with index 1.
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let cos2 = cos_theta_i * cos_theta_i;
+    let sin2 = 1.0 - cos2;
+
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * a * cos_theta_i;
+    let r_s = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+
+    (r_s + r_p) / 2.0
+}
+
+pub fn fresnel_complex_rgb(cos_theta_i: Float, eta: Vec3, k: Vec3) -> Vec3 {
+    Vec3::new(
+        fresnel_complex(cos_theta_i, eta.x(), k.x()),
+        fresnel_complex(cos_theta_i, eta.y(), k.y()),
+        fresnel_complex(cos_theta_i, eta.z(), k.z()),
+    )
+}
@@ -0,0 +1,330 @@
+use crate::image::Image;
+use crate::utils::Float;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// What texture lookups outside [0, 1] see.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+    Repeat, // The image tiles the plane
+    Mirror, // Every other tile is flipped, so the edges always match
+    Clamp,  // The edge pixels stretch out forever
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    Nearest,   // The closest pixel, blocky up close
+    Bilinear,  // Blend of the four closest pixels
+    Bicubic,   // Catmull-Rom spline through the sixteen closest pixels, sharper up close
+    Trilinear, // Bilinear at the two mip levels closest to the size of the pixel footprint
+    Ewa,       // Gaussian over the elliptical footprint, sharp at grazing angles (Heckbert 1989)
+}
+
+// Largest ratio of the axes of the footprint `Ewa` filters. Longer footprints are widened,
+// which blurs them a little but bounds the number of pixels read.
+const MAX_ANISOTROPY: Float = 8.0;
+
+// Texels are kept in single precision whatever `Float` is, which halves the memory they take.
+type Component = f32;
+
+struct Level {
+    width: usize,
+    height: usize,
+    texels: Vec<[Component; 3]>,
+}
+
+// Pyramid of ever smaller copies of an image, each half the size of the one before it down to a
+// single pixel, so a lookup can read from the copy whose pixels match the area it covers.
+// Coordinates (s, t) run from (0, 0) at the top left of the image to (1, 1) at the bottom right.
+#[derive(Clone)]
+pub struct MipMap {
+    levels: Arc<Vec<Level>>, // Shared between copies, which can wrap differently
+    alpha: Option<Arc<Vec<Component>>>, // Alpha of the full size image, if it has any
+    wrap: WrapMode,
+}
+
+impl MipMap {
+    pub fn new(image: &Image, wrap: WrapMode) -> MipMap {
+        let (width, height) = (image.width.max(1) as usize, image.height.max(1) as usize);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let c = image.pixel(x as i32, y as i32);
+                texels.push([c.x() as Component, c.y() as Component, c.z() as Component]);
+            }
+        }
+
+        let mut levels = vec![Level {
+            width,
+            height,
+            texels,
+        }];
+
+        // Each pixel of a level is the average of the two by two block below it. Odd sizes
+        // round up, and blocks that hang off the edge reuse the edge pixels.
+        while let Some(last) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+            let (width, height) = (last.width.div_ceil(2), last.height.div_ceil(2));
+            let mut texels = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    let mut sum = [0.0; 3];
+                    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let sx = (2 * x + dx).min(last.width - 1);
+                        let sy = (2 * y + dy).min(last.height - 1);
+                        let texel = last.texels[sy * last.width + sx];
+                        for c in 0..3 {
+                            sum[c] += texel[c] / 4.0;
+                        }
+                    }
+                    texels.push(sum);
+                }
+            }
+            levels.push(Level {
+                width,
+                height,
+                texels,
+            });
+        }
+        // Alpha is only read for cutouts, which test single points, so it isn't filtered.
+        let alpha = image.has_alpha().then(|| {
+            let mut alpha = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    alpha.push(image.alpha(x as i32, y as i32) as Component);
+                }
+            }
+            Arc::new(alpha)
+        });
+
+        MipMap {
+            levels: Arc::new(levels),
+            alpha,
+            wrap,
+        }
+    }
+
+    pub fn set_wrap(&mut self, wrap: WrapMode) {
+        self.wrap = wrap;
+    }
+
+    pub fn filter(
+        &self,
+        filter: FilterMode,
+        s: Float,
+        t: Float,
+        dst0: (Float, Float),
+        dst1: (Float, Float),
+    ) -> Vec3 {
+        // Looks up (s, t), where the footprint of the lookup spans `dst0` and `dst1`.
+        match filter {
+            FilterMode::Nearest => self.nearest(0, s, t),
+            FilterMode::Bilinear => self.bilinear(0, s, t),
+            FilterMode::Bicubic => self.bicubic(s, t),
+            FilterMode::Trilinear => {
+                let width = 2.0
+                    * dst0
+                        .0
+                        .abs()
+                        .max(dst0.1.abs())
+                        .max(dst1.0.abs())
+                        .max(dst1.1.abs());
+                self.trilinear(s, t, width)
+            }
+            FilterMode::Ewa => self.ewa(s, t, dst0, dst1),
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.alpha.is_some()
+    }
+
+    pub fn alpha(&self, s: Float, t: Float) -> Float {
+        // Bilinear blend of the alpha of the full size image, 1 for images without alpha.
+        let Some(alpha) = &self.alpha else {
+            return 1.0;
+        };
+        let Level { width, height, .. } = self.levels[0];
+        let (x, y) = (s * width as Float - 0.5, t * height as Float - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let at = |x: i64, y: i64| alpha[self.index(0, x, y)] as Float;
+        (1.0 - dx) * (1.0 - dy) * at(x0, y0)
+            + dx * (1.0 - dy) * at(x0 + 1, y0)
+            + (1.0 - dx) * dy * at(x0, y0 + 1)
+            + dx * dy * at(x0 + 1, y0 + 1)
+    }
+
+    fn index(&self, level: usize, x: i64, y: i64) -> usize {
+        // Position in a level of the pixel at (x, y), after wrapping.
+        let level = &self.levels[level];
+        let wrap = |i: i64, size: usize| -> usize {
+            let size = size as i64;
+            let i = match self.wrap {
+                WrapMode::Repeat => i.rem_euclid(size),
+                WrapMode::Mirror => {
+                    let i = i.rem_euclid(2 * size);
+                    if i >= size {
+                        2 * size - 1 - i
+                    } else {
+                        i
+                    }
+                }
+                WrapMode::Clamp => i.clamp(0, size - 1),
+            };
+            i as usize
+        };
+        wrap(y, level.height) * level.width + wrap(x, level.width)
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> Vec3 {
+        let texel = self.levels[level].texels[self.index(level, x, y)];
+        Vec3::new(texel[0] as Float, texel[1] as Float, texel[2] as Float)
+    }
+
+    fn nearest(&self, level: usize, s: Float, t: Float) -> Vec3 {
+        let Level { width, height, .. } = self.levels[level];
+        let x = (s * width as Float).floor() as i64;
+        let y = (t * height as Float).floor() as i64;
+        self.texel(level, x, y)
+    }
+
+    fn bilinear(&self, level: usize, s: Float, t: Float) -> Vec3 {
+        // Pixel centers sit half a pixel in from their corners.
+        let Level { width, height, .. } = self.levels[level];
+        let (x, y) = (s * width as Float - 0.5, t * height as Float - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        (1.0 - dx) * (1.0 - dy) * self.texel(level, x0, y0)
+            + dx * (1.0 - dy) * self.texel(level, x0 + 1, y0)
+            + (1.0 - dx) * dy * self.texel(level, x0, y0 + 1)
+            + dx * dy * self.texel(level, x0 + 1, y0 + 1)
+    }
+
+    fn bicubic(&self, s: Float, t: Float) -> Vec3 {
+        let Level { width, height, .. } = self.levels[0];
+        let (x, y) = (s * width as Float - 0.5, t * height as Float - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let weights = |f: Float| {
+            [
+                ((-0.5 * f + 1.0) * f - 0.5) * f,
+                (1.5 * f - 2.5) * f * f + 1.0,
+                ((-1.5 * f + 2.0) * f + 0.5) * f,
+                (0.5 * f - 0.5) * f * f,
+            ]
+        };
+        let (wx, wy) = (weights(x - x0), weights(y - y0));
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        for (j, wy) in wy.iter().enumerate() {
+            for (i, wx) in wx.iter().enumerate() {
+                let texel = self.texel(0, x0 - 1 + i as i64, y0 - 1 + j as i64);
+                sum = sum + texel * (wx * wy);
+            }
+        }
+        // The spline overshoots next to sharp edges.
+        Vec3::new(sum.x().max(0.0), sum.y().max(0.0), sum.z().max(0.0))
+    }
+
+    fn trilinear(&self, s: Float, t: Float, width: Float) -> Vec3 {
+        // Picks the level where a pixel is `width` across, and blends the levels on either side.
+        let top = (self.levels.len() - 1) as Float;
+        let level = top + width.max(1e-8).log2();
+        if level <= 0.0 {
+            return self.bilinear(0, s, t);
+        }
+        if level >= top {
+            return self.bilinear(self.levels.len() - 1, s, t);
+        }
+        let below = level.floor();
+        let f = level - below;
+        let below = below as usize;
+        (1.0 - f) * self.bilinear(below, s, t) + f * self.bilinear(below + 1, s, t)
+    }
+
+    fn ewa(&self, s: Float, t: Float, dst0: (Float, Float), dst1: (Float, Float)) -> Vec3 {
+        let length = |d: (Float, Float)| (d.0 * d.0 + d.1 * d.1).sqrt();
+        let (major_axis, mut minor_axis) = if length(dst0) >= length(dst1) {
+            (dst0, dst1)
+        } else {
+            (dst1, dst0)
+        };
+        let (major, mut minor) = (length(major_axis), length(minor_axis));
+
+        if minor * MAX_ANISOTROPY < major && minor > 0.0 {
+            let scale = major / (minor * MAX_ANISOTROPY);
+            minor_axis = (minor_axis.0 * scale, minor_axis.1 * scale);
+            minor *= scale;
+        }
+        if minor == 0.0 {
+            return self.bilinear(0, s, t);
+        }
+
+        // The level is picked by the minor axis, so the ellipse is a few pixels across there.
+        let top = (self.levels.len() - 1) as Float;
+        let level = (top + minor.log2()).max(0.0);
+        let below = level.floor();
+        let f = level - below;
+        let below = below as usize;
+        let at = |level: usize| self.ewa_level(level, s, t, major_axis, minor_axis);
+        (1.0 - f) * at(below) + f * at(below + 1)
+    }
+
+    fn ewa_level(
+        &self,
+        level: usize,
+        s: Float,
+        t: Float,
+        dst0: (Float, Float),
+        dst1: (Float, Float),
+    ) -> Vec3 {
+        if level >= self.levels.len() {
+            return self.texel(self.levels.len() - 1, 0, 0);
+        }
+        let Level { width, height, .. } = self.levels[level];
+        let (w, h) = (width as Float, height as Float);
+        let (x, y) = (s * w - 0.5, t * h - 0.5);
+        let (ds0, dt0, ds1, dt1) = (dst0.0 * w, dst0.1 * h, dst1.0 * w, dst1.1 * h);
+
+        // Implicit equation of the ellipse, A s^2 + B s t + C t^2 < 1, widened by a pixel so it
+        // always covers at least one.
+        let mut a = dt0 * dt0 + dt1 * dt1 + 1.0;
+        let mut b = -2.0 * (ds0 * dt0 + ds1 * dt1);
+        let mut c = ds0 * ds0 + ds1 * ds1 + 1.0;
+        let inverse_f = 1.0 / (a * c - b * b * 0.25);
+        a *= inverse_f;
+        b *= inverse_f;
+        c *= inverse_f;
+
+        // Bounding box of the ellipse in pixels.
+        let determinant = -b * b + 4.0 * a * c;
+        let inverse_determinant = 1.0 / determinant;
+        let (u_sqrt, v_sqrt) = ((determinant * c).sqrt(), (a * determinant).sqrt());
+        let x0 = (x - 2.0 * inverse_determinant * u_sqrt).ceil() as i64;
+        let x1 = (x + 2.0 * inverse_determinant * u_sqrt).floor() as i64;
+        let y0 = (y - 2.0 * inverse_determinant * v_sqrt).ceil() as i64;
+        let y1 = (y + 2.0 * inverse_determinant * v_sqrt).floor() as i64;
+
+        let alpha: Float = 2.0;
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        let mut weight_sum = 0.0;
+        for j in y0..=y1 {
+            let dy = j as Float - y;
+            for i in x0..=x1 {
+                let dx = i as Float - x;
+                let r2 = a * dx * dx + b * dx * dy + c * dy * dy;
+                if r2 < 1.0 {
+                    let weight = (-alpha * r2).exp() - (-alpha).exp();
+                    sum = sum + self.texel(level, i, j) * weight;
+                    weight_sum += weight;
+                }
+            }
+        }
+        if weight_sum <= 0.0 {
+            return self.bilinear(level, s, t);
+        }
+        sum / weight_sum
+    }
+}
@@ -0,0 +1,303 @@
+use crate::utils::Float;
+use crate::vec3::Vec3;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+const TABLE_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseKind {
+    Perlin,         // Random gradients blended with a cubic, as in Ray Tracing: The Next Week
+    ImprovedPerlin, // Perlin's 2002 version, with fixed gradients and a smoother quintic blend
+    Simplex,        // Perlin's simplex noise, which has fewer grid artifacts
+    Worley,         // Distance to the nearest of randomly scattered points
+}
+
+// Lattice noise evaluated at any point in space. Every kind returns values in about [-1, 1],
+// which change over a distance of about 1. The same seed always gives the same pattern.
+#[derive(Debug, Clone)]
+pub struct Noise {
+    kind: NoiseKind,
+    permutation: Vec<usize>, // Shuffled indices, repeated once so lookups don't need to wrap
+    gradients: Vec<Vec3>,    // Random unit vectors, for `NoiseKind::Perlin`
+    seed: u32,
+}
+
+impl Noise {
+    pub fn new(kind: NoiseKind, seed: u64) -> Noise {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut permutation: Vec<usize> = (0..TABLE_SIZE).collect();
+        permutation.shuffle(&mut rng);
+        permutation.extend_from_within(..);
+
+        let gradients = (0..TABLE_SIZE)
+            .map(|_| loop {
+                let v = Vec3::new(
+                    rng.random_range(-1.0..1.0),
+                    rng.random_range(-1.0..1.0),
+                    rng.random_range(-1.0..1.0),
+                );
+                if v.length_squared() > 1e-3 && v.length_squared() <= 1.0 {
+                    break Vec3::unit_vector(v);
+                }
+            })
+            .collect();
+
+        Noise {
+            kind,
+            permutation,
+            gradients,
+            seed: rng.random(),
+        }
+    }
+
+    pub fn value(&self, p: Vec3) -> Float {
+        match self.kind {
+            NoiseKind::Perlin => self.perlin(p),
+            NoiseKind::ImprovedPerlin => self.improved_perlin(p),
+            NoiseKind::Simplex => self.simplex(p),
+            NoiseKind::Worley => 2.0 * self.worley(p).0 - 1.0,
+        }
+    }
+
+    fn hash(&self, i: i32, j: i32, k: i32) -> usize {
+        let p = &self.permutation;
+        p[p[p[(i & 255) as usize] + (j & 255) as usize] + (k & 255) as usize]
+    }
+
+    fn perlin(&self, p: Vec3) -> Float {
+        let (i, j, k) = (p.x().floor(), p.y().floor(), p.z().floor());
+        let (u, v, w) = (p.x() - i, p.y() - j, p.z() - k);
+        let (i, j, k) = (i as i32, j as i32, k as i32);
+
+        // Hermite cubic blend between the gradients at the corners of the cell.
+        let smooth = |t: Float| t * t * (3.0 - 2.0 * t);
+        let (uu, vv, ww) = (smooth(u), smooth(v), smooth(w));
+
+        let mut sum = 0.0;
+        for (di, dj, dk) in corners() {
+            let gradient = self.gradients[self.hash(i + di, j + dj, k + dk)];
+            let weight = Vec3::new(u - di as Float, v - dj as Float, w - dk as Float);
+            sum += (di as Float * uu + (1 - di) as Float * (1.0 - uu))
+                * (dj as Float * vv + (1 - dj) as Float * (1.0 - vv))
+                * (dk as Float * ww + (1 - dk) as Float * (1.0 - ww))
+                * gradient.dot(&weight);
+        }
+        sum
+    }
+
+    fn improved_perlin(&self, p: Vec3) -> Float {
+        let (i, j, k) = (p.x().floor(), p.y().floor(), p.z().floor());
+        let (x, y, z) = (p.x() - i, p.y() - j, p.z() - k);
+        let (i, j, k) = (i as i32, j as i32, k as i32);
+
+        let fade = |t: Float| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let lerp = |t: Float, a: Float, b: Float| a + t * (b - a);
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let g = |di: i32, dj: i32, dk: i32| {
+            let hash = self.hash(i + di, j + dj, k + dk);
+            improved_gradient(hash, x - di as Float, y - dj as Float, z - dk as Float)
+        };
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, g(0, 0, 0), g(1, 0, 0)),
+                lerp(u, g(0, 1, 0), g(1, 1, 0)),
+            ),
+            lerp(
+                v,
+                lerp(u, g(0, 0, 1), g(1, 0, 1)),
+                lerp(u, g(0, 1, 1), g(1, 1, 1)),
+            ),
+        )
+    }
+
+    fn simplex(&self, p: Vec3) -> Float {
+        // Skews space so that each cube splits into six tetrahedra, then sums a falloff around
+        // the four corners of the tetrahedron `p` is in (Gustavson's implementation).
+        const F3: Float = 1.0 / 3.0;
+        const G3: Float = 1.0 / 6.0;
+        let s = (p.x() + p.y() + p.z()) * F3;
+        let (i, j, k) = (
+            (p.x() + s).floor(),
+            (p.y() + s).floor(),
+            (p.z() + s).floor(),
+        );
+        let t = (i + j + k) * G3;
+        let x0 = Vec3::new(p.x() - (i - t), p.y() - (j - t), p.z() - (k - t));
+        let (i, j, k) = (i as i32, j as i32, k as i32);
+
+        // Order of the axes from largest to smallest offset picks the tetrahedron.
+        let (o1, o2) = if x0.x() >= x0.y() {
+            if x0.y() >= x0.z() {
+                ((1, 0, 0), (1, 1, 0))
+            } else if x0.x() >= x0.z() {
+                ((1, 0, 0), (1, 0, 1))
+            } else {
+                ((0, 0, 1), (1, 0, 1))
+            }
+        } else if x0.y() < x0.z() {
+            ((0, 0, 1), (0, 1, 1))
+        } else if x0.x() < x0.z() {
+            ((0, 1, 0), (0, 1, 1))
+        } else {
+            ((0, 1, 0), (1, 1, 0))
+        };
+
+        let mut sum = 0.0;
+        for (n, (di, dj, dk)) in [(0, 0, 0), o1, o2, (1, 1, 1)].into_iter().enumerate() {
+            let g = n as Float * G3;
+            let d = x0 - Vec3::new(di as Float - g, dj as Float - g, dk as Float - g);
+            let falloff = 0.6 - d.length_squared();
+            if falloff > 0.0 {
+                let hash = self.hash(i + di, j + dj, k + dk);
+                sum += falloff.powi(4) * improved_gradient(hash, d.x(), d.y(), d.z());
+            }
+        }
+        32.0 * sum
+    }
+
+    pub fn worley(&self, p: Vec3) -> (Float, Float, Float) {
+        // Distances to the nearest and second nearest feature points, and a random number in
+        // [0, 1) belonging to the nearest one. Each unit cell holds one point, somewhere random
+        // within it.
+        let (i, j, k) = (
+            p.x().floor() as i32,
+            p.y().floor() as i32,
+            p.z().floor() as i32,
+        );
+        let (mut f1, mut f2, mut id) = (Float::INFINITY, Float::INFINITY, 0.0);
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let cell = (i + di, j + dj, k + dk);
+                    let feature = Vec3::new(
+                        cell.0 as Float + self.cell_random(cell, 0),
+                        cell.1 as Float + self.cell_random(cell, 1),
+                        cell.2 as Float + self.cell_random(cell, 2),
+                    );
+                    let distance = (feature - p).length();
+                    if distance < f1 {
+                        (f2, f1) = (f1, distance);
+                        id = self.cell_random(cell, 3);
+                    } else if distance < f2 {
+                        f2 = distance;
+                    }
+                }
+            }
+        }
+        (f1.min(1.0), f2.min(1.5), id)
+    }
+
+    fn cell_random(&self, (i, j, k): (i32, i32, i32), channel: u32) -> Float {
+        // Random number in [0, 1) for a cell, the same every time it is asked for.
+        let mut h = self.seed ^ channel.wrapping_mul(0x9e3779b9);
+        for c in [i, j, k] {
+            h = (h ^ c as u32).wrapping_mul(0x85ebca6b);
+            h ^= h >> 13;
+            h = h.wrapping_mul(0xc2b2ae35);
+            h ^= h >> 16;
+        }
+        (h >> 8) as Float / (1 << 24) as Float
+    }
+}
+
+fn corners() -> impl Iterator<Item = (i32, i32, i32)> {
+    (0..8).map(|n| (n & 1, (n >> 1) & 1, (n >> 2) & 1))
+}
+
+fn improved_gradient(hash: usize, x: Float, y: Float, z: Float) -> Float {
+    // Dot product with one of the 12 vectors from the center of a cube to its edges.
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fractal {
+    Fbm,        // Plain sum of octaves, for clouds and gentle variation
+    Turbulence, // Sum of absolute values, which creases where the noise crosses zero
+    Ridged,     // Turbulence turned upside down, for sharp ridges and veins
+}
+
+// Noise summed over several octaves, each at `lacunarity` times the frequency and `gain` times
+// the amplitude of the last. Values are scaled to about [0, 1].
+#[derive(Debug, Clone)]
+pub struct FractalNoise {
+    noise: Noise,
+    fractal: Fractal,
+    octaves: u32,
+    lacunarity: Float,
+    gain: Float,
+}
+
+impl FractalNoise {
+    pub fn new(kind: NoiseKind, fractal: Fractal) -> FractalNoise {
+        FractalNoise {
+            noise: Noise::new(kind, 0),
+            fractal,
+            octaves: 7,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> FractalNoise {
+        self.noise = Noise::new(self.noise.kind, seed);
+        self
+    }
+
+    pub fn with_octaves(mut self, octaves: u32) -> FractalNoise {
+        self.octaves = octaves.max(1);
+        self
+    }
+
+    pub fn with_lacunarity(mut self, lacunarity: Float) -> FractalNoise {
+        self.lacunarity = lacunarity;
+        self
+    }
+
+    pub fn with_gain(mut self, gain: Float) -> FractalNoise {
+        self.gain = gain;
+        self
+    }
+
+    pub fn noise(&self) -> &Noise {
+        &self.noise
+    }
+
+    pub fn value(&self, p: Vec3) -> Float {
+        let (mut sum, mut total) = (0.0, 0.0);
+        let (mut frequency, mut amplitude) = (1.0, 1.0);
+        let mut weight = 1.0;
+        for _ in 0..self.octaves {
+            let n = self.noise.value(p * frequency);
+            let octave = match self.fractal {
+                Fractal::Fbm => 0.5 * (n + 1.0),
+                Fractal::Turbulence => n.abs(),
+                Fractal::Ridged => {
+                    // Each octave is faded where the one before it is low, which keeps the
+                    // valleys between ridges smooth.
+                    let ridge = (1.0 - n.abs()).powi(2) * weight;
+                    weight = ridge.clamp(0.0, 1.0);
+                    ridge
+                }
+            };
+            sum += amplitude * octave;
+            total += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        (sum / total).clamp(0.0, 1.0)
+    }
+}
@@ -0,0 +1,49 @@
+use crate::utils::Float;
+use crate::vec3::Vec3;
+
+// Orthonormal basis with `w` along a surface normal. Materials work in this local frame, where
+// the normal is the z axis, and convert their directions back to world space.
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn new(n: Vec3) -> Onb {
+        // Builds the tangents without branches or normalization (Duff et al. 2017).
+        // NOTE: the parameter `n` is assumed to have unit length.
+        let sign = Float::copysign(1.0, n.z());
+        let a = -1.0 / (sign + n.z());
+        let b = n.x() * n.y() * a;
+        let u = Vec3::new(1.0 + sign * n.x() * n.x() * a, sign * b, -sign * n.x());
+        let v = Vec3::new(b, sign + n.y() * n.y() * a, -n.y());
+        Onb { u, v, w: n }
+    }
+
+    pub fn new_from_tangent(n: Vec3, tangent: Vec3) -> Onb {
+        // Builds the basis with `u` along `tangent` made perpendicular to `n`, so that it follows
+        // a surface's parameterization, such as its dpdu. Falls back to `new` where the tangent
+        // is zero or along the normal.
+        // NOTE: the parameter `n` is assumed to have unit length.
+        let u = tangent - n * n.dot(&tangent);
+        if u.near_zero() {
+            return Onb::new(n);
+        }
+        let u = Vec3::unit_vector(u);
+        Onb {
+            u,
+            v: Vec3::cross(&n, &u),
+            w: n,
+        }
+    }
+
+    pub fn to_local(self, a: Vec3) -> Vec3 {
+        Vec3::new(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+
+    pub fn to_world(self, a: Vec3) -> Vec3 {
+        a.x() * self.u + a.y() * self.v + a.z() * self.w
+    }
+}
@@ -1,17 +1,25 @@
+use crate::utils::Float;
 use crate::vec3::Vec3;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    pub time: Float, // Moment within the camera shutter interval the ray exists at
+    pub wavelength: Float, // Hero wavelength in nanometres when rendering spectrally, otherwise 0
 }
 
 impl Ray {
-    pub fn new(origin: Vec3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+    pub fn new(origin: Vec3, direction: Vec3, time: Float) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time,
+            wavelength: 0.0,
+        }
     }
 
-    pub fn at(&self, t: f64) -> Vec3 {
+    pub fn at(&self, t: Float) -> Vec3 {
         self.origin + (self.direction * t)
     }
 }
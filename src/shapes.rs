@@ -2,9 +2,73 @@ use crate::aabb::Aabb;
 use crate::hittables::{HitRecord, Hittable};
 use crate::material::Material;
 use crate::ray::Ray;
-use crate::utils::{map_uv_to_range, Interval};
+use crate::textures::UvDifferentials;
+use crate::utils::{gamma, map_uv_to_range, Float, Interval, PI};
 use crate::vec3::Vec3;
-use std::f64::consts::PI;
+
+fn box_face_normal(t: Float, slab_t: [Float; 3], direction: Vec3, entering: bool) -> Vec3 {
+    // Returns the outward normal of the box face on the slab whose entry or exit distance is `t`.
+    // A ray enters a slab through its min face when travelling in the positive direction.
+    let axis = slab_t.iter().position(|&slab| slab == t).unwrap_or(2);
+    let sign = if (direction[axis] > 0.0) == entering {
+        -1.0
+    } else {
+        1.0
+    };
+    match axis {
+        0 => Vec3::new(sign, 0.0, 0.0),
+        1 => Vec3::new(0.0, sign, 0.0),
+        _ => Vec3::new(0.0, 0.0, sign),
+    }
+}
+
+fn box_hit_point(
+    r: Ray,
+    t: Float,
+    outward_normal: Vec3,
+    min_bound: Vec3,
+    max_bound: Vec3,
+) -> (Vec3, Vec3) {
+    // Returns the hit point on a box face and the bound on its rounding error. The coordinate
+    // across the face is snapped onto it, so only its own rounding remains.
+    let p = r.at(t);
+    let p_error = (r.origin.abs() + (r.direction * t).abs()) * gamma(5);
+    let snap = |axis: usize| {
+        if outward_normal[axis] > 0.0 {
+            (max_bound[axis], gamma(1) * max_bound[axis].abs())
+        } else if outward_normal[axis] < 0.0 {
+            (min_bound[axis], gamma(1) * min_bound[axis].abs())
+        } else {
+            (p[axis], p_error[axis])
+        }
+    };
+    let (x, x_error) = snap(0);
+    let (y, y_error) = snap(1);
+    let (z, z_error) = snap(2);
+    (Vec3::new(x, y, z), Vec3::new(x_error, y_error, z_error))
+}
+
+fn box_face_tangents(outward_normal: Vec3, dimensions: Vec3) -> (Vec3, Vec3) {
+    // Returns how far a point on a box face moves per unit of u and v, for the layout of
+    // `Cuboid::get_cuboid_uv`, where each face covers a quarter of the width of the texture and
+    // a third of its height.
+    let (x, y, z) = (
+        Vec3::new(4.0 * dimensions.x(), 0.0, 0.0),
+        Vec3::new(0.0, 3.0 * dimensions.y(), 0.0),
+        Vec3::new(0.0, 0.0, 4.0 * dimensions.z()),
+    );
+    if outward_normal.x() > 0.0 {
+        (-z, y)
+    } else if outward_normal.x() < 0.0 {
+        (z, y)
+    } else if outward_normal.y() > 0.0 {
+        (x, -z * 0.75)
+    } else if outward_normal.y() < 0.0 {
+        (x, z * 0.75)
+    } else {
+        (x, y)
+    }
+}
 
 pub struct Cuboid {
     center: Vec3,
@@ -16,16 +80,16 @@ pub struct Cuboid {
 impl Cuboid {
     pub fn new(center: Vec3, dimensions: Vec3, material: Material) -> Cuboid {
         let dimensions = Vec3::new(
-            f64::max(0.0, dimensions.x()),
-            f64::max(0.0, dimensions.y()),
-            f64::max(0.0, dimensions.z()),
+            Float::max(0.0, dimensions.x()),
+            Float::max(0.0, dimensions.y()),
+            Float::max(0.0, dimensions.z()),
         );
         let half_dimensions = dimensions / 2.0;
         let bbox = Aabb::new_from_vec3(center - half_dimensions, center + half_dimensions);
         Cuboid {
             center,
             dimensions,
-            material,
+            material: material.with_emitter_area(Cuboid::area(dimensions), true),
             bbox,
         }
     }
@@ -48,12 +112,21 @@ impl Cuboid {
         Cuboid {
             center,
             dimensions,
-            material,
+            material: material.with_emitter_area(Cuboid::area(dimensions), true),
             bbox,
         }
     }
 
-    fn get_cuboid_uv(p_relative_to_center: Vec3, dimensions: Vec3) -> (f64, f64) {
+    fn area(dimensions: Vec3) -> Float {
+        let (x, y, z) = (dimensions.x(), dimensions.y(), dimensions.z());
+        2.0 * (x * y + y * z + z * x)
+    }
+
+    fn get_cuboid_uv(
+        p_relative_to_center: Vec3,
+        dimensions: Vec3,
+        outward_normal: Vec3,
+    ) -> (Float, Float) {
         // Define UV ranges for each face of the cuboid
         let top_uv_range = ((0.25, 0.666666), (0.5, 1.0));
         let bottom_uv_range = ((0.25, 0.0), (0.5, 0.333333));
@@ -67,36 +140,33 @@ impl Cuboid {
         let half_height = dimensions.y() / 2.0;
         let half_depth = dimensions.z() / 2.0;
 
-        // Tolerance to handle floating-point precision issues
-        let tolerance = 1e-8;
-
         // Face mapping and UV calculation
-        if (p_relative_to_center.x() - half_width).abs() < tolerance {
+        if outward_normal.x() > 0.0 {
             // Right face
             let u = 1.0 - (p_relative_to_center.z() + half_depth) / dimensions.z();
             let v = (p_relative_to_center.y() + half_height) / dimensions.y();
             map_uv_to_range(u, v, &right_uv_range)
-        } else if (p_relative_to_center.x() + half_width).abs() < tolerance {
+        } else if outward_normal.x() < 0.0 {
             // Left face
             let u = (p_relative_to_center.z() + half_depth) / dimensions.z();
             let v = (p_relative_to_center.y() + half_height) / dimensions.y();
             map_uv_to_range(u, v, &left_uv_range)
-        } else if (p_relative_to_center.y() - half_height).abs() < tolerance {
+        } else if outward_normal.y() > 0.0 {
             // Top face
             let u = (p_relative_to_center.x() + half_width) / dimensions.x();
             let v = 1.0 - (p_relative_to_center.z() + half_depth) / dimensions.z();
             map_uv_to_range(u, v, &top_uv_range)
-        } else if (p_relative_to_center.y() + half_height).abs() < tolerance {
+        } else if outward_normal.y() < 0.0 {
             // Bottom face
             let u = (p_relative_to_center.x() + half_width) / dimensions.x();
             let v = (p_relative_to_center.z() + half_depth) / dimensions.z();
             map_uv_to_range(u, v, &bottom_uv_range)
-        } else if (p_relative_to_center.z() - half_depth).abs() < tolerance {
+        } else if outward_normal.z() > 0.0 {
             // Front face
             let u = (p_relative_to_center.x() + half_width) / dimensions.x();
             let v = (p_relative_to_center.y() + half_height) / dimensions.y();
             map_uv_to_range(u, v, &front_uv_range)
-        } else if (p_relative_to_center.z() + half_depth).abs() < tolerance {
+        } else if outward_normal.z() < 0.0 {
             // Back face
             let u = (p_relative_to_center.x() + half_width) / dimensions.x();
             let v = (p_relative_to_center.y() + half_height) / dimensions.y();
@@ -108,7 +178,7 @@ impl Cuboid {
 }
 
 impl Hittable for Cuboid {
-    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
         // Half the dimensions of the cuboid for calculations
         let half_dimensions = self.dimensions / 2.0;
 
@@ -145,42 +215,41 @@ impl Hittable for Cuboid {
         }
 
         // Set the intersection time to t_min if it's within range, otherwise use t_max
-        let t = if t_min < ray_t.min { t_max } else { t_min };
+        let t = if t_min <= ray_t.min { t_max } else { t_min };
 
         // Check if the valid t is within the ray interval
-        if t < ray_t.min || t > ray_t.max {
+        if !ray_t.surrounds(t) {
             return None;
         }
 
-        // Calculate the intersection point
-        let p = r.at(t);
-
-        // Determine the outward normal based on the intersection point
-        let outward_normal = if (p.x() - max_bound.x()).abs() < f64::EPSILON {
-            Vec3::new(1.0, 0.0, 0.0)
-        } else if (p.x() - min_bound.x()).abs() < f64::EPSILON {
-            Vec3::new(-1.0, 0.0, 0.0)
-        } else if (p.y() - max_bound.y()).abs() < f64::EPSILON {
-            Vec3::new(0.0, 1.0, 0.0)
-        } else if (p.y() - min_bound.y()).abs() < f64::EPSILON {
-            Vec3::new(0.0, -1.0, 0.0)
-        } else if (p.z() - max_bound.z()).abs() < f64::EPSILON {
-            Vec3::new(0.0, 0.0, 1.0)
+        // Determine the outward normal from the slab the ray enters or leaves through. Comparing
+        // the intersection point against the bounds fails once the point is rounded.
+        let outward_normal = if t == t_min {
+            box_face_normal(t, [t_min_x, t_min_y, t_min_z], r.direction, true)
         } else {
-            Vec3::new(0.0, 0.0, -1.0)
+            box_face_normal(t, [t_max_x, t_max_y, t_max_z], r.direction, false)
         };
 
+        // Calculate the intersection point
+        let (p, p_error) = box_hit_point(r, t, outward_normal, min_bound, max_bound);
+
         let p_relative_to_center = p - self.center;
-        let (u, v) = Cuboid::get_cuboid_uv(p_relative_to_center, self.dimensions);
+        let (u, v) = Cuboid::get_cuboid_uv(p_relative_to_center, self.dimensions, outward_normal);
+        let (dpdu, dpdv) = box_face_tangents(outward_normal, self.dimensions);
 
         let mut rec = HitRecord {
             p,
+            p_error,
             normal: outward_normal,
+            geometric_normal: outward_normal,
             t,
             front_face: false,
             material: &self.material,
             u,
             v,
+            dpdu,
+            dpdv,
+            uv_differentials: UvDifferentials::default(),
         };
 
         rec.set_face_normal(r, outward_normal);
@@ -195,25 +264,39 @@ impl Hittable for Cuboid {
 
 pub struct Sphere {
     center: Vec3,
-    radius: f64,
+    motion: Vec3, // Displacement of the center between time 0 and time 1
+    radius: Float,
     material: Material,
     bbox: Aabb,
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius: f64, material: Material) -> Sphere {
-        let radius = f64::max(0.0, radius);
+    pub fn new(center: Vec3, radius: Float, material: Material) -> Sphere {
+        Sphere::new_moving(center, center, radius, material)
+    }
+
+    pub fn new_moving(center1: Vec3, center2: Vec3, radius: Float, material: Material) -> Sphere {
+        // The center moves linearly from `center1` at time 0 to `center2` at time 1.
+        let radius = Float::max(0.0, radius);
         let radius_vec = Vec3::new(radius, radius, radius);
-        let bbox = Aabb::new_from_vec3(center - radius_vec, center + radius_vec);
+        let box1 = Aabb::new_from_vec3(center1 - radius_vec, center1 + radius_vec);
+        let box2 = Aabb::new_from_vec3(center2 - radius_vec, center2 + radius_vec);
         Sphere {
-            center,
+            center: center1,
+            motion: center2 - center1,
             radius,
-            material,
-            bbox,
+            material: material.with_emitter_area(4.0 * PI * radius * radius, true),
+            bbox: Aabb::new_from_aabb(box1, box2),
         }
     }
 
-    fn get_sphere_uv(p: Vec3) -> (f64, f64) {
+    fn center_at(&self, time: Float) -> Vec3 {
+        // Outside time 0 to 1 the sphere holds still at the nearer end of its motion, as an
+        // `AnimatedTransform` holds its end keyframes, so it never leaves its bounding box.
+        self.center + self.motion * time.clamp(0.0, 1.0)
+    }
+
+    fn get_sphere_uv(p: Vec3) -> (Float, Float) {
         // p: a given point on the sphere of radius one, centered at the origin.
         // u: returned value [0,1] of angle around the Y axis from X=-1.
         // v: returned value [0,1] of angle from Y=-1 to Y=+1.
@@ -221,8 +304,8 @@ impl Sphere {
         //     <0 1 0> yields <0.50 1.00>       < 0 -1  0> yields <0.50 0.00>
         //     <0 0 1> yields <0.25 0.50>       < 0  0 -1> yields <0.75 0.50>
 
-        let theta = f64::acos(-p.y());
-        let phi = f64::atan2(-p.z(), p.x()) + PI;
+        let theta = Float::acos(-p.y());
+        let phi = Float::atan2(-p.z(), p.x()) + PI;
 
         let u = phi / (2.0 * PI);
         let v = theta / PI;
@@ -232,39 +315,78 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: Ray, ray_t: Interval) -> Option<HitRecord> {
-        let oc = ray.origin - self.center;
+    fn hit(&self, ray: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let center = self.center_at(ray.time);
+        let oc = ray.origin - center;
         let a = ray.direction.length_squared();
         let half_b = oc.dot(&ray.direction);
         let c = oc.length_squared() - self.radius * self.radius;
-        let discriminant = (half_b * half_b) - (a * c) + f64::EPSILON;
-
-        if discriminant >= 0.0 {
-            let sqrtd = discriminant.sqrt();
-            let root_a = ((-half_b) - sqrtd) / a;
-            let root_b = ((-half_b) + sqrtd) / a;
-            for root in [root_a, root_b].iter() {
-                if *root < ray_t.max && *root > ray_t.min {
-                    let p = ray.at(*root);
-                    let outward_normal = (p - self.center) / self.radius;
-                    let front_face = ray.direction.dot(&outward_normal) < 0.0;
-                    let (u, v) = Sphere::get_sphere_uv(outward_normal);
-
-                    return Some(HitRecord {
-                        t: *root,
-                        p,
-                        normal: if front_face {
-                            outward_normal
-                        } else {
-                            -outward_normal
-                        },
-                        front_face,
-                        material: &self.material,
-                        u,
-                        v,
-                    });
-                }
+
+        // Computing the discriminant from the distance between the center and the line of the
+        // ray avoids the cancellation in `half_b * half_b - a * c` for distant spheres.
+        let closest = (oc - ray.direction * (half_b / a)).length();
+        let discriminant = a * (self.radius - closest) * (self.radius + closest);
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        // Solve the quadratic in a form that doesn't subtract nearly equal values.
+        let sqrtd = discriminant.sqrt();
+        let q = if half_b > 0.0 {
+            -(half_b + sqrtd)
+        } else {
+            -(half_b - sqrtd)
+        };
+        let (root_a, root_b) = (q / a, c / q);
+        let roots = if root_a <= root_b {
+            [root_a, root_b]
+        } else {
+            [root_b, root_a]
+        };
+
+        for root in roots {
+            if !ray_t.surrounds(root) {
+                continue;
             }
+
+            // Project the hit point back onto the sphere, which leaves only a few roundings of
+            // error in it.
+            let offset = ray.at(root) - center;
+            let offset = offset * (self.radius / offset.length());
+            let p = center + offset;
+            let p_error = offset.abs() * gamma(5) + p.abs() * gamma(1);
+
+            let outward_normal = offset / self.radius;
+            let front_face = ray.direction.dot(&outward_normal) < 0.0;
+            let (u, v) = Sphere::get_sphere_uv(outward_normal);
+
+            // Derivatives of the point on the sphere with its longitude and latitude, which
+            // `get_sphere_uv` spreads over 2 pi and pi.
+            let (sin_phi, cos_phi) = (2.0 * PI * u).sin_cos();
+            let (sin_theta, cos_theta) = (PI * v).sin_cos();
+            let dpdu = Vec3::new(offset.z(), 0.0, -offset.x()) * (2.0 * PI);
+            let dpdv = Vec3::new(-cos_theta * cos_phi, sin_theta, cos_theta * sin_phi)
+                * (PI * self.radius);
+
+            let normal = if front_face {
+                outward_normal
+            } else {
+                -outward_normal
+            };
+            return Some(HitRecord {
+                t: root,
+                p,
+                p_error,
+                normal,
+                geometric_normal: normal,
+                front_face,
+                material: &self.material,
+                u,
+                v,
+                dpdu,
+                dpdv,
+                uv_differentials: UvDifferentials::default(),
+            });
         }
         None
     }
@@ -276,25 +398,29 @@ impl Hittable for Sphere {
 
 pub struct Cube {
     center: Vec3,
-    size: f64,
+    size: Float,
     material: Material,
     bbox: Aabb,
 }
 
 impl Cube {
-    pub fn new(center: Vec3, size: f64, material: Material) -> Cube {
-        let size = f64::max(0.0, size);
+    pub fn new(center: Vec3, size: Float, material: Material) -> Cube {
+        let size = Float::max(0.0, size);
         let half_size = Vec3::new(size / 2.0, size / 2.0, size / 2.0);
         let bbox = Aabb::new_from_vec3(center - half_size, center + half_size);
         Cube {
             center,
             size,
-            material,
+            material: material.with_emitter_area(6.0 * size * size, true),
             bbox,
         }
     }
 
-    fn get_cube_uv(p_relative_to_center: Vec3, half_size: f64) -> (f64, f64) {
+    fn get_cube_uv(
+        p_relative_to_center: Vec3,
+        half_size: Float,
+        outward_normal: Vec3,
+    ) -> (Float, Float) {
         // Define UV ranges for each face
         let top_uv_range = ((0.25, 0.666666), (0.5, 1.0));
         let bottom_uv_range = ((0.25, 0.0), (0.5, 0.333333));
@@ -303,36 +429,33 @@ impl Cube {
         let front_uv_range = ((0.25, 0.333333), (0.5, 0.666666));
         let back_uv_range = ((0.75, 0.333333), (1.0, 0.666666));
 
-        // Tolerance to handle floating-point precision issues
-        let tolerance = 1e-8;
-
         // Face mapping and UV calculation
-        if (p_relative_to_center.x() - half_size).abs() < tolerance {
+        if outward_normal.x() > 0.0 {
             // Right face
             let u = 1.0 - (p_relative_to_center.z() + half_size) / (2.0 * half_size);
             let v = (p_relative_to_center.y() + half_size) / (2.0 * half_size);
             map_uv_to_range(u, v, &right_uv_range)
-        } else if (p_relative_to_center.x() + half_size).abs() < tolerance {
+        } else if outward_normal.x() < 0.0 {
             // Left face
             let u = (p_relative_to_center.z() + half_size) / (2.0 * half_size);
             let v = (p_relative_to_center.y() + half_size) / (2.0 * half_size);
             map_uv_to_range(u, v, &left_uv_range)
-        } else if (p_relative_to_center.y() - half_size).abs() < tolerance {
+        } else if outward_normal.y() > 0.0 {
             // Top face
             let u = (p_relative_to_center.x() + half_size) / (2.0 * half_size);
             let v = 1.0 - (p_relative_to_center.z() + half_size) / (2.0 * half_size);
             map_uv_to_range(u, v, &top_uv_range)
-        } else if (p_relative_to_center.y() + half_size).abs() < tolerance {
+        } else if outward_normal.y() < 0.0 {
             // Bottom face
             let u = (p_relative_to_center.x() + half_size) / (2.0 * half_size);
             let v = (p_relative_to_center.z() + half_size) / (2.0 * half_size);
             map_uv_to_range(u, v, &bottom_uv_range)
-        } else if (p_relative_to_center.z() - half_size).abs() < tolerance {
+        } else if outward_normal.z() > 0.0 {
             // Front face
             let u = (p_relative_to_center.x() + half_size) / (2.0 * half_size);
             let v = (p_relative_to_center.y() + half_size) / (2.0 * half_size);
             map_uv_to_range(u, v, &front_uv_range)
-        } else if (p_relative_to_center.z() + half_size).abs() < tolerance {
+        } else if outward_normal.z() < 0.0 {
             // Back face
             let u = (p_relative_to_center.x() + half_size) / (2.0 * half_size);
             let v = (p_relative_to_center.y() + half_size) / (2.0 * half_size);
@@ -344,7 +467,7 @@ impl Cube {
 }
 
 impl Hittable for Cube {
-    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
         // Half the size of the cube for calculations
         let half_size = self.size / 2.0;
 
@@ -381,44 +504,42 @@ impl Hittable for Cube {
         }
 
         // Set the intersection time to t_min if it's within range, otherwise use t_max
-        let t = if t_min < ray_t.min { t_max } else { t_min };
+        let t = if t_min <= ray_t.min { t_max } else { t_min };
 
         // Check if the valid t is within the ray interval
-        if t < ray_t.min || t > ray_t.max {
+        if !ray_t.surrounds(t) {
             return None;
         }
 
-        // Calculate the intersection point
-        let p = r.at(t);
-
-        // Determine the outward normal based on the intersection point
-        let tolerance = 1e-8;
-        let outward_normal = if (p.x() - max_bound.x()).abs() < tolerance {
-            Vec3::new(1.0, 0.0, 0.0)
-        } else if (p.x() - min_bound.x()).abs() < tolerance {
-            Vec3::new(-1.0, 0.0, 0.0)
-        } else if (p.y() - max_bound.y()).abs() < tolerance {
-            Vec3::new(0.0, 1.0, 0.0)
-        } else if (p.y() - min_bound.y()).abs() < tolerance {
-            Vec3::new(0.0, -1.0, 0.0)
-        } else if (p.z() - max_bound.z()).abs() < tolerance {
-            Vec3::new(0.0, 0.0, 1.0)
+        // Determine the outward normal from the slab the ray enters or leaves through.
+        let outward_normal = if t == t_min {
+            box_face_normal(t, [t_min_x, t_min_y, t_min_z], r.direction, true)
         } else {
-            Vec3::new(0.0, 0.0, -1.0)
+            box_face_normal(t, [t_max_x, t_max_y, t_max_z], r.direction, false)
         };
 
+        // Calculate the intersection point
+        let (p, p_error) = box_hit_point(r, t, outward_normal, min_bound, max_bound);
+
         let p_relative_to_center = p - self.center;
-        let (u, v) = Cube::get_cube_uv(p_relative_to_center, half_size);
+        let (u, v) = Cube::get_cube_uv(p_relative_to_center, half_size, outward_normal);
+        let size = 2.0 * half_size;
+        let (dpdu, dpdv) = box_face_tangents(outward_normal, Vec3::new(size, size, size));
 
         // Create the hit record
         let mut rec = HitRecord {
             p,
+            p_error,
             normal: outward_normal,
+            geometric_normal: outward_normal,
             t,
             front_face: false,
             material: &self.material,
             u,
             v,
+            dpdu,
+            dpdv,
+            uv_differentials: UvDifferentials::default(),
         };
 
         // Set the face normal in the hit record
@@ -439,7 +560,7 @@ pub struct Quad {
     material: Material,
     bbox: Aabb,
     normal: Vec3,
-    d: f64,
+    d: Float,
     w: Vec3,
 }
 
@@ -454,7 +575,7 @@ impl Quad {
             q,
             u,
             v,
-            material,
+            material: material.with_emitter_area(n.length(), false),
             bbox: Aabb::default(),
             normal,
             d,
@@ -471,7 +592,7 @@ impl Quad {
         self.bbox = Aabb::new_from_aabb(bbox_diagonal1, bbox_diagonal2);
     }
 
-    fn is_interior(a: f64, b: f64, rec: &mut HitRecord) -> bool {
+    fn is_interior(a: Float, b: Float, rec: &mut HitRecord) -> bool {
         let unit_interval = Interval::new(0.0, 1.0);
         // Given the hit point in plane coordinates, return false if it is outside the
         // primitive, otherwise set the hit record UV coordinates and return true.
@@ -487,17 +608,17 @@ impl Quad {
 }
 
 impl Hittable for Quad {
-    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
         let denom = Vec3::dot(&self.normal, &r.direction);
 
         // No hit if the ray is parallel to the plane.
-        if f64::abs(denom) < 1e-8 {
+        if Float::abs(denom) < 1e-8 {
             return None;
         }
 
         // Return none if the hit point parameter t is outside the ray interval.
         let t = (self.d - Vec3::dot(&self.normal, &r.origin)) / denom;
-        if !ray_t.contains(t) {
+        if !ray_t.surrounds(t) {
             return None;
         }
 
@@ -507,14 +628,23 @@ impl Hittable for Quad {
         let alpha = Vec3::dot(&self.w, &Vec3::cross(&planar_hitpt_vector, &self.v));
         let beta = Vec3::dot(&self.w, &Vec3::cross(&self.u, &planar_hitpt_vector));
 
+        // The distance of the point from the plane is off by a few roundings of the terms of
+        // the plane equation and of the ray, which are all bounded by the magnitudes below.
+        let p_error = (r.origin.abs() + (r.direction * t).abs()) * gamma(7);
+
         let mut rec = HitRecord {
             p: intersection,
+            p_error,
             normal: self.normal,
+            geometric_normal: self.normal,
             t,
             front_face: false,
             material: &self.material,
             u: 0.0,
             v: 0.0,
+            dpdu: self.u,
+            dpdv: self.v,
+            uv_differentials: UvDifferentials::default(),
         };
 
         if !Quad::is_interior(alpha, beta, &mut rec) {
@@ -530,3 +660,29 @@ impl Hittable for Quad {
         self.bbox
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    #[test]
+    fn moving_sphere_stays_in_its_box() {
+        // A sphere moving from x = 0 to x = 4 is hit at its end positions by rays at times
+        // before 0 and after 1, which lie inside its bounding box.
+        let white = Material::Lambertian(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+        let sphere = Sphere::new_moving(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(4.0, 0.0, 0.0),
+            1.0,
+            white,
+        );
+        let ray_t = Interval::new(0.0, Float::INFINITY);
+        for (time, x) in [(-0.5, 0.0), (0.5, 2.0), (1.5, 4.0)] {
+            let r = Ray::new(Vec3::new(x, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), time);
+            let rec = sphere.hit(r, ray_t).unwrap();
+            assert!((rec.p - Vec3::new(x, 0.0, 1.0)).length() < 1e-4);
+            assert!(sphere.bounding_box().hit(r, ray_t));
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use crate::distribution::Distribution2D;
+use crate::environment::{direction_to_equirect, equirect_pdf, equirect_to_direction, Environment};
+use crate::lights::{Light, LightSample};
+use crate::onb::Onb;
+use crate::spectrum::{blackbody, cie_xyz, xyz_to_linear_srgb, LAMBDA_MAX, LAMBDA_MIN};
+use crate::utils::{degrees_to_radians, random_double, Float, PI};
+use crate::vec3::Vec3;
+
+// Size of the table of sky radiance that directions are sampled from.
+const TABLE_WIDTH: usize = 128;
+const TABLE_HEIGHT: usize = 64;
+
+// Angular radius of the sun, and its luminance above the atmosphere in nits.
+const SUN_RADIUS: Float = 0.2665;
+const SUN_LUMINANCE: Float = 2.0e9;
+
+// Daylight sky from the analytic model of Preetham, Shirley and Smits (1999), with the sun as a
+// disk in it. The world is laid out with y up, x pointing east and -z pointing north. Radiance is
+// in nits, so a clear sky at noon is many thousands of times brighter than a light of 1, and
+// scenes scale it down with `with_intensity`. Below the horizon is an even gray ground lit by the
+// sky and the sun.
+pub struct Sky {
+    sun_frame: Onb, // Frame whose normal points towards the sun
+    cos_sun_radius: Float,
+    sun_radiance: Vec3,
+    theta_sun: Float,
+    zenith: Vec3, // Luminance and chromaticity of the zenith, as (Y, x, y)
+    perez: [[Float; 5]; 3],
+    ground_albedo: Float,
+    ground: Vec3,
+    intensity: Float,
+    sun_chance: Float, // How often `sample` picks the sun rather than the sky
+    distribution: Distribution2D,
+}
+
+impl Sky {
+    pub fn new(sun_direction: Vec3, turbidity: Float) -> Sky {
+        // Turbidity is the haziness of the air, from 2 for a very clear sky to 10 for a hazy one.
+        let sun_direction = Vec3::unit_vector(sun_direction);
+        let t = turbidity.clamp(2.0, 10.0);
+
+        // The model is only fitted for the sun above the horizon, so a sun that has set leaves
+        // the sky as it is at sunset.
+        let theta_sun = sun_direction.y().clamp(0.0, 1.0).acos();
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance = 1000.0 * ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192);
+        let chromaticity = |m: [[Float; 4]; 3]| {
+            let powers = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+            let row = |r: [Float; 4]| r.iter().zip(powers).map(|(a, b)| a * b).sum::<Float>();
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        let sky = Sky {
+            sun_frame: Onb::new(sun_direction),
+            cos_sun_radius: degrees_to_radians(SUN_RADIUS).cos(),
+            sun_radiance: Self::sun_radiance(theta_sun, t),
+            theta_sun,
+            zenith: Vec3::new(zenith_luminance.max(0.0), zenith_x, zenith_y),
+            perez,
+            ground_albedo: 0.2,
+            ground: Vec3::new(0.0, 0.0, 0.0),
+            intensity: 1.0,
+            sun_chance: 0.0,
+            distribution: Distribution2D::new(&[0.0], 1, 1),
+        };
+        sky.update()
+    }
+
+    pub fn new_from_date(
+        day_of_year: Float,
+        solar_time: Float,
+        latitude: Float,
+        turbidity: Float,
+    ) -> Sky {
+        // Places the sun for a day of the year from 1 to 365, a local solar time in hours where
+        // the sun is highest at 12, and a latitude in degrees, positive to the north.
+        let latitude = degrees_to_radians(latitude);
+        let declination = 0.4093 * (2.0 * PI * (day_of_year - 81.0) / 368.0).sin();
+        let hour_angle = PI * (solar_time - 12.0) / 12.0;
+
+        let up = latitude.sin() * declination.sin()
+            + latitude.cos() * declination.cos() * hour_angle.cos();
+        let east = -declination.cos() * hour_angle.sin();
+        let north = latitude.cos() * declination.sin()
+            - latitude.sin() * declination.cos() * hour_angle.cos();
+        Sky::new(Vec3::new(east, up, -north), turbidity)
+    }
+
+    pub fn with_intensity(mut self, intensity: Float) -> Sky {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_ground_albedo(mut self, albedo: Float) -> Sky {
+        self.ground_albedo = albedo;
+        self.update()
+    }
+
+    fn sun_radiance(theta_sun: Float, turbidity: Float) -> Vec3 {
+        // Sunlight is a 5778K blackbody dimmed by the air it passes through: Rayleigh scattering
+        // takes out the blue, and haze takes out every wavelength more evenly.
+        if theta_sun >= PI / 2.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let air_mass =
+            1.0 / (theta_sun.cos() + 0.15 * (93.885 - theta_sun.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * turbidity - 0.04586;
+
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        let mut luminance = 0.0;
+        let mut lambda = LAMBDA_MIN + 0.5;
+        while lambda < LAMBDA_MAX {
+            let microns = lambda / 1000.0;
+            let rayleigh = (-0.008735 * microns.powf(-4.08) * air_mass).exp();
+            let aerosol = (-beta * microns.powf(-1.3) * air_mass).exp();
+            let value = blackbody(lambda, 5778.0);
+            xyz = xyz + cie_xyz(lambda) * value * rayleigh * aerosol;
+            luminance += cie_xyz(lambda).y() * value;
+            lambda += 1.0;
+        }
+        xyz_to_linear_srgb(xyz * (SUN_LUMINANCE / luminance))
+    }
+
+    fn perez(coefficients: [Float; 5], cos_theta: Float, gamma: Float) -> Float {
+        let [a, b, c, d, e] = coefficients;
+        (1.0 + a * (b / cos_theta.max(1e-3)).exp())
+            * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    }
+
+    fn sky_radiance(&self, direction: Vec3) -> Vec3 {
+        // Radiance of the sky without the sun disk, or of the ground below the horizon.
+        if direction.y() < 0.0 {
+            return self.ground;
+        }
+        let cos_theta = direction.y();
+        let gamma = direction.dot(&self.sun_frame.w).clamp(-1.0, 1.0).acos();
+        let relative = |i: usize| {
+            Self::perez(self.perez[i], cos_theta, gamma)
+                / Self::perez(self.perez[i], 1.0, self.theta_sun)
+        };
+        let luminance = self.zenith.x() * relative(0);
+        let x = self.zenith.y() * relative(1);
+        let y = self.zenith.z() * relative(2);
+        if y <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+
+        let xyz = Vec3::new(x / y, 1.0, (1.0 - x - y) / y) * luminance;
+        let rgb = xyz_to_linear_srgb(xyz);
+        Vec3::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+    }
+
+    fn update(mut self) -> Sky {
+        // Tabulates the sky to light the ground and to pick directions from. The sun is left
+        // out of the table and sampled on its own, since it is far too small for the table to
+        // resolve.
+        let luminance = |c: Vec3| 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z();
+        let cell = 2.0 * PI * PI / (TABLE_WIDTH * TABLE_HEIGHT) as Float;
+        let sun_solid_angle = 2.0 * PI * (1.0 - self.cos_sun_radius);
+
+        let mut table = vec![Vec3::new(0.0, 0.0, 0.0); TABLE_WIDTH * TABLE_HEIGHT];
+        let mut sky_irradiance = Vec3::new(0.0, 0.0, 0.0);
+        for y in 0..TABLE_HEIGHT / 2 {
+            let v = (y as Float + 0.5) / TABLE_HEIGHT as Float;
+            let sin_theta = (PI * v).sin();
+            for x in 0..TABLE_WIDTH {
+                let u = (x as Float + 0.5) / TABLE_WIDTH as Float;
+                let direction = equirect_to_direction(u, v);
+                let radiance = self.sky_radiance(direction);
+                sky_irradiance = sky_irradiance + radiance * direction.y() * sin_theta * cell;
+                table[y * TABLE_WIDTH + x] = radiance;
+            }
+        }
+
+        // The ground reflects light evenly, so its radiance is its albedo times the light that
+        // falls on it over pi.
+        let sun_irradiance = self.sun_radiance * sun_solid_angle * self.sun_frame.w.y().max(0.0);
+        self.ground = (sky_irradiance + sun_irradiance) * self.ground_albedo / PI;
+        for radiance in table.iter_mut().skip(TABLE_WIDTH * TABLE_HEIGHT / 2) {
+            *radiance = self.ground;
+        }
+
+        let mut func = Vec::with_capacity(table.len());
+        let mut sky_power = 0.0;
+        for (i, radiance) in table.iter().enumerate() {
+            let v = ((i / TABLE_WIDTH) as Float + 0.5) / TABLE_HEIGHT as Float;
+            let value = luminance(*radiance) * (PI * v).sin();
+            sky_power += value * cell;
+            func.push(value);
+        }
+        let sun_power = luminance(self.sun_radiance) * sun_solid_angle;
+        self.sun_chance = if sun_power > 0.0 {
+            sun_power / (sun_power + sky_power)
+        } else {
+            0.0
+        };
+        self.distribution = Distribution2D::new(&func, TABLE_WIDTH, TABLE_HEIGHT);
+        self
+    }
+
+    fn in_sun(&self, direction: Vec3) -> bool {
+        // Whether the direction sees the sun. The part of its disk below the horizon is hidden
+        // by the ground.
+        let direction = Vec3::unit_vector(direction);
+        direction.y() >= 0.0 && direction.dot(&self.sun_frame.w) >= self.cos_sun_radius
+    }
+}
+
+impl Environment for Sky {
+    fn radiance(&self, direction: Vec3) -> Vec3 {
+        let direction = Vec3::unit_vector(direction);
+        let mut radiance = self.sky_radiance(direction);
+        if self.in_sun(direction) {
+            radiance = radiance + self.sun_radiance;
+        }
+        radiance * self.intensity
+    }
+
+    fn pdf(&self, direction: Vec3) -> Float {
+        // Density over solid angle of `sample` picking `direction`, by either of its strategies.
+        let (u, v) = direction_to_equirect(direction);
+        let sky_pdf = equirect_pdf(self.distribution.pdf(u, v), v);
+        let sun_pdf = if self.in_sun(direction) {
+            1.0 / (2.0 * PI * (1.0 - self.cos_sun_radius))
+        } else {
+            0.0
+        };
+        self.sun_chance * sun_pdf + (1.0 - self.sun_chance) * sky_pdf
+    }
+
+    fn as_light(&self) -> &dyn Light {
+        self
+    }
+}
+
+impl Light for Sky {
+    fn sample(&self, _p: Vec3) -> Option<LightSample> {
+        let direction = if random_double() < self.sun_chance {
+            // Evenly within the disk of the sun.
+            let cos_theta = 1.0 - random_double() * (1.0 - self.cos_sun_radius);
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * random_double();
+            let local = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+            let direction = self.sun_frame.to_world(local);
+
+            // Directions in the part of the sun below the horizon see the ground, which the
+            // other strategy covers, so they are given up rather than counted twice.
+            if direction.y() < 0.0 {
+                return None;
+            }
+            direction
+        } else {
+            let (u, v, _) = self.distribution.sample(random_double(), random_double());
+            equirect_to_direction(u, v)
+        };
+
+        let pdf = self.pdf(direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance: Float::INFINITY,
+            irradiance: self.radiance(direction) / pdf,
+            pdf: Some(pdf),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sun_below_the_horizon_is_hidden() {
+        // A sun setting, with the lower part of its disk already below the horizon.
+        let sky = Sky::new(Vec3::new(1.0, 0.002, 0.0), 3.0);
+        assert!(sky.sun_chance > 0.0);
+        let above = Vec3::unit_vector(Vec3::new(1.0, 0.003, 0.0));
+        let below = Vec3::unit_vector(Vec3::new(1.0, -0.001, 0.0));
+        assert!(sky.in_sun(above) && !sky.in_sun(below));
+
+        // Only the sky strategy can pick the hidden part, so its density is far lower.
+        assert!(sky.pdf(below) * 10.0 < sky.pdf(above));
+        let sun_radiance = sky.sun_radiance * sky.intensity;
+        assert!((sky.radiance(above) - sky.radiance(below)).length() > 0.5 * sun_radiance.length());
+
+        // Samples the sun strategy would put below the horizon are given up.
+        for _ in 0..10_000 {
+            if let Some(sample) = sky.sample(Vec3::new(0.0, 0.0, 0.0)) {
+                assert_eq!(sample.pdf, Some(sky.pdf(sample.direction)));
+                if sample.direction.y() < 0.0 {
+                    assert!(sample.direction.dot(&sky.sun_frame.w) < sky.cos_sun_radius);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,386 @@
+// Published and fitted constants are kept as they are, even where single precision can't hold
+// them.
+#![allow(clippy::excessive_precision)]
+
+use crate::utils::Float;
+use crate::vec3::Vec3;
+use std::ops::{Add, Mul};
+
+// Range of wavelengths in nanometres that paths are traced at when rendering spectrally.
+pub const LAMBDA_MIN: Float = 360.0;
+pub const LAMBDA_MAX: Float = 830.0;
+
+// Number of wavelengths carried by each path.
+pub const WAVELENGTH_COUNT: usize = 4;
+
+// Wavelengths a path is traced at, with the density each was sampled with. The first is the hero
+// wavelength, and the others are spread out from it across the visible range.
+#[derive(Debug, Clone, Copy)]
+pub struct SampledWavelengths {
+    lambda: [Float; WAVELENGTH_COUNT],
+    pdf: [Float; WAVELENGTH_COUNT],
+}
+
+impl SampledWavelengths {
+    pub fn sample_visible(u: Float) -> SampledWavelengths {
+        // Samples in proportion to a fit of how sensitive the eye is to each wavelength, as in
+        // PBRT, so little time goes to wavelengths that barely affect the image. The other
+        // wavelengths come from samples evenly offset from the hero's.
+        let mut lambda = [0.0; WAVELENGTH_COUNT];
+        let mut pdf = [0.0; WAVELENGTH_COUNT];
+        for i in 0..WAVELENGTH_COUNT {
+            let ui = (u + i as Float / WAVELENGTH_COUNT as Float).fract();
+            lambda[i] = 538.0 - 138.888889 * (0.85691062 - 1.82750197 * ui).atanh();
+            pdf[i] = visible_wavelengths_pdf(lambda[i]);
+        }
+        SampledWavelengths { lambda, pdf }
+    }
+
+    pub fn hero(&self) -> Float {
+        self.lambda[0]
+    }
+
+    pub fn terminate_secondary(&mut self) {
+        // Once a path has taken a direction that only suits the hero wavelength, such as
+        // refracting through a dispersive surface, the other wavelengths are dropped. The hero
+        // then stands in for all of them.
+        if self.is_secondary_terminated() {
+            return;
+        }
+        for pdf in &mut self.pdf[1..] {
+            *pdf = 0.0;
+        }
+        self.pdf[0] /= WAVELENGTH_COUNT as Float;
+    }
+
+    pub fn is_secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|&pdf| pdf == 0.0)
+    }
+}
+
+fn visible_wavelengths_pdf(lambda: Float) -> Float {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.0;
+    }
+    0.0039398042 / (0.0072 * (lambda - 538.0)).cosh().powi(2)
+}
+
+// Values of a spectrum at the wavelengths of a `SampledWavelengths`.
+#[derive(Debug, Clone, Copy)]
+pub struct SampledSpectrum {
+    values: [Float; WAVELENGTH_COUNT],
+}
+
+impl SampledSpectrum {
+    pub fn new(value: Float) -> SampledSpectrum {
+        SampledSpectrum {
+            values: [value; WAVELENGTH_COUNT],
+        }
+    }
+
+    pub fn from_fn(
+        wavelengths: &SampledWavelengths,
+        f: impl Fn(Float) -> Float,
+    ) -> SampledSpectrum {
+        SampledSpectrum {
+            values: wavelengths.lambda.map(f),
+        }
+    }
+
+    pub fn from_rgb(rgb: Vec3, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        // Reflectance spectrum of an RGB albedo.
+        SampledSpectrum::from_fn(wavelengths, |lambda| rgb_to_spectrum(rgb, lambda))
+    }
+
+    pub fn from_rgb_illuminant(rgb: Vec3, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        // Emission spectrum of an RGB light. White light is D65, the white point of sRGB, scaled
+        // so that RGB (1, 1, 1) keeps a luminance of 1.
+        SampledSpectrum::from_fn(wavelengths, |lambda| {
+            rgb_to_spectrum(rgb, lambda) * d65(lambda) / D65_Y
+        })
+    }
+
+    pub fn to_xyz(self, wavelengths: &SampledWavelengths) -> Vec3 {
+        // Monte Carlo estimate of the integral of the spectrum against the CIE matching
+        // functions, from the sampled wavelengths.
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..WAVELENGTH_COUNT {
+            if wavelengths.pdf[i] > 0.0 {
+                xyz = xyz + cie_xyz(wavelengths.lambda[i]) * (self.values[i] / wavelengths.pdf[i]);
+            }
+        }
+        xyz / WAVELENGTH_COUNT as Float
+    }
+
+    pub fn to_rgb(self, wavelengths: &SampledWavelengths) -> Vec3 {
+        xyz_to_linear_srgb(self.to_xyz(wavelengths))
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn add(self, other: SampledSpectrum) -> SampledSpectrum {
+        let mut values = self.values;
+        for (value, other) in values.iter_mut().zip(other.values) {
+            *value += other;
+        }
+        SampledSpectrum { values }
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+
+    fn mul(self, other: SampledSpectrum) -> SampledSpectrum {
+        let mut values = self.values;
+        for (value, other) in values.iter_mut().zip(other.values) {
+            *value *= other;
+        }
+        SampledSpectrum { values }
+    }
+}
+
+// Index of refraction that changes with wavelength, which splits white light into colors.
+#[derive(Debug, Clone, Copy)]
+pub enum Dispersion {
+    // n = a + b / λ², with λ in micrometres.
+    Cauchy { a: Float, b: Float },
+    // n² = 1 + Σ bᵢ λ² / (λ² - cᵢ), with λ in micrometres.
+    Sellmeier { b: [Float; 3], c: [Float; 3] },
+}
+
+impl Dispersion {
+    // Schott N-BK7, a common crown glass.
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+
+    // Schott N-SF11, a dense flint glass that disperses light about three times as much.
+    pub const DENSE_FLINT: Dispersion = Dispersion::Sellmeier {
+        b: [1.73759695, 0.313747346, 1.89878101],
+        c: [0.013188707, 0.0623068142, 155.23629],
+    };
+
+    // Wavelength that the index of refraction of a glass is usually quoted at.
+    pub const D_LINE: Float = 587.6;
+
+    pub fn refraction_index(&self, wavelength: Float) -> Float {
+        let lambda = wavelength / 1000.0;
+        let lambda2 = lambda * lambda;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / lambda2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: Float = b
+                    .iter()
+                    .zip(c)
+                    .map(|(b, c)| b * lambda2 / (lambda2 - c))
+                    .sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
+
+// Spectrum of a light source. Only its shape matters, since lights scale it to the brightness
+// they are given.
+#[derive(Debug, Clone, Copy)]
+pub enum Spectrum {
+    // Blackbody radiator at a temperature in Kelvin.
+    Blackbody(Float),
+    Illuminant(StandardIlluminant),
+}
+
+// CIE standard illuminants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StandardIlluminant {
+    D65, // Average daylight
+    A,   // Incandescent bulb
+    F2,  // Cool white fluorescent tube
+    F11, // Narrow band fluorescent tube
+}
+
+impl Spectrum {
+    pub fn value(&self, lambda: Float) -> Float {
+        match self {
+            Spectrum::Blackbody(kelvin) => blackbody(lambda, *kelvin),
+            Spectrum::Illuminant(StandardIlluminant::D65) => d65(lambda),
+            Spectrum::Illuminant(StandardIlluminant::A) => blackbody(lambda, 2856.0),
+            Spectrum::Illuminant(StandardIlluminant::F2) => fluorescent(&F2, lambda),
+            Spectrum::Illuminant(StandardIlluminant::F11) => fluorescent(&F11, lambda),
+        }
+    }
+
+    pub fn to_xyz(self) -> Vec3 {
+        // Integrates the spectrum against the CIE matching functions in 1nm steps.
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        let mut lambda = LAMBDA_MIN + 0.5;
+        while lambda < LAMBDA_MAX {
+            xyz = xyz + cie_xyz(lambda) * self.value(lambda);
+            lambda += 1.0;
+        }
+        xyz
+    }
+
+    pub fn to_rgb(self) -> Vec3 {
+        xyz_to_linear_srgb(self.to_xyz())
+    }
+
+    pub fn luminous_efficacy(self) -> Float {
+        // Lumens given off per watt of light between `LAMBDA_MIN` and `LAMBDA_MAX`.
+        let mut power = 0.0;
+        let mut lambda = LAMBDA_MIN + 0.5;
+        while lambda < LAMBDA_MAX {
+            power += self.value(lambda);
+            lambda += 1.0;
+        }
+        683.0 * self.to_xyz().y() / power
+    }
+}
+
+pub fn blackbody(lambda: Float, kelvin: Float) -> Float {
+    // Planck's law, divided by its value at the peak wavelength so that the result stays near 1
+    // at any temperature.
+    let planck = |lambda: Float| {
+        let l = lambda * 1e-9;
+        let c = 299792458.0;
+        let h = 6.62607015e-34;
+        let kb = 1.380649e-23;
+        2.0 * h * c * c / (l.powi(5) * ((h * c / (l * kb * kelvin)).exp() - 1.0))
+    };
+    let peak = 2.8977721e6 / kelvin;
+    planck(lambda) / planck(peak)
+}
+
+fn fluorescent(table: &[Float; 81], lambda: Float) -> Float {
+    // Fluorescent tables run from 380nm to 780nm in 5nm steps.
+    if !(380.0..=780.0).contains(&lambda) {
+        return 0.0;
+    }
+    let t = (lambda - 380.0) / 5.0;
+    let i = (t as usize).min(table.len() - 2);
+    let f = t - i as Float;
+    table[i] * (1.0 - f) + table[i + 1] * f
+}
+
+const F2: [Float; 81] = [
+    1.18, 1.48, 1.84, 2.15, 3.44, 15.69, 3.85, 3.74, 4.19, 4.62, 5.06, 34.98, 11.81, 6.27, 6.63,
+    6.93, 7.19, 7.40, 7.54, 7.62, 7.65, 7.62, 7.62, 7.45, 7.28, 7.15, 7.05, 7.04, 7.16, 7.47, 8.04,
+    8.88, 10.01, 24.88, 16.64, 14.59, 16.16, 17.56, 18.62, 21.47, 22.79, 19.29, 18.66, 17.73,
+    16.54, 15.21, 13.80, 12.36, 10.95, 9.65, 8.40, 7.32, 6.31, 5.43, 4.68, 4.02, 3.45, 2.96, 2.55,
+    2.19, 1.89, 1.64, 1.53, 1.27, 1.10, 0.99, 0.88, 0.76, 0.68, 0.61, 0.56, 0.54, 0.51, 0.47, 0.47,
+    0.43, 0.46, 0.47, 0.40, 0.33, 0.27,
+];
+
+const F11: [Float; 81] = [
+    0.91, 0.63, 0.46, 0.37, 1.29, 12.68, 1.59, 1.79, 2.46, 3.33, 4.49, 33.94, 12.13, 6.95, 7.19,
+    7.12, 6.72, 6.13, 5.46, 4.79, 5.66, 14.29, 14.96, 8.97, 4.72, 2.33, 1.47, 1.10, 0.89, 0.83,
+    1.18, 4.90, 39.59, 72.84, 32.61, 7.52, 2.83, 1.96, 1.67, 4.43, 11.28, 14.76, 12.73, 9.74, 7.33,
+    9.72, 55.27, 42.58, 13.18, 13.16, 12.26, 5.11, 2.07, 2.34, 3.58, 3.01, 2.48, 2.14, 1.54, 1.33,
+    1.46, 1.94, 2.00, 1.20, 1.35, 4.10, 5.58, 2.51, 0.57, 0.27, 0.23, 0.21, 0.24, 0.24, 0.20, 0.24,
+    0.32, 0.26, 0.16, 0.12, 0.09,
+];
+
+fn rgb_to_spectrum(rgb: Vec3, lambda: Float) -> Float {
+    // Smits' method: the spectrum is white plus the two basis spectra of the primary and
+    // secondary color between the smallest component and the others. This keeps albedos
+    // within [0, 1] and scales linearly with the color.
+    let (r, g, b) = (rgb.x(), rgb.y(), rgb.z());
+    let bin = smits_bin(lambda);
+    if r <= g && r <= b {
+        let spectrum = r * SMITS_WHITE[bin];
+        if g <= b {
+            spectrum + (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+        } else {
+            spectrum + (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+        }
+    } else if g <= r && g <= b {
+        let spectrum = g * SMITS_WHITE[bin];
+        if r <= b {
+            spectrum + (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+        } else {
+            spectrum + (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+        }
+    } else {
+        let spectrum = b * SMITS_WHITE[bin];
+        if r <= g {
+            spectrum + (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+        } else {
+            spectrum + (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+        }
+    }
+}
+
+fn smits_bin(lambda: Float) -> usize {
+    // The basis spectra have ten even bins from 380nm to 720nm, extended flat past both ends.
+    (((lambda - 380.0) / 34.0).max(0.0) as usize).min(9)
+}
+
+const SMITS_WHITE: [Float; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [Float; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [Float; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [Float; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [Float; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [Float; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [Float; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+pub fn d65(lambda: Float) -> Float {
+    // CIE standard illuminant D65, interpolated from 10nm steps starting at 360nm.
+    let t = ((lambda - LAMBDA_MIN) / 10.0).clamp(0.0, (D65.len() - 1) as Float);
+    let i = (t as usize).min(D65.len() - 2);
+    let f = t - i as Float;
+    D65[i] * (1.0 - f) + D65[i + 1] * f
+}
+
+// Luminance of the D65 table below, found by integrating it against `cie_xyz`.
+const D65_Y: Float = 10569.35;
+
+const D65: [Float; 48] = [
+    46.6383, 52.0891, 49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008,
+    117.812, 114.861, 115.923, 108.811, 109.354, 107.802, 104.79, 107.689, 104.405, 104.046, 100.0,
+    96.3342, 95.788, 88.6856, 90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268, 80.2146,
+    82.2778, 78.2842, 69.7213, 71.6091, 74.349, 61.604, 69.8856, 75.087, 63.5927, 46.4182, 66.8054,
+    63.3828, 64.304, 59.4519, 51.959, 57.4406, 60.3125,
+];
+
+pub fn cie_xyz(lambda: Float) -> Vec3 {
+    // CIE 1931 color matching functions, from the multi-lobe fit of Wyman, Sloan and Shirley.
+    let g = |mu: Float, sigma_below: Float, sigma_above: Float| {
+        let sigma = if lambda < mu {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+pub fn xyz_to_linear_srgb(xyz: Vec3) -> Vec3 {
+    let (x, y, z) = (xyz.x(), xyz.y(), xyz.z());
+    Vec3::new(
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    )
+}
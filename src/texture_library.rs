@@ -0,0 +1,334 @@
+use crate::image::ColorSpace;
+use crate::noise::{Fractal, FractalNoise, NoiseKind};
+use crate::texture_manager::{LoadError, TextureManager};
+use crate::texture_nodes::{
+    AddTexture, ColorRamp, GradientTexture, HueSaturationTexture, InvertTexture, MixTexture,
+    MultiplyTexture, ScaleTexture, TriplanarTexture, UvTransformTexture,
+};
+use crate::textures::{
+    CheckerTexture, GraniteTexture, MarbleTexture, NoiseTexture, SolidColor, Texture, WoodTexture,
+};
+use crate::utils::Float;
+use crate::vec3::Vec3;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// Named textures read from a scene file, so materials can be put together without writing
+// Rust. Each line names one texture:
+//
+//     rust = mix (color 0.6 0.6 0.65) (color 0.4 0.15 0.05) (noise simplex fbm 4)
+//
+// A texture is a node name followed by its arguments, which are numbers, words, names of
+// textures defined on earlier lines, or other nodes in parentheses. A lone number where a
+// texture is expected is that shade of gray. Everything after a # is a comment. The nodes are:
+//
+//     color r g b                 image file          data file (image used as stored)
+//     checker size even odd       noise kind fractal scale
+//     marble scale                wood scale          granite scale
+//     mix a b factor              multiply a b        add a b
+//     scale texture factor        invert texture      hsv texture hue saturation value
+//     ramp input position r g b [position r g b ...]
+//     uv texture scale_u scale_v rotation offset_u offset_v
+//     triplanar texture scale [sharpness]
+//     gradient x0 y0 z0 x1 y1 z1
+//
+// Noise kinds are perlin, improved_perlin, simplex and worley, and fractals are fbm,
+// turbulence and ridged.
+pub struct TextureLibrary {
+    textures: HashMap<String, Arc<dyn Texture>>,
+}
+
+impl TextureLibrary {
+    pub fn load(filename: &str, manager: &TextureManager) -> Result<TextureLibrary, LoadError> {
+        // The file, and the images named in it, are found through the search paths of
+        // `manager`.
+        let (path, text) = manager.read_to_string(filename)?;
+        TextureLibrary::parse(&text, manager)
+            .map_err(|message| LoadError::Invalid { path, message })
+    }
+
+    pub fn load_scene(scene_file: &Path) -> Result<TextureLibrary, LoadError> {
+        // Reads the file at `scene_file`, with the images named in it looked for next to it
+        // first, see `TextureManager::new_for_scene`.
+        let manager = TextureManager::new_for_scene(scene_file);
+        let filename = scene_file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        TextureLibrary::load(filename, &manager)
+    }
+
+    pub fn parse(text: &str, manager: &TextureManager) -> Result<TextureLibrary, String> {
+        let mut textures = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let at_line = |e: String| format!("line {}: {}", number + 1, e);
+
+            let (name, definition) = line
+                .split_once('=')
+                .ok_or_else(|| at_line(String::from("expected name = texture")))?;
+            let name = name.trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(at_line(format!("\"{}\" is not a texture name", name)));
+            }
+            if textures.contains_key(name) {
+                return Err(at_line(format!("{} is defined twice", name)));
+            }
+
+            let mut parser = Parser {
+                tokens: tokenize(definition),
+                next: 0,
+                textures: &textures,
+                manager,
+            };
+            let texture = parser.definition().map_err(at_line)?;
+            textures.insert(name.to_string(), texture);
+        }
+        Ok(TextureLibrary { textures })
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<dyn Texture>, String> {
+        self.textures
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("No texture named {}", name))
+    }
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    // Words and numbers are split by whitespace, and parentheses are tokens of their own.
+    text.replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+        .map(String::from)
+        .collect()
+}
+
+struct Parser<'a> {
+    tokens: Vec<String>,
+    next: usize,
+    textures: &'a HashMap<String, Arc<dyn Texture>>,
+    manager: &'a TextureManager,
+}
+
+impl Parser<'_> {
+    fn definition(&mut self) -> Result<Arc<dyn Texture>, String> {
+        // The outermost node needs no parentheses, but may have them.
+        let texture = if self.tokens.len() == 1 || self.peek() == Some("(") {
+            self.texture()?
+        } else {
+            self.node()?
+        };
+        match self.peek() {
+            None => Ok(texture),
+            Some(token) => Err(format!("unexpected {}", token)),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.next).map(String::as_str)
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .get(self.next)
+            .ok_or("unexpected end of line")?
+            .clone();
+        self.next += 1;
+        Ok(token)
+    }
+
+    fn number(&mut self) -> Result<Float, String> {
+        let token = self.word()?;
+        token
+            .parse::<Float>()
+            .map_err(|_| format!("expected a number, found {}", token))
+    }
+
+    fn color(&mut self) -> Result<Vec3, String> {
+        Ok(Vec3::new(self.number()?, self.number()?, self.number()?))
+    }
+
+    fn texture(&mut self) -> Result<Arc<dyn Texture>, String> {
+        let token = self.word()?;
+        if token == "(" {
+            let texture = self.node()?;
+            return match self.word()?.as_str() {
+                ")" => Ok(texture),
+                token => Err(format!("expected ), found {}", token)),
+            };
+        }
+        if let Ok(value) = token.parse::<Float>() {
+            return Ok(Arc::new(SolidColor::new(Vec3::new(value, value, value))));
+        }
+        self.textures
+            .get(&token)
+            .cloned()
+            .ok_or_else(|| format!("no texture named {}", token))
+    }
+
+    fn node(&mut self) -> Result<Arc<dyn Texture>, String> {
+        let kind = self.word()?;
+        let texture: Arc<dyn Texture> = match kind.as_str() {
+            "color" => Arc::new(SolidColor::new(self.color()?)),
+            "image" => Arc::new(
+                self.manager
+                    .image_texture(&self.word()?, ColorSpace::Srgb)?,
+            ),
+            "data" => Arc::new(
+                self.manager
+                    .image_texture(&self.word()?, ColorSpace::Linear)?,
+            ),
+            "checker" => Arc::new(CheckerTexture::new(
+                self.number()?,
+                self.texture()?,
+                self.texture()?,
+            )),
+            "noise" => {
+                let noise = match self.word()?.as_str() {
+                    "perlin" => NoiseKind::Perlin,
+                    "improved_perlin" => NoiseKind::ImprovedPerlin,
+                    "simplex" => NoiseKind::Simplex,
+                    "worley" => NoiseKind::Worley,
+                    kind => return Err(format!("unknown noise {}", kind)),
+                };
+                let fractal = match self.word()?.as_str() {
+                    "fbm" => Fractal::Fbm,
+                    "turbulence" => Fractal::Turbulence,
+                    "ridged" => Fractal::Ridged,
+                    fractal => return Err(format!("unknown fractal {}", fractal)),
+                };
+                Arc::new(NoiseTexture::new(
+                    FractalNoise::new(noise, fractal),
+                    self.number()?,
+                ))
+            }
+            "marble" => Arc::new(MarbleTexture::new(self.number()?)),
+            "wood" => Arc::new(WoodTexture::new(self.number()?)),
+            "granite" => Arc::new(GraniteTexture::new(self.number()?)),
+            "mix" => Arc::new(MixTexture::new(
+                self.texture()?,
+                self.texture()?,
+                self.texture()?,
+            )),
+            "multiply" => Arc::new(MultiplyTexture::new(self.texture()?, self.texture()?)),
+            "add" => Arc::new(AddTexture::new(self.texture()?, self.texture()?)),
+            "scale" => Arc::new(ScaleTexture::new(self.texture()?, self.number()?)),
+            "invert" => Arc::new(InvertTexture::new(self.texture()?)),
+            "hsv" => Arc::new(
+                HueSaturationTexture::new(self.texture()?)
+                    .with_hue(self.number()?)
+                    .with_saturation(self.number()?)
+                    .with_value(self.number()?),
+            ),
+            "ramp" => {
+                let input = self.texture()?;
+                let mut stops = vec![(self.number()?, self.color()?)];
+                while self.peek().is_some_and(|token| token != ")") {
+                    stops.push((self.number()?, self.color()?));
+                }
+                Arc::new(ColorRamp::new(input, stops))
+            }
+            "uv" => Arc::new(
+                UvTransformTexture::new(self.texture()?)
+                    .with_scale(self.number()?, self.number()?)
+                    .with_rotation(self.number()?)
+                    .with_offset(self.number()?, self.number()?),
+            ),
+            "triplanar" => {
+                let mut triplanar = TriplanarTexture::new(self.texture()?, self.number()?);
+                if self.peek().is_some_and(|token| token != ")") {
+                    triplanar = triplanar.with_sharpness(self.number()?);
+                }
+                Arc::new(triplanar)
+            }
+            "gradient" => Arc::new(GradientTexture::new(self.color()?, self.color()?)),
+            kind => return Err(format!("unknown texture node {}", kind)),
+        };
+        Ok(texture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("texture_library_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn loads_through_search_paths() {
+        let dir = scratch_dir("load");
+        std::fs::write(
+            dir.join("a.textures"),
+            "red = color 1 0 0\ndark = scale red 0.5\n",
+        )
+        .unwrap();
+        let manager = TextureManager::new().with_search_path(&dir);
+
+        let library = TextureLibrary::load("a.textures", &manager).unwrap();
+        let dark = library.get("dark").unwrap();
+        let color = dark.value(0.0, 0.0, Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!((color.x(), color.y(), color.z()), (0.5, 0.0, 0.0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn scene_images_are_found_next_to_the_scene() {
+        // The image is only next to the scene file, not in any directory `new` looks in.
+        let dir = scratch_dir("scene");
+        std::fs::copy("images/earthmap.jpg", dir.join("scene_earth.jpg")).unwrap();
+        std::fs::write(
+            dir.join("scene.textures"),
+            "earth = image scene_earth.jpg\n",
+        )
+        .unwrap();
+
+        let library = TextureLibrary::load_scene(&dir.join("scene.textures")).unwrap();
+        assert!(library.get("earth").is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_file_lists_search_paths() {
+        let dir = scratch_dir("missing");
+        let manager = TextureManager::new().with_search_path(&dir);
+        match TextureLibrary::load("missing.textures", &manager) {
+            Err(LoadError::NotFound { filename, searched }) => {
+                assert_eq!(filename, "missing.textures");
+                assert!(searched.contains(&dir.join("missing.textures")));
+            }
+            _ => panic!("expected the file not to be found"),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parse_errors_name_the_file_and_line() {
+        let dir = scratch_dir("invalid");
+        std::fs::write(
+            dir.join("bad.textures"),
+            "red = color 1 0 0\nblue = colour 0 0 1\n",
+        )
+        .unwrap();
+        let manager = TextureManager::new().with_search_path(&dir);
+        match TextureLibrary::load("bad.textures", &manager) {
+            Err(LoadError::Invalid { path, message }) => {
+                assert!(path.ends_with("bad.textures"));
+                assert!(message.starts_with("line 2:"), "{}", message);
+            }
+            _ => panic!("expected the file to be invalid"),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+use crate::image::{ColorSpace, Image};
+use crate::mipmap::{MipMap, WrapMode};
+use crate::textures::ImageTexture;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+// Why a file couldn't be loaded. Converts into the `String` errors used elsewhere, so `?` still
+// works in functions returning those.
+#[derive(Debug, Clone)]
+pub enum LoadError {
+    NotFound {
+        filename: String,
+        searched: Vec<PathBuf>,
+    },
+    Invalid {
+        path: PathBuf,
+        message: String, // What went wrong reading or decoding the file
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::NotFound { filename, searched } => {
+                let searched: Vec<String> = searched
+                    .iter()
+                    .map(|candidate| candidate.display().to_string())
+                    .collect();
+                write!(
+                    f,
+                    "File {} not found, looked for {}",
+                    filename,
+                    searched.join(", ")
+                )
+            }
+            LoadError::Invalid { path, message } => {
+                write!(f, "Failed to load {}: {}", path.display(), message)
+            }
+        }
+    }
+}
+
+impl From<LoadError> for String {
+    fn from(error: LoadError) -> String {
+        error.to_string()
+    }
+}
+
+// Finds image files, and other files scenes read such as texture descriptions, and loads each
+// image only once. Filenames are looked for in each search directory in turn, and absolute
+// paths are used as they are. Loaded images, and the mip maps built from them, are kept by path
+// and color space and shared between everything that asks for them.
+pub struct TextureManager {
+    search_paths: Vec<PathBuf>,
+    images: Mutex<HashMap<(PathBuf, ColorSpace), Arc<Image>>>,
+    mipmaps: Mutex<HashMap<(PathBuf, ColorSpace), MipMap>>,
+}
+
+impl TextureManager {
+    pub fn new() -> TextureManager {
+        // Looks in the images directory under the working directory, as scenes always have.
+        let current_dir = std::env::current_dir().unwrap_or_default();
+        TextureManager {
+            search_paths: vec![current_dir.join("images")],
+            images: Mutex::new(HashMap::new()),
+            mipmaps: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn new_for_scene(scene_file: &Path) -> TextureManager {
+        // Looks next to the scene file first, then in the images directory beside it, then
+        // where `new` looks.
+        let scene_dir = scene_file.parent().unwrap_or(Path::new("")).to_path_buf();
+        let mut manager = TextureManager::new();
+        manager
+            .search_paths
+            .splice(0..0, [scene_dir.clone(), scene_dir.join("images")]);
+        manager
+    }
+
+    pub fn global() -> &'static TextureManager {
+        // The manager `ImageTexture::new` and `EnvironmentLight::new` load through, so the same
+        // file named in different places of a scene is only decoded once.
+        static GLOBAL: OnceLock<TextureManager> = OnceLock::new();
+        GLOBAL.get_or_init(TextureManager::new)
+    }
+
+    pub fn with_search_path(mut self, dir: impl AsRef<Path>) -> TextureManager {
+        // Adds a directory to look in after the others.
+        self.search_paths.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn search_paths(&self) -> &[PathBuf] {
+        &self.search_paths
+    }
+
+    pub fn resolve(&self, filename: &str) -> Result<PathBuf, LoadError> {
+        let path = Path::new(filename);
+        let candidates: Vec<PathBuf> = if path.is_absolute() {
+            vec![path.to_path_buf()]
+        } else {
+            self.search_paths.iter().map(|dir| dir.join(path)).collect()
+        };
+        match candidates.iter().find(|candidate| candidate.is_file()) {
+            Some(candidate) => Ok(candidate.canonicalize().unwrap_or(candidate.clone())),
+            None => Err(LoadError::NotFound {
+                filename: filename.to_string(),
+                searched: candidates,
+            }),
+        }
+    }
+
+    pub fn read_to_string(&self, filename: &str) -> Result<(PathBuf, String), LoadError> {
+        // Finds a text file and reads it, returning where it was found along with the text.
+        let path = self.resolve(filename)?;
+        match std::fs::read_to_string(&path) {
+            Ok(text) => Ok((path, text)),
+            Err(e) => Err(LoadError::Invalid {
+                path,
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    pub fn image(&self, filename: &str, color_space: ColorSpace) -> Result<Arc<Image>, LoadError> {
+        let key = (self.resolve(filename)?, color_space);
+        let mut images = self.images.lock().unwrap();
+        if let Some(image) = images.get(&key) {
+            return Ok(image.clone());
+        }
+        let image = Image::load(&key.0, color_space).map_err(|message| LoadError::Invalid {
+            path: key.0.clone(),
+            message,
+        })?;
+        let image = Arc::new(image);
+        images.insert(key, image.clone());
+        Ok(image)
+    }
+
+    pub fn mipmap(&self, filename: &str, color_space: ColorSpace) -> Result<MipMap, LoadError> {
+        // The mip map comes with wrap mode `Repeat`. Its levels are shared, so changing the wrap
+        // mode of the copy returned doesn't change anything else.
+        let key = (self.resolve(filename)?, color_space);
+        if let Some(mipmap) = self.mipmaps.lock().unwrap().get(&key) {
+            return Ok(mipmap.clone());
+        }
+        let image = self.image(filename, color_space)?;
+        let mipmap = MipMap::new(&image, WrapMode::Repeat);
+        self.mipmaps.lock().unwrap().insert(key, mipmap.clone());
+        Ok(mipmap)
+    }
+
+    pub fn image_texture(
+        &self,
+        filename: &str,
+        color_space: ColorSpace,
+    ) -> Result<ImageTexture, LoadError> {
+        Ok(ImageTexture::new_from_mipmap(
+            self.mipmap(filename, color_space)?,
+        ))
+    }
+}
+
+impl Default for TextureManager {
+    fn default() -> TextureManager {
+        TextureManager::new()
+    }
+}
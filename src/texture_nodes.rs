@@ -0,0 +1,512 @@
+use crate::textures::{gray, Texture, UvDifferentials, UvTransform};
+use crate::utils::Float;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// Textures built out of other textures, which can be wired together into a graph to make
+// patterns no single texture gives. Where a node takes a number from a texture, such as a
+// blend factor, it reads the texture as gray.
+
+// Blend from `a` where `factor` is 0 to `b` where it is 1.
+pub struct MixTexture {
+    a: Arc<dyn Texture>,
+    b: Arc<dyn Texture>,
+    factor: Arc<dyn Texture>,
+}
+
+impl MixTexture {
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>, factor: Arc<dyn Texture>) -> MixTexture {
+        MixTexture { a, b, factor }
+    }
+}
+
+impl Texture for MixTexture {
+    fn value(&self, u: Float, v: Float, p: Vec3) -> Vec3 {
+        self.value_filtered(u, v, p, UvDifferentials::default())
+    }
+
+    fn value_filtered(&self, u: Float, v: Float, p: Vec3, differentials: UvDifferentials) -> Vec3 {
+        let f = gray(self.factor.value_filtered(u, v, p, differentials)).clamp(0.0, 1.0);
+        (1.0 - f) * self.a.value_filtered(u, v, p, differentials)
+            + f * self.b.value_filtered(u, v, p, differentials)
+    }
+}
+
+// Product of two textures, channel by channel, for tinting and masking.
+pub struct MultiplyTexture {
+    a: Arc<dyn Texture>,
+    b: Arc<dyn Texture>,
+}
+
+impl MultiplyTexture {
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>) -> MultiplyTexture {
+        MultiplyTexture { a, b }
+    }
+}
+
+impl Texture for MultiplyTexture {
+    fn value(&self, u: Float, v: Float, p: Vec3) -> Vec3 {
+        self.value_filtered(u, v, p, UvDifferentials::default())
+    }
+
+    fn value_filtered(&self, u: Float, v: Float, p: Vec3, differentials: UvDifferentials) -> Vec3 {
+        self.a.value_filtered(u, v, p, differentials)
+            * self.b.value_filtered(u, v, p, differentials)
+    }
+}
+
+// Sum of two textures, for layering detail on top of a base.
+pub struct AddTexture {
+    a: Arc<dyn Texture>,
+    b: Arc<dyn Texture>,
+}
+
+impl AddTexture {
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>) -> AddTexture {
+        AddTexture { a, b }
+    }
+}
+
+impl Texture for AddTexture {
+    fn value(&self, u: Float, v: Float, p: Vec3) -> Vec3 {
+        self.value_filtered(u, v, p, UvDifferentials::default())
+    }
+
+    fn value_filtered(&self, u: Float, v: Float, p: Vec3, differentials: UvDifferentials) -> Vec3 {
+        self.a.value_filtered(u, v, p, differentials)
+            + self.b.value_filtered(u, v, p, differentials)
+    }
+}
+
+// A texture with every value multiplied by a constant.
+pub struct ScaleTexture {
+    texture: Arc<dyn Texture>,
+    scale: Float,
+}
+
+impl ScaleTexture {
+    pub fn new(texture: Arc<dyn Texture>, scale: Float) -> ScaleTexture {
+        ScaleTexture { texture, scale }
+    }
+}
+
+impl Texture for ScaleTexture {
+    fn value(&self, u: Float, v: Float, p: Vec3) -> Vec3 {
+        self.value_filtered(u, v, p, UvDifferentials::default())
+    }
+
+    fn value_filtered(&self, u: Float, v: Float, p: Vec3, differentials: UvDifferentials) -> Vec3 {
+        self.texture.value_filtered(u, v, p, differentials) * self.scale
+    }
+}
+
+// One minus a texture, which turns masks inside out.
+pub struct InvertTexture {
+    texture: Arc<dyn Texture>,
+}
+
+impl InvertTexture {
+    pub fn new(texture: Arc<dyn Texture>) -> InvertTexture {
+        InvertTexture { texture }
+    }
+}
+
+impl Texture for InvertTexture {
+    fn value(&self, u: Float, v: Float, p: Vec3) -> Vec3 {
+        self.value_filtered(u, v, p, UvDifferentials::default())
+    }
+
+    fn value_filtered(&self, u: Float, v: Float, p: Vec3, differentials: UvDifferentials) -> Vec3 {
+        Vec3::new(1.0, 1.0, 1.0) - self.texture.value_filtered(u, v, p, differentials)
+    }
+}
+
+// Gray from a texture mapped to colors, which blend between stops placed along [0, 1]. Values
+// beyond the first and last stops take their colors.
+pub struct ColorRamp {
+    input: Arc<dyn Texture>,
+    stops: Vec<(Float, Vec3)>, // Sorted by position
+}
+
+impl ColorRamp {
+    pub fn new(input: Arc<dyn Texture>, stops: Vec<(Float, Vec3)>) -> ColorRamp {
+        let mut stops = stops;
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        ColorRamp { input, stops }
+    }
+
+    fn color(&self, t: Float) -> Vec3 {
+        let (Some(first), Some(last)) = (self.stops.first(), self.stops.last()) else {
+            return Vec3::new(t, t, t);
+        };
+        // NaN, which no stop can be placed around, takes the first color.
+        if t.is_nan() || t <= first.0 {
+            return first.1;
+        }
+        if t >= last.0 {
+            return last.1;
+        }
+        let next = self.stops.partition_point(|stop| stop.0 <= t);
+        let ((t0, c0), (t1, c1)) = (self.stops[next - 1], self.stops[next]);
+        let f = (t - t0) / (t1 - t0);
+        (1.0 - f) * c0 + f * c1
+    }
+}
+
+impl Texture for ColorRamp {
+    fn value(&self, u: Float, v: Float, p: Vec3) -> Vec3 {
+        self.value_filtered(u, v, p, UvDifferentials::default())
+    }
+
+    fn value_filtered(&self, u: Float, v: Float, p: Vec3, differentials: UvDifferentials) -> Vec3 {
+        self.color(gray(self.input.value_filtered(u, v, p, differentials)))
+    }
+}
+
+// A texture with its hue turned, and its saturation and value (brightness) scaled.
+pub struct HueSaturationTexture {
+    texture: Arc<dyn Texture>,
+    hue: Float, // Turns, so 1 goes all the way round
+    saturation: Float,
+    value: Float,
+}
+
+impl HueSaturationTexture {
+    pub fn new(texture: Arc<dyn Texture>) -> HueSaturationTexture {
+        HueSaturationTexture {
+            texture,
+            hue: 0.0,
+            saturation: 1.0,
+            value: 1.0,
+        }
+    }
+
+    pub fn with_hue(mut self, degrees: Float) -> HueSaturationTexture {
+        self.hue = degrees / 360.0;
+        self
+    }
+
+    pub fn with_saturation(mut self, saturation: Float) -> HueSaturationTexture {
+        self.saturation = saturation;
+        self
+    }
+
+    pub fn with_value(mut self, value: Float) -> HueSaturationTexture {
+        self.value = value;
+        self
+    }
+}
+
+impl Texture for HueSaturationTexture {
+    fn value(&self, u: Float, v: Float, p: Vec3) -> Vec3 {
+        self.value_filtered(u, v, p, UvDifferentials::default())
+    }
+
+    fn value_filtered(&self, u: Float, v: Float, p: Vec3, differentials: UvDifferentials) -> Vec3 {
+        let c = self.texture.value_filtered(u, v, p, differentials);
+
+        // Hue in turns, saturation and value of the color (Smith 1978).
+        let max = c.x().max(c.y()).max(c.z());
+        let min = c.x().min(c.y()).min(c.z());
+        let chroma = max - min;
+        let hue = if chroma <= 0.0 {
+            0.0
+        } else if max == c.x() {
+            ((c.y() - c.z()) / chroma).rem_euclid(6.0) / 6.0
+        } else if max == c.y() {
+            ((c.z() - c.x()) / chroma + 2.0) / 6.0
+        } else {
+            ((c.x() - c.y()) / chroma + 4.0) / 6.0
+        };
+        let saturation = if max > 0.0 { chroma / max } else { 0.0 };
+
+        let hue = (hue + self.hue).rem_euclid(1.0);
+        let saturation = (saturation * self.saturation).clamp(0.0, 1.0);
+        let value = max.max(0.0) * self.value;
+
+        // Back to RGB, one channel at a time.
+        let channel = |n: Float| {
+            let k = (n + hue * 6.0).rem_euclid(6.0);
+            value - value * saturation * k.min(4.0 - k).clamp(0.0, 1.0)
+        };
+        Vec3::new(channel(5.0), channel(3.0), channel(1.0))
+    }
+}
+
+// A texture looked up at texture coordinates scaled, rotated about the origin and offset, in
+// that order, so any texture can be tiled, turned and slid over a surface.
+pub struct UvTransformTexture {
+    texture: Arc<dyn Texture>,
+    transform: UvTransform,
+}
+
+impl UvTransformTexture {
+    pub fn new(texture: Arc<dyn Texture>) -> UvTransformTexture {
+        UvTransformTexture {
+            texture,
+            transform: UvTransform::new(),
+        }
+    }
+
+    pub fn with_scale(mut self, scale_u: Float, scale_v: Float) -> UvTransformTexture {
+        self.transform = self.transform.with_scale(scale_u, scale_v);
+        self
+    }
+
+    pub fn with_rotation(mut self, degrees: Float) -> UvTransformTexture {
+        self.transform = self.transform.with_rotation(degrees);
+        self
+    }
+
+    pub fn with_offset(mut self, offset_u: Float, offset_v: Float) -> UvTransformTexture {
+        self.transform = self.transform.with_offset(offset_u, offset_v);
+        self
+    }
+}
+
+impl Texture for UvTransformTexture {
+    fn value(&self, u: Float, v: Float, p: Vec3) -> Vec3 {
+        self.value_filtered(u, v, p, UvDifferentials::default())
+    }
+
+    fn value_filtered(&self, u: Float, v: Float, p: Vec3, differentials: UvDifferentials) -> Vec3 {
+        let (u, v) = self.transform.point(u, v);
+        self.texture
+            .value_filtered(u, v, p, self.transform.differentials(differentials))
+    }
+}
+
+// A texture projected onto the surface along each of the three axes, with the position as its
+// texture coordinates, and blended by how squarely the surface faces each axis. Surfaces
+// without texture coordinates of their own, or with stretched ones, can be textured this way.
+//
+// The surface normal comes in `UvDifferentials::normal`, which the camera fills in with the
+// geometric normal of each hit. Lookups without one, through `value` or with default
+// differentials, such as cutout masks and bump heights, blend the three projections equally.
+pub struct TriplanarTexture {
+    texture: Arc<dyn Texture>,
+    scale: Float,     // Repeats of the texture per unit of distance
+    sharpness: Float, // Higher values narrow the blend where the projections meet
+}
+
+impl TriplanarTexture {
+    pub fn new(texture: Arc<dyn Texture>, scale: Float) -> TriplanarTexture {
+        TriplanarTexture {
+            texture,
+            scale,
+            sharpness: 4.0,
+        }
+    }
+
+    pub fn with_sharpness(mut self, sharpness: Float) -> TriplanarTexture {
+        self.sharpness = sharpness;
+        self
+    }
+}
+
+impl Texture for TriplanarTexture {
+    fn value(&self, u: Float, v: Float, p: Vec3) -> Vec3 {
+        self.value_filtered(u, v, p, UvDifferentials::default())
+    }
+
+    fn value_filtered(
+        &self,
+        _u: Float,
+        _v: Float,
+        p: Vec3,
+        differentials: UvDifferentials,
+    ) -> Vec3 {
+        // Without a normal every projection counts the same, see above. The texture coordinates
+        // of the projections don't follow the surface's, so their lookups aren't filtered.
+        let n = differentials.normal.abs();
+        let weights = [
+            n.x().powf(self.sharpness),
+            n.y().powf(self.sharpness),
+            n.z().powf(self.sharpness),
+        ];
+        let total: Float = weights.iter().sum();
+        let weights = if total > 0.0 {
+            weights.map(|w| w / total)
+        } else {
+            [1.0 / 3.0; 3]
+        };
+
+        let q = p * self.scale;
+        let differentials = UvDifferentials {
+            normal: differentials.normal,
+            ..Default::default()
+        };
+        let projections = [(q.z(), q.y()), (q.x(), q.z()), (q.x(), q.y())];
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        for (weight, (u, v)) in weights.into_iter().zip(projections) {
+            if weight > 0.0 {
+                sum = sum + weight * self.texture.value_filtered(u, v, p, differentials);
+            }
+        }
+        sum
+    }
+}
+
+// Gray ramp through space, 0 at `start` and 1 at `end` and constant across the line between
+// them. Feeding it to a `ColorRamp` or `MixTexture` colors objects by height or depth.
+pub struct GradientTexture {
+    start: Vec3,
+    end: Vec3,
+}
+
+impl GradientTexture {
+    pub fn new(start: Vec3, end: Vec3) -> GradientTexture {
+        GradientTexture { start, end }
+    }
+}
+
+impl Texture for GradientTexture {
+    fn value(&self, _u: Float, _v: Float, p: Vec3) -> Vec3 {
+        let axis = self.end - self.start;
+        let t = if axis.length_squared() > 0.0 {
+            ((p - self.start).dot(&axis) / axis.length_squared()).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        Vec3::new(t, t, t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textures::SolidColor;
+
+    // Shows the texture coordinates it is looked up at as red and green.
+    struct UvColor;
+
+    impl Texture for UvColor {
+        fn value(&self, u: Float, v: Float, _p: Vec3) -> Vec3 {
+            Vec3::new(u, v, 0.0)
+        }
+    }
+
+    fn solid(r: Float, g: Float, b: Float) -> Arc<dyn Texture> {
+        Arc::new(SolidColor::new(Vec3::new(r, g, b)))
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn triplanar_follows_the_normal() {
+        let triplanar = TriplanarTexture::new(Arc::new(UvColor), 2.0);
+        let p = Vec3::new(0.1, 0.2, 0.3);
+        let facing = |normal: Vec3| UvDifferentials {
+            normal,
+            ..Default::default()
+        };
+
+        // Facing straight along an axis, only the projection along it is seen, whichever side
+        // the surface faces.
+        let along_x = triplanar.value_filtered(0.0, 0.0, p, facing(Vec3::new(-1.0, 0.0, 0.0)));
+        assert_close(along_x, Vec3::new(0.6, 0.4, 0.0));
+        let along_y = triplanar.value_filtered(0.0, 0.0, p, facing(Vec3::new(0.0, 1.0, 0.0)));
+        assert_close(along_y, Vec3::new(0.2, 0.6, 0.0));
+        let along_z = triplanar.value_filtered(0.0, 0.0, p, facing(Vec3::new(0.0, 0.0, 1.0)));
+        assert_close(along_z, Vec3::new(0.2, 0.4, 0.0));
+    }
+
+    #[test]
+    fn triplanar_without_a_normal_blends_equally() {
+        let triplanar = TriplanarTexture::new(Arc::new(UvColor), 2.0);
+        let p = Vec3::new(0.1, 0.2, 0.3);
+        let equal =
+            (Vec3::new(0.6, 0.4, 0.0) + Vec3::new(0.2, 0.6, 0.0) + Vec3::new(0.2, 0.4, 0.0)) / 3.0;
+
+        assert_close(triplanar.value(0.7, 0.8, p), equal);
+        let filtered = triplanar.value_filtered(0.7, 0.8, p, UvDifferentials::default());
+        assert_close(filtered, equal);
+    }
+
+    #[test]
+    fn ramp_takes_the_first_color_for_nan() {
+        let ramp = ColorRamp::new(
+            solid(Float::NAN, Float::NAN, Float::NAN),
+            vec![
+                (0.2, Vec3::new(1.0, 0.0, 0.0)),
+                (0.8, Vec3::new(0.0, 0.0, 1.0)),
+            ],
+        );
+        assert_close(
+            ramp.value(0.0, 0.0, Vec3::new(0.0, 0.0, 0.0)),
+            Vec3::new(1.0, 0.0, 0.0),
+        );
+    }
+
+    fn at_origin(texture: &dyn Texture) -> Vec3 {
+        texture.value(0.0, 0.0, Vec3::new(0.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn mix_blends_by_the_factor() {
+        let (a, b) = (solid(1.0, 0.0, 0.0), solid(0.0, 0.5, 1.0));
+        let mix = MixTexture::new(a.clone(), b.clone(), solid(0.25, 0.25, 0.25));
+        assert_close(at_origin(&mix), Vec3::new(0.75, 0.125, 0.25));
+
+        // The factor is read as gray, and held to [0, 1].
+        let mix = MixTexture::new(a.clone(), b.clone(), solid(0.0, 0.3, 0.6));
+        assert_close(at_origin(&mix), Vec3::new(0.7, 0.15, 0.3));
+        let mix = MixTexture::new(a, b, solid(2.0, 2.0, 2.0));
+        assert_close(at_origin(&mix), Vec3::new(0.0, 0.5, 1.0));
+    }
+
+    #[test]
+    fn ramp_blends_between_stops() {
+        let (red, green, blue) = (
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        // Given out of order, to check they are sorted.
+        let stops = vec![(0.8, blue), (0.2, red), (0.4, green)];
+        let ramp = |t: Float| {
+            let ramp = ColorRamp::new(solid(t, t, t), stops.clone());
+            at_origin(&ramp)
+        };
+
+        assert_close(ramp(0.0), red);
+        assert_close(ramp(0.2), red);
+        assert_close(ramp(0.3), Vec3::new(0.5, 0.5, 0.0));
+        assert_close(ramp(0.4), green);
+        assert_close(ramp(0.7), Vec3::new(0.0, 0.25, 0.75));
+        assert_close(ramp(0.8), blue);
+        assert_close(ramp(1.5), blue);
+    }
+
+    #[test]
+    fn hue_saturation_round_trip() {
+        // Without changes every color comes back as it was.
+        let colors = [
+            Vec3::new(0.8, 0.3, 0.1),
+            Vec3::new(0.1, 0.7, 0.4),
+            Vec3::new(0.2, 0.3, 0.9),
+            Vec3::new(0.9, 0.1, 0.6),
+            Vec3::new(0.5, 0.5, 0.5),
+            Vec3::new(0.0, 0.0, 0.0),
+        ];
+        for c in colors {
+            let texture = HueSaturationTexture::new(solid(c.x(), c.y(), c.z()));
+            assert_close(at_origin(&texture), c);
+        }
+
+        // A third of a turn takes red to green, and two thirds to blue.
+        let red = solid(0.8, 0.2, 0.2);
+        let green = HueSaturationTexture::new(red.clone()).with_hue(120.0);
+        assert_close(at_origin(&green), Vec3::new(0.2, 0.8, 0.2));
+        let blue = HueSaturationTexture::new(red.clone()).with_hue(240.0);
+        assert_close(at_origin(&blue), Vec3::new(0.2, 0.2, 0.8));
+
+        // No saturation leaves the gray of the brightest channel, which value scales.
+        let gray = HueSaturationTexture::new(red)
+            .with_saturation(0.0)
+            .with_value(0.5);
+        assert_close(at_origin(&gray), Vec3::new(0.4, 0.4, 0.4));
+    }
+}
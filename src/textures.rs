@@ -1,10 +1,106 @@
-use crate::image::Image;
-use crate::utils::Interval;
+use crate::image::ColorSpace;
+use crate::mipmap::{FilterMode, MipMap, WrapMode};
+use crate::noise::{Fractal, FractalNoise, Noise, NoiseKind};
+use crate::texture_manager::{LoadError, TextureManager};
+use crate::utils::{degrees_to_radians, Float};
 use crate::vec3::Vec3;
 use std::sync::Arc;
 
 pub trait Texture: Send + Sync {
-    fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3;
+    fn value(&self, u: Float, v: Float, p: Vec3) -> Vec3;
+
+    fn value_filtered(&self, u: Float, v: Float, p: Vec3, _differentials: UvDifferentials) -> Vec3 {
+        // Average of the texture over the area around (u, v) that one pixel covers. Only
+        // textures with detail fine enough to alias need to do more than `value`.
+        self.value(u, v, p)
+    }
+
+    fn opacity(&self, u: Float, v: Float, p: Vec3) -> Float {
+        // How solid a surface cut out by this texture is at (u, v), from 0 for a hole to 1. By
+        // default the texture is read as gray.
+        gray(self.value(u, v, p))
+    }
+}
+
+pub fn gray(c: Vec3) -> Float {
+    // A color read as a single number, for textures used as masks, factors and heights.
+    (c.x() + c.y() + c.z()) / 3.0
+}
+
+// How far the texture coordinates move from one pixel to the next, across and down the image,
+// and which way the surface faces, for textures projected onto it from outside.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UvDifferentials {
+    pub dudx: Float,
+    pub dvdx: Float,
+    pub dudy: Float,
+    pub dvdy: Float,
+    pub normal: Vec3, // Geometric normal at the lookup, zero where there is no surface
+}
+
+// Texture coordinates scaled, rotated about the origin and offset, in that order. Shared by the
+// textures that can be tiled, turned and slid over a surface.
+#[derive(Debug, Clone, Copy)]
+pub struct UvTransform {
+    scale: (Float, Float),
+    rotation: Float, // Radians
+    offset: (Float, Float),
+}
+
+impl UvTransform {
+    pub fn new() -> UvTransform {
+        UvTransform {
+            scale: (1.0, 1.0),
+            rotation: 0.0,
+            offset: (0.0, 0.0),
+        }
+    }
+
+    pub fn with_scale(mut self, scale_u: Float, scale_v: Float) -> UvTransform {
+        // Scaling by 2 repeats a texture twice across the surface.
+        self.scale = (scale_u, scale_v);
+        self
+    }
+
+    pub fn with_rotation(mut self, degrees: Float) -> UvTransform {
+        self.rotation = degrees_to_radians(degrees);
+        self
+    }
+
+    pub fn with_offset(mut self, offset_u: Float, offset_v: Float) -> UvTransform {
+        self.offset = (offset_u, offset_v);
+        self
+    }
+
+    pub fn point(&self, u: Float, v: Float) -> (Float, Float) {
+        let (u, v) = self.vector(u, v);
+        (u + self.offset.0, v + self.offset.1)
+    }
+
+    pub fn differentials(&self, differentials: UvDifferentials) -> UvDifferentials {
+        let (dudx, dvdx) = self.vector(differentials.dudx, differentials.dvdx);
+        let (dudy, dvdy) = self.vector(differentials.dudy, differentials.dvdy);
+        UvDifferentials {
+            dudx,
+            dvdx,
+            dudy,
+            dvdy,
+            ..differentials
+        }
+    }
+
+    fn vector(&self, u: Float, v: Float) -> (Float, Float) {
+        // Differences between texture coordinates are scaled and rotated, but not offset.
+        let (sin, cos) = self.rotation.sin_cos();
+        let (u, v) = (u * self.scale.0, v * self.scale.1);
+        (cos * u - sin * v, sin * u + cos * v)
+    }
+}
+
+impl Default for UvTransform {
+    fn default() -> UvTransform {
+        UvTransform::new()
+    }
 }
 
 pub struct SolidColor {
@@ -18,19 +114,19 @@ impl SolidColor {
 }
 
 impl Texture for SolidColor {
-    fn value(&self, _u: f64, _v: f64, _p: Vec3) -> Vec3 {
+    fn value(&self, _u: Float, _v: Float, _p: Vec3) -> Vec3 {
         self.albedo
     }
 }
 
 pub struct CheckerTexture {
-    inv_scale: f64,
+    inv_scale: Float,
     even: Arc<dyn Texture>,
     odd: Arc<dyn Texture>,
 }
 
 impl CheckerTexture {
-    pub fn new(inv_scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> CheckerTexture {
+    pub fn new(inv_scale: Float, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> CheckerTexture {
         let inv_scale = 1.0 / inv_scale;
         CheckerTexture {
             inv_scale,
@@ -39,7 +135,7 @@ impl CheckerTexture {
         }
     }
 
-    pub fn new_from_rgb(inv_scale: f64, c1: Vec3, c2: Vec3) -> CheckerTexture {
+    pub fn new_from_rgb(inv_scale: Float, c1: Vec3, c2: Vec3) -> CheckerTexture {
         let inv_scale = 1.0 / inv_scale;
         let even = Arc::new(SolidColor::new(c1));
         let odd = Arc::new(SolidColor::new(c2));
@@ -50,49 +146,304 @@ impl CheckerTexture {
             odd,
         }
     }
-}
 
-impl Texture for CheckerTexture {
-    fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+    fn is_even(&self, p: Vec3) -> bool {
         let x_integer = (self.inv_scale * p.x()).floor() as i32;
         let y_integer = (self.inv_scale * p.y()).floor() as i32;
         let z_integer = (self.inv_scale * p.z()).floor() as i32;
 
-        let is_even = (x_integer + y_integer + z_integer) % 2 == 0;
+        (x_integer + y_integer + z_integer) % 2 == 0
+    }
+}
 
-        if is_even {
+impl Texture for CheckerTexture {
+    fn value(&self, u: Float, v: Float, p: Vec3) -> Vec3 {
+        if self.is_even(p) {
             self.even.value(u, v, p)
         } else {
             self.odd.value(u, v, p)
         }
     }
+
+    fn value_filtered(&self, u: Float, v: Float, p: Vec3, differentials: UvDifferentials) -> Vec3 {
+        if self.is_even(p) {
+            self.even.value_filtered(u, v, p, differentials)
+        } else {
+            self.odd.value_filtered(u, v, p, differentials)
+        }
+    }
 }
 
+// Image wrapped onto a surface by its uv coordinates, with v = 1 at the top of the image. The
+// uv coordinates can be scaled, rotated about the origin and offset before the lookup, in that
+// order.
 pub struct ImageTexture {
-    image: Image,
+    mipmap: MipMap,
+    filter: FilterMode,
+    uv: UvTransform,
 }
 
 impl ImageTexture {
-    pub fn new(filename: &str) -> ImageTexture {
-        let image = Image::new(filename).expect("");
-        ImageTexture { image }
+    pub fn new(filename: &str) -> Result<ImageTexture, LoadError> {
+        // A color image, such as a photograph, decoded from sRGB.
+        ImageTexture::new_with_color_space(filename, ColorSpace::Srgb)
+    }
+
+    pub fn new_data(filename: &str) -> Result<ImageTexture, LoadError> {
+        // An image of data, such as a normal or roughness map, whose values are used as stored.
+        ImageTexture::new_with_color_space(filename, ColorSpace::Linear)
+    }
+
+    pub fn new_with_color_space(
+        filename: &str,
+        color_space: ColorSpace,
+    ) -> Result<ImageTexture, LoadError> {
+        // Loads through the global `TextureManager`, so each file is only read once.
+        TextureManager::global().image_texture(filename, color_space)
+    }
+
+    pub fn new_from_mipmap(mipmap: MipMap) -> ImageTexture {
+        ImageTexture {
+            mipmap,
+            filter: FilterMode::Bilinear,
+            uv: UvTransform::new(),
+        }
+    }
+
+    pub fn with_wrap(mut self, wrap: WrapMode) -> ImageTexture {
+        self.mipmap.set_wrap(wrap);
+        self
+    }
+
+    pub fn with_filter(mut self, filter: FilterMode) -> ImageTexture {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_uv_scale(mut self, scale_u: Float, scale_v: Float) -> ImageTexture {
+        // Scaling by 2 repeats the image twice across the surface.
+        self.uv = self.uv.with_scale(scale_u, scale_v);
+        self
+    }
+
+    pub fn with_uv_rotation(mut self, degrees: Float) -> ImageTexture {
+        self.uv = self.uv.with_rotation(degrees);
+        self
+    }
+
+    pub fn with_uv_offset(mut self, offset_u: Float, offset_v: Float) -> ImageTexture {
+        self.uv = self.uv.with_offset(offset_u, offset_v);
+        self
     }
 }
 
 impl Texture for ImageTexture {
-    fn value(&self, mut u: f64, mut v: f64, _p: Vec3) -> Vec3 {
-        u = Interval::new(0.0, 1.0).clamp(u);
-        v = 1.0 - Interval::new(0.0, 1.0).clamp(v);
-
-        let i = (u * (self.image.width) as f64) as i32;
-        let j = (v * (self.image.height) as f64) as i32;
-        let pixel = self.image.pixel_data(i, j);
-
-        let color_scale = 1.0 / 255.0;
-        Vec3::new(
-            color_scale * pixel.0 as f64,
-            color_scale * pixel.1 as f64,
-            color_scale * pixel.2 as f64,
+    fn value(&self, u: Float, v: Float, p: Vec3) -> Vec3 {
+        self.value_filtered(u, v, p, UvDifferentials::default())
+    }
+
+    fn value_filtered(&self, u: Float, v: Float, _p: Vec3, differentials: UvDifferentials) -> Vec3 {
+        let (u, v) = self.uv.point(u, v);
+        let d = self.uv.differentials(differentials);
+
+        // Image rows run down from the top, against v.
+        self.mipmap.filter(
+            self.filter,
+            u,
+            1.0 - v,
+            (d.dudx, -d.dvdx),
+            (d.dudy, -d.dvdy),
         )
     }
+
+    fn opacity(&self, u: Float, v: Float, p: Vec3) -> Float {
+        // Images with alpha are cut out by it, and others are read as gray.
+        if !self.mipmap.has_alpha() {
+            return gray(self.value(u, v, p));
+        }
+        let (u, v) = self.uv.point(u, v);
+        self.mipmap.alpha(u, 1.0 - v)
+    }
+}
+
+// Fractal noise mapped onto a blend between two colors.
+pub struct NoiseTexture {
+    noise: FractalNoise,
+    scale: Float,
+    low: Vec3,
+    high: Vec3,
+}
+
+impl NoiseTexture {
+    pub fn new(noise: FractalNoise, scale: Float) -> NoiseTexture {
+        // `scale` is the frequency of the noise, so larger values give finer detail.
+        NoiseTexture {
+            noise,
+            scale,
+            low: Vec3::new(0.0, 0.0, 0.0),
+            high: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn with_colors(mut self, low: Vec3, high: Vec3) -> NoiseTexture {
+        self.low = low;
+        self.high = high;
+        self
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: Float, _v: Float, p: Vec3) -> Vec3 {
+        let t = self.noise.value(p * self.scale);
+        (1.0 - t) * self.low + t * self.high
+    }
+}
+
+// Stripes across the x axis, bent by turbulence into veins.
+pub struct MarbleTexture {
+    noise: FractalNoise,
+    scale: Float,
+    distortion: Float, // How far turbulence pushes the stripes around
+    base: Vec3,
+    vein: Vec3,
+}
+
+impl MarbleTexture {
+    pub fn new(scale: Float) -> MarbleTexture {
+        MarbleTexture {
+            noise: FractalNoise::new(NoiseKind::Perlin, Fractal::Turbulence),
+            scale,
+            distortion: 10.0,
+            base: Vec3::new(0.9, 0.9, 0.88),
+            vein: Vec3::new(0.25, 0.25, 0.3),
+        }
+    }
+
+    pub fn with_noise(mut self, noise: FractalNoise) -> MarbleTexture {
+        self.noise = noise;
+        self
+    }
+
+    pub fn with_distortion(mut self, distortion: Float) -> MarbleTexture {
+        self.distortion = distortion;
+        self
+    }
+
+    pub fn with_colors(mut self, base: Vec3, vein: Vec3) -> MarbleTexture {
+        self.base = base;
+        self.vein = vein;
+        self
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: Float, _v: Float, p: Vec3) -> Vec3 {
+        let p = p * self.scale;
+        let stripes = (p.x() + self.distortion * self.noise.value(p)).sin();
+        // Veins run where the stripes cross zero, narrowed by the power.
+        let t = (1.0 - stripes.abs()).powi(6);
+        (1.0 - t) * self.base + t * self.vein
+    }
+}
+
+// Growth rings around the y axis, wobbled by noise so they aren't perfect circles.
+pub struct WoodTexture {
+    noise: FractalNoise,
+    scale: Float,
+    distortion: Float,
+    light: Vec3,
+    dark: Vec3,
+}
+
+impl WoodTexture {
+    pub fn new(scale: Float) -> WoodTexture {
+        // `scale` is the number of rings per unit of distance from the axis.
+        WoodTexture {
+            noise: FractalNoise::new(NoiseKind::ImprovedPerlin, Fractal::Fbm).with_octaves(4),
+            scale,
+            distortion: 0.6,
+            light: Vec3::new(0.75, 0.5, 0.27),
+            dark: Vec3::new(0.4, 0.2, 0.08),
+        }
+    }
+
+    pub fn with_noise(mut self, noise: FractalNoise) -> WoodTexture {
+        self.noise = noise;
+        self
+    }
+
+    pub fn with_distortion(mut self, distortion: Float) -> WoodTexture {
+        self.distortion = distortion;
+        self
+    }
+
+    pub fn with_colors(mut self, light: Vec3, dark: Vec3) -> WoodTexture {
+        self.light = light;
+        self.dark = dark;
+        self
+    }
+}
+
+impl Texture for WoodTexture {
+    fn value(&self, _u: Float, _v: Float, p: Vec3) -> Vec3 {
+        let p = p * self.scale;
+        // The noise is stretched along the grain, which runs up the trunk.
+        let wobble = self.noise.value(Vec3::new(p.x(), 0.1 * p.y(), p.z())) - 0.5;
+        let radius = (p.x() * p.x() + p.z() * p.z()).sqrt() + self.distortion * wobble;
+        let ring = radius - radius.floor();
+        // Each ring fades slowly from early to late wood, then changes sharply.
+        let t = ring.powi(4);
+        (1.0 - t) * self.light + t * self.dark
+    }
+}
+
+// Speckled stone made of grains of a few minerals, from cellular noise, with fine fractal noise
+// shading each grain.
+pub struct GraniteTexture {
+    cells: Noise,
+    noise: FractalNoise,
+    scale: Float,
+    minerals: Vec<Vec3>,
+}
+
+impl GraniteTexture {
+    pub fn new(scale: Float) -> GraniteTexture {
+        // `scale` is the number of grains per unit of distance.
+        GraniteTexture {
+            cells: Noise::new(NoiseKind::Worley, 0),
+            noise: FractalNoise::new(NoiseKind::Simplex, Fractal::Fbm).with_octaves(4),
+            scale,
+            minerals: vec![
+                Vec3::new(0.75, 0.55, 0.5),  // Pink feldspar
+                Vec3::new(0.8, 0.8, 0.78),   // Quartz
+                Vec3::new(0.55, 0.53, 0.52), // Gray feldspar
+                Vec3::new(0.06, 0.06, 0.06), // Biotite
+            ],
+        }
+    }
+
+    pub fn with_noise(mut self, noise: FractalNoise) -> GraniteTexture {
+        self.noise = noise;
+        self
+    }
+
+    pub fn with_minerals(mut self, minerals: Vec<Vec3>) -> GraniteTexture {
+        // Colors of the grains, which are equally common.
+        if !minerals.is_empty() {
+            self.minerals = minerals;
+        }
+        self
+    }
+}
+
+impl Texture for GraniteTexture {
+    fn value(&self, _u: Float, _v: Float, p: Vec3) -> Vec3 {
+        let p = p * self.scale;
+        let (f1, f2, id) = self.cells.worley(p);
+        let mineral = self.minerals[(id * self.minerals.len() as Float) as usize];
+        // Darken the boundaries between grains and vary the shade within them.
+        let edge = ((f2 - f1) * 8.0).min(1.0);
+        let shade = 0.7 + 0.3 * edge + 0.3 * (self.noise.value(p * 4.0) - 0.5);
+        mineral * shade.max(0.0)
+    }
 }
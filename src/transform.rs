@@ -0,0 +1,253 @@
+use crate::utils::{degrees_to_radians, gamma, Float};
+use crate::vec3::Vec3;
+use std::ops::Mul;
+
+// Affine transform stored as the top three rows of a 4x4 matrix. The bottom row is
+// always (0, 0, 0, 1), so it is never stored.
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    m: [[Float; 4]; 3],
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+        ],
+    };
+
+    pub const fn new(m: [[Float; 4]; 3]) -> Transform {
+        Transform { m }
+    }
+
+    pub fn new_translation(offset: Vec3) -> Transform {
+        Transform::new([
+            [1.0, 0.0, 0.0, offset.x()],
+            [0.0, 1.0, 0.0, offset.y()],
+            [0.0, 0.0, 1.0, offset.z()],
+        ])
+    }
+
+    pub fn new_scale(scale: Vec3) -> Transform {
+        Transform::new([
+            [scale.x(), 0.0, 0.0, 0.0],
+            [0.0, scale.y(), 0.0, 0.0],
+            [0.0, 0.0, scale.z(), 0.0],
+        ])
+    }
+
+    pub fn new_rotation_x(angle: Float) -> Transform {
+        let (sin_theta, cos_theta) = degrees_to_radians(angle).sin_cos();
+        Transform::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, cos_theta, -sin_theta, 0.0],
+            [0.0, sin_theta, cos_theta, 0.0],
+        ])
+    }
+
+    // Uses the same orientation as `RotateY`, so both rotate a point the same way.
+    pub fn new_rotation_y(angle: Float) -> Transform {
+        let (sin_theta, cos_theta) = degrees_to_radians(angle).sin_cos();
+        Transform::new([
+            [cos_theta, 0.0, sin_theta, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [-sin_theta, 0.0, cos_theta, 0.0],
+        ])
+    }
+
+    pub fn new_rotation_z(angle: Float) -> Transform {
+        let (sin_theta, cos_theta) = degrees_to_radians(angle).sin_cos();
+        Transform::new([
+            [cos_theta, -sin_theta, 0.0, 0.0],
+            [sin_theta, cos_theta, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+        ])
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3],
+            m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3],
+            m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3],
+        )
+    }
+
+    pub fn transform_point_with_error(&self, p: Vec3, p_error: Vec3) -> (Vec3, Vec3) {
+        // Transforms a point that is already off by up to `p_error` in each coordinate, and
+        // returns it along with a bound on its error after the transform, as in PBRT.
+        let m = &self.m;
+        let error = |row: &[Float; 4]| {
+            (gamma(3) + 1.0)
+                * (row[0].abs() * p_error.x()
+                    + row[1].abs() * p_error.y()
+                    + row[2].abs() * p_error.z())
+                + gamma(3)
+                    * ((row[0] * p.x()).abs()
+                        + (row[1] * p.y()).abs()
+                        + (row[2] * p.z()).abs()
+                        + row[3].abs())
+        };
+        let error = Vec3::new(error(&m[0]), error(&m[1]), error(&m[2]));
+        (self.transform_point(p), error)
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+
+    pub fn transpose_vector(&self, v: Vec3) -> Vec3 {
+        // Multiplies by the transpose of the linear part. Applied to the inverse transform,
+        // this maps an object space normal to world space.
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x() + m[1][0] * v.y() + m[2][0] * v.z(),
+            m[0][1] * v.x() + m[1][1] * v.y() + m[2][1] * v.z(),
+            m[0][2] * v.x() + m[1][2] * v.y() + m[2][2] * v.z(),
+        )
+    }
+
+    pub fn inverse(&self) -> Transform {
+        let m = &self.m;
+
+        // Cofactors of the linear 3x3 part.
+        let c00 = m[1][1] * m[2][2] - m[1][2] * m[2][1];
+        let c01 = m[1][2] * m[2][0] - m[1][0] * m[2][2];
+        let c02 = m[1][0] * m[2][1] - m[1][1] * m[2][0];
+        let c10 = m[0][2] * m[2][1] - m[0][1] * m[2][2];
+        let c11 = m[0][0] * m[2][2] - m[0][2] * m[2][0];
+        let c12 = m[0][1] * m[2][0] - m[0][0] * m[2][1];
+        let c20 = m[0][1] * m[1][2] - m[0][2] * m[1][1];
+        let c21 = m[0][2] * m[1][0] - m[0][0] * m[1][2];
+        let c22 = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+
+        let det = m[0][0] * c00 + m[0][1] * c01 + m[0][2] * c02;
+        assert!(det != 0.0, "Transform is not invertible");
+        let inv_det = 1.0 / det;
+
+        let linear = Transform::new([
+            [c00 * inv_det, c10 * inv_det, c20 * inv_det, 0.0],
+            [c01 * inv_det, c11 * inv_det, c21 * inv_det, 0.0],
+            [c02 * inv_det, c12 * inv_det, c22 * inv_det, 0.0],
+        ]);
+
+        // The inverse translation is the original translation undone by the inverse rotation.
+        let t = -linear.transform_vector(Vec3::new(m[0][3], m[1][3], m[2][3]));
+        let mut inv = linear;
+        inv.m[0][3] = t.x();
+        inv.m[1][3] = t.y();
+        inv.m[2][3] = t.z();
+        inv
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::IDENTITY
+    }
+}
+
+impl Mul<Transform> for Transform {
+    type Output = Transform;
+
+    // `a * b` applies `b` first, then `a`.
+    fn mul(self, other: Transform) -> Transform {
+        let a = &self.m;
+        let b = &other.m;
+        let mut m = [[0.0; 4]; 3];
+
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j];
+            }
+            row[3] += a[i][3];
+        }
+
+        Transform { m }
+    }
+}
+
+// Pose of an animated object at a point in time. Rotation is given as Euler angles in degrees
+// and is applied in X, Y, Z order after scaling.
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe {
+    pub time: Float,
+    pub translation: Vec3,
+    pub rotation: Vec3,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    pub fn new(time: Float, translation: Vec3, rotation: Vec3, scale: Vec3) -> Keyframe {
+        Keyframe {
+            time,
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform::new_translation(self.translation)
+            * Transform::new_rotation_z(self.rotation.z())
+            * Transform::new_rotation_y(self.rotation.y())
+            * Transform::new_rotation_x(self.rotation.x())
+            * Transform::new_scale(self.scale)
+    }
+
+    fn lerp(a: &Keyframe, b: &Keyframe, time: Float) -> Keyframe {
+        let f = (time - a.time) / (b.time - a.time);
+        Keyframe {
+            time,
+            translation: a.translation * (1.0 - f) + b.translation * f,
+            rotation: a.rotation * (1.0 - f) + b.rotation * f,
+            scale: a.scale * (1.0 - f) + b.scale * f,
+        }
+    }
+}
+
+// Transform interpolated between keyframes. The components of each keyframe are interpolated
+// separately rather than the matrices, so rotations don't shrink the object mid-motion.
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    keyframes: Vec<Keyframe>,
+}
+
+impl AnimatedTransform {
+    pub fn new(mut keyframes: Vec<Keyframe>) -> AnimatedTransform {
+        assert!(
+            !keyframes.is_empty(),
+            "AnimatedTransform needs at least one keyframe"
+        );
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        AnimatedTransform { keyframes }
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    pub fn at(&self, time: Float) -> Transform {
+        // Times outside the keyframe range hold the first or last pose.
+        let first = &self.keyframes[0];
+        let last = &self.keyframes[self.keyframes.len() - 1];
+        if time <= first.time {
+            return first.transform();
+        }
+        if time >= last.time {
+            return last.transform();
+        }
+
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        let a = &self.keyframes[next - 1];
+        let b = &self.keyframes[next];
+        Keyframe::lerp(a, b, time).transform()
+    }
+}
@@ -1,29 +1,96 @@
 use crate::vec3::Vec3;
 use rand::Rng;
-use std::f64::consts::PI;
 use std::ops::Add;
 
-pub fn random_double() -> f64 {
+// Floating point type used by the renderer. Building with the `f32` feature halves the size of
+// vectors, rays and BVH nodes at the cost of precision.
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+pub const PI: Float = std::f64::consts::PI as Float;
+
+pub fn random_double() -> Float {
     // Returns a random real in [0, 1).
-    let mut rng = rand::thread_rng();
-    rng.random::<f64>()
+    let mut rng = rand::rng();
+    rng.random::<Float>()
 }
 
-pub fn random_double_range(min: f64, max: f64) -> f64 {
+pub fn random_double_range(min: Float, max: Float) -> Float {
     // Returns a random real in [min, max).
-    let mut rng = rand::thread_rng();
-    rng.gen_range(min..max)
+    let mut rng = rand::rng();
+    rng.random_range(min..max)
 }
-pub fn degrees_to_radians(degrees: f64) -> f64 {
+pub fn degrees_to_radians(degrees: Float) -> Float {
     degrees * PI / 180.0
 }
 
+// Bound on the relative rounding error of `n` consecutive floating point operations, as in PBRT.
+pub fn gamma(n: i32) -> Float {
+    let machine_epsilon = Float::EPSILON * 0.5;
+    (n as Float * machine_epsilon) / (1.0 - n as Float * machine_epsilon)
+}
+
+pub fn next_float_up(v: Float) -> Float {
+    // Returns the smallest representable value greater than v.
+    if v.is_infinite() && v > 0.0 {
+        return v;
+    }
+    // Turns -0.0 into 0.0, so the step below goes to the smallest positive value.
+    let v = if v == 0.0 { 0.0 } else { v };
+    let bits = v.to_bits();
+    if v >= 0.0 {
+        Float::from_bits(bits + 1)
+    } else {
+        Float::from_bits(bits - 1)
+    }
+}
+
+pub fn next_float_down(v: Float) -> Float {
+    // Returns the largest representable value less than v.
+    if v.is_infinite() && v < 0.0 {
+        return v;
+    }
+    let v = if v == 0.0 { -0.0 } else { v };
+    let bits = v.to_bits();
+    if v > 0.0 {
+        Float::from_bits(bits - 1)
+    } else {
+        Float::from_bits(bits + 1)
+    }
+}
+
+pub fn hash_float(values: &[Float]) -> Float {
+    // A number in [0, 1) that looks random but always comes out the same for the same values,
+    // as PBRT's `HashFloat`. The bits of each value are mixed in with the finalizer of
+    // MurmurHash3.
+    let mut hash: u64 = 0;
+    for value in values {
+        // The cast is only needed when `Float` is f32.
+        #[allow(clippy::unnecessary_cast)]
+        let bits = (*value as f64).to_bits();
+        hash ^= bits;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        hash ^= hash >> 33;
+    }
+    // The top 24 bits, which even an f32 holds exactly, so the result stays below 1.
+    (hash >> 40) as Float / (1u64 << 24) as Float
+}
+
 pub fn _random_int_range(min: i32, max: i32) -> i32 {
-    random_double_range((min) as f64, (max + 1) as f64) as i32
+    random_double_range((min) as Float, (max + 1) as Float) as i32
 }
 
 // Maps normalized UV coordinates to the specified UV range for a face
-pub fn map_uv_to_range(u: f64, v: f64, uv_range: &((f64, f64), (f64, f64))) -> (f64, f64) {
+pub fn map_uv_to_range(
+    u: Float,
+    v: Float,
+    uv_range: &((Float, Float), (Float, Float)),
+) -> (Float, Float) {
     let (u_min, v_min) = uv_range.0;
     let (u_max, v_max) = uv_range.1;
 
@@ -40,12 +107,12 @@ pub fn map_uv_to_range(u: f64, v: f64, uv_range: &((f64, f64), (f64, f64))) -> (
 
 #[derive(Debug, Clone, Copy)]
 pub struct Interval {
-    pub min: f64,
-    pub max: f64,
+    pub min: Float,
+    pub max: Float,
 }
 
 impl Interval {
-    pub const fn new(min: f64, max: f64) -> Interval {
+    pub const fn new(min: Float, max: Float) -> Interval {
         Interval { min, max }
     }
 
@@ -56,19 +123,19 @@ impl Interval {
         Interval { min, max }
     }
 
-    pub fn size(&self) -> f64 {
+    pub fn size(&self) -> Float {
         self.max - self.min
     }
 
-    pub fn contains(&self, x: f64) -> bool {
+    pub fn contains(&self, x: Float) -> bool {
         self.min <= x && x <= self.max
     }
 
-    pub fn surrounds(&self, x: f64) -> bool {
+    pub fn surrounds(&self, x: Float) -> bool {
         self.min < x && x < self.max
     }
 
-    pub fn clamp(&self, x: f64) -> f64 {
+    pub fn clamp(&self, x: Float) -> Float {
         if x < self.min {
             return self.min;
         }
@@ -78,13 +145,13 @@ impl Interval {
         x
     }
 
-    pub fn expand(&self, delta: f64) -> Interval {
+    pub fn expand(&self, delta: Float) -> Interval {
         let padding = delta / 2.0;
         Interval::new(self.min - padding, self.max + padding)
     }
 
-    pub const EMPTY: Interval = Interval::new(f64::INFINITY, f64::NEG_INFINITY);
-    pub const UNIVERSE: Interval = Interval::new(f64::NEG_INFINITY, f64::INFINITY);
+    pub const EMPTY: Interval = Interval::new(Float::INFINITY, Float::NEG_INFINITY);
+    pub const UNIVERSE: Interval = Interval::new(Float::NEG_INFINITY, Float::INFINITY);
 }
 
 impl Default for Interval {
@@ -93,15 +160,15 @@ impl Default for Interval {
     }
 }
 
-impl Add<f64> for Interval {
+impl Add<Float> for Interval {
     type Output = Interval;
 
-    fn add(self, displacement: f64) -> Interval {
+    fn add(self, displacement: Float) -> Interval {
         Interval::new(self.min + displacement, self.max + displacement)
     }
 }
 
-pub fn rotate_y_translation(cube_center: Vec3, rotation_angle_deg: f64) -> Vec3 {
+pub fn rotate_y_translation(cube_center: Vec3, rotation_angle_deg: Float) -> Vec3 {
     let theta_rad = rotation_angle_deg * PI / 180.0;
 
     let (x, z) = (cube_center.x(), cube_center.z());
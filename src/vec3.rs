@@ -1,40 +1,40 @@
-use crate::utils::{random_double, random_double_range};
+use crate::utils::{random_double, random_double_range, Float};
 use std::cmp::PartialEq;
 use std::ops::{Add, Div, Index, Mul, Neg, Sub};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Vec3 {
-    x: f64,
-    y: f64,
-    z: f64,
+    x: Float,
+    y: Float,
+    z: Float,
 }
 
 impl Vec3 {
-    pub fn new(x: f64, y: f64, z: f64) -> Vec3 {
+    pub fn new(x: Float, y: Float, z: Float) -> Vec3 {
         Vec3 { x, y, z }
     }
 
-    pub fn x(&self) -> f64 {
+    pub fn x(&self) -> Float {
         self.x
     }
 
-    pub fn y(&self) -> f64 {
+    pub fn y(&self) -> Float {
         self.y
     }
 
-    pub fn z(&self) -> f64 {
+    pub fn z(&self) -> Float {
         self.z
     }
 
-    pub fn length_squared(&self) -> f64 {
+    pub fn length_squared(&self) -> Float {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
-    pub fn length(&self) -> f64 {
-        f64::sqrt(self.length_squared())
+    pub fn length(&self) -> Float {
+        Float::sqrt(self.length_squared())
     }
 
-    pub fn dot(&self, other: &Vec3) -> f64 {
+    pub fn dot(&self, other: &Vec3) -> Float {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
 
@@ -56,7 +56,7 @@ impl Vec3 {
     }
 
     pub fn random_unit_vector() -> Vec3 {
-        return Vec3::unit_vector(Vec3::random_in_unit_sphere());
+        Vec3::unit_vector(Vec3::random_in_unit_sphere())
     }
 
     pub fn random_on_hemisphere(normal: &Vec3) -> Vec3 {
@@ -91,10 +91,10 @@ impl Vec3 {
     }
 
     pub fn random() -> Vec3 {
-        return Vec3::new(random_double(), random_double(), random_double());
+        Vec3::new(random_double(), random_double(), random_double())
     }
 
-    pub fn random_range(min: f64, max: f64) -> Vec3 {
+    pub fn random_range(min: Float, max: Float) -> Vec3 {
         Vec3::new(
             random_double_range(min, max),
             random_double_range(min, max),
@@ -102,18 +102,24 @@ impl Vec3 {
         )
     }
 
+    pub fn abs(&self) -> Vec3 {
+        Vec3::new(self.x.abs(), self.y.abs(), self.z.abs())
+    }
+
     pub fn near_zero(&self) -> bool {
-        self.x.abs() < f64::EPSILON && self.y.abs() < f64::EPSILON && self.z.abs() < f64::EPSILON
+        self.x.abs() < Float::EPSILON
+            && self.y.abs() < Float::EPSILON
+            && self.z.abs() < Float::EPSILON
     }
 
     pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
-        return v - 2.0 * Vec3::dot(&v, &n) * n;
+        v - 2.0 * Vec3::dot(&v, &n) * n
     }
 
-    pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f64) -> Vec3 {
+    pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: Float) -> Vec3 {
         let cos_theta = ((-*uv).dot(n)).min(1.0);
         let r_out_perp = (*uv + *n * cos_theta) * etai_over_etat;
-        let r_out_parallel = *n * (-1.0 * (1.0 - r_out_perp.length_squared()).abs().sqrt());
+        let r_out_parallel = *n * -(1.0 - r_out_perp.length_squared()).abs().sqrt();
         r_out_perp + r_out_parallel
     }
 
@@ -175,7 +181,7 @@ impl Mul<Vec3> for Vec3 {
     }
 }
 
-impl Mul<Vec3> for f64 {
+impl Mul<Vec3> for Float {
     type Output = Vec3;
 
     fn mul(self, other: Vec3) -> Vec3 {
@@ -187,7 +193,7 @@ impl Mul<Vec3> for f64 {
     }
 }
 
-impl Div<Vec3> for f64 {
+impl Div<Vec3> for Float {
     type Output = Vec3;
 
     fn div(self, other: Vec3) -> Vec3 {
@@ -211,10 +217,10 @@ impl Div<Vec3> for Vec3 {
     }
 }
 
-impl Mul<f64> for Vec3 {
+impl Mul<Float> for Vec3 {
     type Output = Vec3;
 
-    fn mul(self, other: f64) -> Vec3 {
+    fn mul(self, other: Float) -> Vec3 {
         Vec3 {
             x: self.x * other,
             y: self.y * other,
@@ -223,10 +229,10 @@ impl Mul<f64> for Vec3 {
     }
 }
 
-impl Div<f64> for Vec3 {
+impl Div<Float> for Vec3 {
     type Output = Vec3;
 
-    fn div(self, other: f64) -> Vec3 {
+    fn div(self, other: Float) -> Vec3 {
         Vec3 {
             x: self.x / other,
             y: self.y / other,
@@ -242,7 +248,7 @@ impl PartialEq for Vec3 {
 }
 
 impl Index<usize> for Vec3 {
-    type Output = f64;
+    type Output = Float;
 
     fn index(&self, index: usize) -> &Self::Output {
         match index {
@@ -0,0 +1,606 @@
+use crate::aabb::Aabb;
+use crate::bvh::{BuildNode, BvhBuild, BvhStats};
+use crate::hittables::{HitRecord, Hittable, HittableList};
+use crate::ray::Ray;
+use crate::utils::{Float, Interval};
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+const WIDTH: usize = 4;
+
+// Every level of the binary tree can push up to three children, see `MAX_SAH_DEPTH`.
+const STACK_SIZE: usize = 384;
+
+// Four bounding boxes stored axis by axis, so one ray can be tested against all of them with a
+// single SIMD operation per slab.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb4 {
+    min: [[Float; WIDTH]; 3],
+    max: [[Float; WIDTH]; 3],
+}
+
+impl Aabb4 {
+    pub const EMPTY: Aabb4 = Aabb4 {
+        min: [[Float::INFINITY; WIDTH]; 3],
+        max: [[Float::NEG_INFINITY; WIDTH]; 3],
+    };
+
+    pub fn new(boxes: &[Aabb]) -> Aabb4 {
+        let mut packed = Aabb4::EMPTY;
+        for (lane, bbox) in boxes.iter().take(WIDTH).enumerate() {
+            packed.set(lane, bbox);
+        }
+        packed
+    }
+
+    pub fn set(&mut self, lane: usize, bbox: &Aabb) {
+        for axis in 0..3 {
+            let interval = bbox.axis_interval(axis as i32);
+            self.min[axis][lane] = interval.min;
+            self.max[axis][lane] = interval.max;
+        }
+    }
+
+    pub fn get(&self, lane: usize) -> Aabb {
+        Aabb::new_const(
+            Interval::new(self.min[0][lane], self.max[0][lane]),
+            Interval::new(self.min[1][lane], self.max[1][lane]),
+            Interval::new(self.min[2][lane], self.max[2][lane]),
+        )
+    }
+
+    pub fn hit(&self, origin: Vec3, inv_direction: Vec3, ray_t: Interval) -> (u8, [Float; WIDTH]) {
+        // Returns a bit mask of the boxes hit by the ray and the distance at which it enters each
+        // box. Empty boxes are never hit.
+        //
+        // SSE and SSE2 are always available on x86_64. Double precision uses AVX when the CPU
+        // has it, checked at run time so every build (and every test run) takes that path on
+        // such CPUs. Single precision fits all four boxes in one SSE register.
+        #[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+        {
+            // The detection result is cached by std, so this is a load and a branch.
+            if is_x86_feature_detected!("avx") {
+                // SAFETY: AVX was just detected.
+                unsafe { self.hit_avx(origin, inv_direction, ray_t) }
+            } else {
+                self.hit_sse2(origin, inv_direction, ray_t)
+            }
+        }
+        #[cfg(all(target_arch = "x86_64", feature = "f32"))]
+        {
+            self.hit_sse(origin, inv_direction, ray_t)
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            self.hit_scalar(origin, inv_direction, ray_t)
+        }
+    }
+
+    fn slabs(&self, axis: usize, inv_direction: Vec3) -> (&[Float; WIDTH], &[Float; WIDTH]) {
+        // Picks the near and far planes of each slab based on the direction of the ray, so an
+        // empty box (min above max) always yields an empty interval.
+        if inv_direction[axis] < 0.0 {
+            (&self.max[axis], &self.min[axis])
+        } else {
+            (&self.min[axis], &self.max[axis])
+        }
+    }
+
+    pub fn hit_scalar(
+        &self,
+        origin: Vec3,
+        inv_direction: Vec3,
+        ray_t: Interval,
+    ) -> (u8, [Float; WIDTH]) {
+        let mut t_min = [ray_t.min; WIDTH];
+        let mut t_max = [ray_t.max; WIDTH];
+        let far_scale = Aabb::far_scale();
+
+        for axis in 0..3 {
+            let (near, far) = self.slabs(axis, inv_direction);
+            for lane in 0..WIDTH {
+                let t0 = (near[lane] - origin[axis]) * inv_direction[axis];
+                let t1 = (far[lane] - origin[axis]) * inv_direction[axis] * far_scale;
+                // `max` and `min` ignore a NaN from 0 * inf, matching the SIMD path.
+                t_min[lane] = t_min[lane].max(t0);
+                t_max[lane] = t_max[lane].min(t1);
+            }
+        }
+
+        let mut mask = 0;
+        for lane in 0..WIDTH {
+            if t_min[lane] < t_max[lane] {
+                mask |= 1 << lane;
+            }
+        }
+        (mask, t_min)
+    }
+
+    // Callers must check that the CPU supports AVX.
+    #[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+    #[target_feature(enable = "avx")]
+    unsafe fn hit_avx(
+        &self,
+        origin: Vec3,
+        inv_direction: Vec3,
+        ray_t: Interval,
+    ) -> (u8, [Float; WIDTH]) {
+        use std::arch::x86_64::*;
+
+        // SAFETY: the caller checked for AVX, and the loads and stores stay within arrays of
+        // four f64.
+        unsafe {
+            let mut t_min = _mm256_set1_pd(ray_t.min);
+            let mut t_max = _mm256_set1_pd(ray_t.max);
+            let far_scale = _mm256_set1_pd(Aabb::far_scale());
+
+            for axis in 0..3 {
+                let (near, far) = self.slabs(axis, inv_direction);
+                let o = _mm256_set1_pd(origin[axis]);
+                let inv = _mm256_set1_pd(inv_direction[axis]);
+
+                let t0 = _mm256_mul_pd(_mm256_sub_pd(_mm256_loadu_pd(near.as_ptr()), o), inv);
+                let t1 = _mm256_mul_pd(_mm256_sub_pd(_mm256_loadu_pd(far.as_ptr()), o), inv);
+                let t1 = _mm256_mul_pd(t1, far_scale);
+
+                // With a NaN in the first operand these return the second one, like the scalar
+                // path.
+                t_min = _mm256_max_pd(t0, t_min);
+                t_max = _mm256_min_pd(t1, t_max);
+            }
+
+            let mask = _mm256_movemask_pd(_mm256_cmp_pd(t_min, t_max, _CMP_LT_OQ)) as u8;
+            let mut t = [0.0; WIDTH];
+            _mm256_storeu_pd(t.as_mut_ptr(), t_min);
+            (mask, t)
+        }
+    }
+
+    #[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+    fn hit_sse2(&self, origin: Vec3, inv_direction: Vec3, ray_t: Interval) -> (u8, [Float; WIDTH]) {
+        use std::arch::x86_64::*;
+
+        // SAFETY: SSE2 is part of the x86_64 baseline, and the loads and stores stay within
+        // arrays of four f64.
+        unsafe {
+            // Two boxes per register, lanes 0-1 in `lo` and lanes 2-3 in `hi`.
+            let mut t_min_lo = _mm_set1_pd(ray_t.min);
+            let mut t_min_hi = t_min_lo;
+            let mut t_max_lo = _mm_set1_pd(ray_t.max);
+            let mut t_max_hi = t_max_lo;
+            let far_scale = _mm_set1_pd(Aabb::far_scale());
+
+            for axis in 0..3 {
+                let (near, far) = self.slabs(axis, inv_direction);
+                let o = _mm_set1_pd(origin[axis]);
+                let inv = _mm_set1_pd(inv_direction[axis]);
+
+                let t0_lo = _mm_mul_pd(_mm_sub_pd(_mm_loadu_pd(near.as_ptr()), o), inv);
+                let t0_hi = _mm_mul_pd(_mm_sub_pd(_mm_loadu_pd(near.as_ptr().add(2)), o), inv);
+                let t1_lo = _mm_mul_pd(_mm_sub_pd(_mm_loadu_pd(far.as_ptr()), o), inv);
+                let t1_hi = _mm_mul_pd(_mm_sub_pd(_mm_loadu_pd(far.as_ptr().add(2)), o), inv);
+                let t1_lo = _mm_mul_pd(t1_lo, far_scale);
+                let t1_hi = _mm_mul_pd(t1_hi, far_scale);
+
+                // With a NaN in the first operand these return the second one, like the scalar
+                // path.
+                t_min_lo = _mm_max_pd(t0_lo, t_min_lo);
+                t_min_hi = _mm_max_pd(t0_hi, t_min_hi);
+                t_max_lo = _mm_min_pd(t1_lo, t_max_lo);
+                t_max_hi = _mm_min_pd(t1_hi, t_max_hi);
+            }
+
+            let mask_lo = _mm_movemask_pd(_mm_cmplt_pd(t_min_lo, t_max_lo));
+            let mask_hi = _mm_movemask_pd(_mm_cmplt_pd(t_min_hi, t_max_hi));
+            let mut t = [0.0; WIDTH];
+            _mm_storeu_pd(t.as_mut_ptr(), t_min_lo);
+            _mm_storeu_pd(t.as_mut_ptr().add(2), t_min_hi);
+            ((mask_lo | mask_hi << 2) as u8, t)
+        }
+    }
+
+    #[cfg(all(target_arch = "x86_64", feature = "f32"))]
+    fn hit_sse(&self, origin: Vec3, inv_direction: Vec3, ray_t: Interval) -> (u8, [Float; WIDTH]) {
+        use std::arch::x86_64::*;
+
+        // SAFETY: SSE is part of the x86_64 baseline, and the loads and stores stay within
+        // arrays of four f32.
+        unsafe {
+            let mut t_min = _mm_set1_ps(ray_t.min);
+            let mut t_max = _mm_set1_ps(ray_t.max);
+            let far_scale = _mm_set1_ps(Aabb::far_scale());
+
+            for axis in 0..3 {
+                let (near, far) = self.slabs(axis, inv_direction);
+                let o = _mm_set1_ps(origin[axis]);
+                let inv = _mm_set1_ps(inv_direction[axis]);
+
+                let t0 = _mm_mul_ps(_mm_sub_ps(_mm_loadu_ps(near.as_ptr()), o), inv);
+                let t1 = _mm_mul_ps(_mm_sub_ps(_mm_loadu_ps(far.as_ptr()), o), inv);
+                let t1 = _mm_mul_ps(t1, far_scale);
+
+                // With a NaN in the first operand these return the second one, like the scalar
+                // path.
+                t_min = _mm_max_ps(t0, t_min);
+                t_max = _mm_min_ps(t1, t_max);
+            }
+
+            let mask = _mm_movemask_ps(_mm_cmplt_ps(t_min, t_max)) as u8;
+            let mut t = [0.0; WIDTH];
+            _mm_storeu_ps(t.as_mut_ptr(), t_min);
+            (mask, t)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct WideNode {
+    bounds: Aabb4,
+    children: [u32; WIDTH], // Interior child: node index. Leaf child: index of the first primitive.
+    counts: [u16; WIDTH],   // Number of primitives of a leaf child, zero for interior children
+    child_count: u8,
+}
+
+// BVH with four children per node, collapsed from the binary SAH tree. Each node tests the ray
+// against all four child boxes at once and visits the hit children nearest first.
+pub struct WideBvh {
+    nodes: Vec<WideNode>,
+    primitives: Vec<Arc<dyn Hittable>>,
+    bbox: Aabb,
+}
+
+impl WideBvh {
+    pub fn new_from_list(list: &HittableList) -> Self {
+        WideBvh::new_with_stats(&list.objects).0
+    }
+
+    pub fn new(objects: &[Arc<dyn Hittable>]) -> Self {
+        WideBvh::new_with_stats(objects).0
+    }
+
+    pub fn new_with_stats(objects: &[Arc<dyn Hittable>]) -> (Self, BvhStats) {
+        let build = BvhBuild::new(objects, false);
+
+        let mut nodes = Vec::new();
+        match &build.root {
+            BuildNode::Interior { .. } => {
+                Self::collapse(&build.root, &mut nodes);
+            }
+            BuildNode::Leaf { bbox, start, count } => {
+                // A tree with a single leaf becomes a root with one child.
+                let mut node = WideNode {
+                    bounds: Aabb4::EMPTY,
+                    children: [0; WIDTH],
+                    counts: [0; WIDTH],
+                    child_count: 0,
+                };
+                if *count > 0 {
+                    node.bounds.set(0, bbox);
+                    node.children[0] = *start as u32;
+                    node.counts[0] = *count as u16;
+                    node.child_count = 1;
+                }
+                nodes.push(node);
+            }
+        }
+
+        let bvh = WideBvh {
+            nodes,
+            primitives: build.objects,
+            bbox: build.root.bbox(),
+        };
+        (bvh, build.stats)
+    }
+
+    fn collapse(node: &BuildNode, nodes: &mut Vec<WideNode>) -> usize {
+        // Gathers up to four descendants of a binary interior node by repeatedly opening the
+        // interior child with the largest surface area.
+        let mut children: Vec<&BuildNode> = match node {
+            BuildNode::Interior { left, right, .. } => vec![left, right],
+            BuildNode::Leaf { .. } => unreachable!("only interior nodes are collapsed"),
+        };
+
+        while children.len() < WIDTH {
+            let largest = children
+                .iter()
+                .enumerate()
+                .filter(|(_, child)| matches!(child, BuildNode::Interior { .. }))
+                .max_by(|(_, a), (_, b)| {
+                    a.bbox().surface_area().total_cmp(&b.bbox().surface_area())
+                })
+                .map(|(i, _)| i);
+
+            match largest {
+                Some(i) => match children.swap_remove(i) {
+                    BuildNode::Interior { left, right, .. } => {
+                        children.push(left);
+                        children.push(right);
+                    }
+                    BuildNode::Leaf { .. } => unreachable!(),
+                },
+                None => break,
+            }
+        }
+
+        let index = nodes.len();
+        nodes.push(WideNode {
+            bounds: Aabb4::EMPTY,
+            children: [0; WIDTH],
+            counts: [0; WIDTH],
+            child_count: children.len() as u8,
+        });
+
+        for (lane, child) in children.iter().enumerate() {
+            nodes[index].bounds.set(lane, &child.bbox());
+            match child {
+                BuildNode::Leaf { start, count, .. } => {
+                    nodes[index].children[lane] = *start as u32;
+                    nodes[index].counts[lane] = *count as u16;
+                }
+                BuildNode::Interior { .. } => {
+                    nodes[index].children[lane] = Self::collapse(child, nodes) as u32;
+                }
+            }
+        }
+
+        index
+    }
+}
+
+impl Hittable for WideBvh {
+    fn hit(&self, r: Ray, ray_t: Interval) -> Option<HitRecord<'_>> {
+        let inv_direction = Vec3::new(
+            1.0 / r.direction.x(),
+            1.0 / r.direction.y(),
+            1.0 / r.direction.z(),
+        );
+
+        let mut temp_rec = None;
+        let mut closest_so_far = ray_t.max;
+
+        // Each entry is a node index with the distance at which the ray enters its box.
+        let mut stack = [(0u32, 0.0); STACK_SIZE];
+        let mut stack_size = 1;
+        stack[0] = (0, ray_t.min);
+
+        while stack_size > 0 {
+            stack_size -= 1;
+            let (node_index, t_enter) = stack[stack_size];
+
+            // Skip nodes that are behind a hit found since they were pushed.
+            if t_enter >= closest_so_far {
+                continue;
+            }
+
+            let node = &self.nodes[node_index as usize];
+            let node_t = Interval::new(ray_t.min, closest_so_far);
+            let (mut mask, t_near) = node.bounds.hit(r.origin, inv_direction, node_t);
+            mask &= (1u8 << node.child_count) - 1;
+
+            // Sort the hit children by entry distance, nearest first.
+            let mut order = [0usize; WIDTH];
+            let mut hit_count = 0;
+            for lane in 0..WIDTH {
+                if mask & (1 << lane) == 0 {
+                    continue;
+                }
+                let mut i = hit_count;
+                while i > 0 && t_near[order[i - 1]] > t_near[lane] {
+                    order[i] = order[i - 1];
+                    i -= 1;
+                }
+                order[i] = lane;
+                hit_count += 1;
+            }
+
+            // Test leaves right away and push interior children farthest first, so the nearest
+            // is popped next.
+            for &lane in order[..hit_count].iter().rev() {
+                let count = node.counts[lane] as usize;
+                if count == 0 {
+                    stack[stack_size] = (node.children[lane], t_near[lane]);
+                    stack_size += 1;
+                }
+            }
+            for &lane in &order[..hit_count] {
+                let count = node.counts[lane] as usize;
+                if count == 0 || t_near[lane] >= closest_so_far {
+                    continue;
+                }
+                let start = node.children[lane] as usize;
+                for object in &self.primitives[start..start + count] {
+                    if let Some(hit) = object.hit(r, Interval::new(ray_t.min, closest_so_far)) {
+                        closest_so_far = hit.t;
+                        temp_rec = Some(hit);
+                    }
+                }
+            }
+        }
+
+        temp_rec
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn check(boxes: &[Aabb], origin: Vec3, direction: Vec3) {
+        // The SIMD and scalar paths of `Aabb4::hit` must agree with each other, down to the entry
+        // distances, and with `Aabb::hit` on every box. Lanes without a box are never hit.
+        let r = Ray::new(origin, direction, 0.0);
+        let inv_direction = Vec3::new(
+            1.0 / direction.x(),
+            1.0 / direction.y(),
+            1.0 / direction.z(),
+        );
+        let ray_t = Interval::new(0.001, Float::INFINITY);
+        let packed = Aabb4::new(boxes);
+
+        let (simd_mask, simd_t) = packed.hit(origin, inv_direction, ray_t);
+        let (scalar_mask, scalar_t) = packed.hit_scalar(origin, inv_direction, ray_t);
+        // `hit` takes the AVX path on CPUs that have it, so check the SSE2 fallback directly.
+        #[cfg(all(target_arch = "x86_64", not(feature = "f32")))]
+        {
+            let (sse2_mask, sse2_t) = packed.hit_sse2(origin, inv_direction, ray_t);
+            assert_eq!(sse2_mask, scalar_mask);
+            for lane in 0..WIDTH {
+                if sse2_mask >> lane & 1 == 1 {
+                    assert_eq!(sse2_t[lane].to_bits(), scalar_t[lane].to_bits());
+                }
+            }
+        }
+        assert_eq!(
+            simd_mask, scalar_mask,
+            "boxes {:?}, origin {:?}, direction {:?}",
+            boxes, origin, direction
+        );
+        for lane in 0..WIDTH {
+            let expected = lane < boxes.len() && boxes[lane].hit(r, ray_t);
+            assert_eq!(
+                scalar_mask >> lane & 1 == 1,
+                expected,
+                "lane {} of boxes {:?}, origin {:?}, direction {:?}",
+                lane,
+                boxes,
+                origin,
+                direction
+            );
+            if expected {
+                assert_eq!(simd_t[lane].to_bits(), scalar_t[lane].to_bits());
+            }
+        }
+    }
+
+    fn random_vec3(rng: &mut StdRng, min: Float, max: Float) -> Vec3 {
+        Vec3::new(
+            rng.random_range(min..max),
+            rng.random_range(min..max),
+            rng.random_range(min..max),
+        )
+    }
+
+    fn with_axis(v: Vec3, axis: usize, value: Float) -> Vec3 {
+        let mut e = [v.x(), v.y(), v.z()];
+        e[axis] = value;
+        Vec3::new(e[0], e[1], e[2])
+    }
+
+    fn random_boxes(rng: &mut StdRng) -> Vec<Aabb> {
+        let count = rng.random_range(1..=WIDTH);
+        (0..count)
+            .map(|_| {
+                let corner = random_vec3(rng, -10.0, 10.0);
+                Aabb::new_from_vec3(corner, corner + random_vec3(rng, 0.0, 5.0))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn random_boxes_and_rays() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20_000 {
+            let boxes = random_boxes(&mut rng);
+            let origin = random_vec3(&mut rng, -15.0, 15.0);
+            let direction = random_vec3(&mut rng, -1.0, 1.0);
+            check(&boxes, origin, direction);
+        }
+    }
+
+    #[test]
+    fn axis_parallel_rays() {
+        // Zero direction components make the inverse infinite, of either sign.
+        let mut rng = StdRng::seed_from_u64(2);
+        for i in 0..20_000 {
+            let boxes = random_boxes(&mut rng);
+            let origin = random_vec3(&mut rng, -15.0, 15.0);
+            let mut direction = random_vec3(&mut rng, -1.0, 1.0);
+            let zero = if i % 2 == 0 { 0.0 } else { -0.0 };
+            // One or two of the components, never all three.
+            let zero_axes = (i / 2) % 6 + 1;
+            for axis in 0..3 {
+                if zero_axes & 1 << axis != 0 {
+                    direction = with_axis(direction, axis, zero);
+                }
+            }
+            check(&boxes, origin, direction);
+        }
+    }
+
+    #[test]
+    fn rays_in_slab_planes() {
+        // A ray lying in the plane of a box face gives 0 * inf = NaN for that slab, which every
+        // test must treat alike.
+        let mut rng = StdRng::seed_from_u64(3);
+        for i in 0..20_000 {
+            let boxes = random_boxes(&mut rng);
+            let target = boxes[rng.random_range(0..boxes.len())];
+            let axis = i % 3;
+            let interval = target.axis_interval(axis as i32);
+            let plane = if rng.random::<bool>() {
+                interval.min
+            } else {
+                interval.max
+            };
+            let origin = with_axis(random_vec3(&mut rng, -15.0, 15.0), axis, plane);
+            let zero = if i % 2 == 0 { 0.0 } else { -0.0 };
+            let direction = with_axis(random_vec3(&mut rng, -1.0, 1.0), axis, zero);
+            check(&boxes, origin, direction);
+        }
+    }
+
+    #[test]
+    fn touching_boxes() {
+        // Boxes sharing a face, hit by rays through points of that face, its edges and corners.
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..20_000 {
+            let low = random_vec3(&mut rng, -10.0, 0.0);
+            let middle = low + random_vec3(&mut rng, 0.5, 5.0);
+            let high = middle + random_vec3(&mut rng, 0.5, 5.0);
+            let axis = rng.random_range(0..3);
+            let boxes = [
+                Aabb::new_from_vec3(low, with_axis(high, axis, middle[axis])),
+                Aabb::new_from_vec3(with_axis(low, axis, middle[axis]), high),
+            ];
+
+            // Some coordinates are snapped to the edges of the shared face.
+            let mut target = random_vec3(&mut rng, 0.0, 1.0);
+            for a in 0..3 {
+                match rng.random_range(0..4) {
+                    0 => target = with_axis(target, a, 0.0),
+                    1 => target = with_axis(target, a, 1.0),
+                    _ => {}
+                }
+            }
+            let target = with_axis(low + target * (high - low), axis, middle[axis]);
+            let origin = random_vec3(&mut rng, -15.0, 15.0);
+            check(&boxes, origin, target - origin);
+        }
+    }
+
+    #[test]
+    fn empty_lanes_are_never_hit() {
+        let packed = Aabb4::EMPTY;
+        for direction in [
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(-0.0, -1.0, 0.0),
+        ] {
+            let inv_direction = Vec3::new(
+                1.0 / direction.x(),
+                1.0 / direction.y(),
+                1.0 / direction.z(),
+            );
+            let ray_t = Interval::new(0.001, Float::INFINITY);
+            let origin = Vec3::new(0.0, 0.0, 0.0);
+            assert_eq!(packed.hit(origin, inv_direction, ray_t).0, 0);
+            assert_eq!(packed.hit_scalar(origin, inv_direction, ray_t).0, 0);
+        }
+    }
+}